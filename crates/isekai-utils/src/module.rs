@@ -1,7 +1,7 @@
 // SPDX-FileCopyrightText: 2025 SEERA Networks Corporation <info@seera-networks.com>
 // SPDX-License-Identifier: MIT
 
-use crate::{NumOrd, img::Detection};
+use crate::{img::Detection, NumOrd};
 
 use super::{jsonize, jsonize_bytes};
 use serde::{Deserialize, Serialize};
@@ -36,16 +36,96 @@ jsonize!(FunctionPyodideOutput);
 #[derive(Serialize, Deserialize, Debug)]
 pub struct GetTicket {
     pub target: String,
+    #[serde(default)]
     pub column_name: String,
+    /// Columns returned together as one row-aligned table. When empty, only
+    /// `column_name` is requested.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub columns: Vec<String>,
 }
 jsonize!(GetTicket);
 
+impl GetTicket {
+    pub fn columns(self: &Self) -> Vec<&str> {
+        if self.columns.is_empty() {
+            vec![self.column_name.as_str()]
+        } else {
+            self.columns.iter().map(|c| c.as_str()).collect()
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct SaveResponse {
     pub table_name: String,
 }
 jsonize_bytes!(SaveResponse);
 
+/// A target stored with `do_put`, as returned by the `list_targets` action.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct StoredTarget {
+    pub target: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+    /// Seconds since the Unix epoch.
+    pub created_at: u64,
+    /// When the target is purged, in seconds since the Unix epoch, if it has
+    /// a TTL.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<u64>,
+    /// Who else may read the target.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub grants: Vec<Grantee>,
+    pub num_rows: i64,
+    pub columns: Vec<String>,
+}
+jsonize_bytes!(StoredTarget);
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct DeleteTargetArgs {
+    pub target: String,
+}
+jsonize_bytes!(DeleteTargetArgs);
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct LabelTargetArgs {
+    pub target: String,
+    /// The new label, or `None` to remove it.
+    #[serde(default)]
+    pub label: Option<String>,
+}
+jsonize_bytes!(LabelTargetArgs);
+
+/// A subject or a group of subjects that a stored target is shared with.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Grantee {
+    Subject(String),
+    /// A group of the `groups` claim of the JWT.
+    Group(String),
+}
+
+/// The arguments of the `grant_target` and `revoke_target` actions.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct GrantTargetArgs {
+    pub target: String,
+    pub grantee: Grantee,
+}
+jsonize_bytes!(GrantTargetArgs);
+
+/// A command `FlightDescriptor` of `do_put`.
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct PutDescriptor {
+    /// An existing target to append to instead of creating a new one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub target: Option<String>,
+    /// Seconds until a new target is purged. The server caps it at its
+    /// maximum TTL.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ttl_secs: Option<u64>,
+}
+jsonize_bytes!(PutDescriptor);
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct MatrixShape {
     pub shape: Vec<usize>,
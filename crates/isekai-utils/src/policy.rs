@@ -2,6 +2,7 @@
 // SPDX-License-Identifier: MIT
 
 use serde::{Deserialize, Serialize};
+use std::collections::hash_map::Entry;
 use std::collections::HashMap;
 
 macro_rules! jsonize {
@@ -61,6 +62,54 @@ impl PolicyFile {
         return res;
     }
 
+    /// Merges `other` into this policy so that the result covers the columns
+    /// of both. A rule, function policy or table verifier whose name is
+    /// already taken by a different one is kept under a suffixed name, and the
+    /// rules of `other` are rewritten to refer to the renamed entries. Rules
+    /// of `other` that use its default table verifier name it explicitly when
+    /// this policy already has a different default; otherwise the default of
+    /// `other` becomes the default of the merged policy.
+    pub fn merge(self: &mut Self, other: PolicyFile) {
+        let func_names = merge_entries(&mut self.func_policy, other.func_policy);
+        let verifier_names = merge_entries(&mut self.table_verifiers, other.table_verifiers);
+        let rename = |names: &HashMap<String, String>, name: String| -> String {
+            names.get(&name).cloned().unwrap_or(name)
+        };
+
+        let other_default = rename(&verifier_names, other.default_table_verifier);
+        let explicit_default = if self.default_table_verifier.is_empty() {
+            self.default_table_verifier = other_default;
+            None
+        } else if other_default.is_empty() || other_default == self.default_table_verifier {
+            None
+        } else {
+            Some(other_default)
+        };
+
+        let rules = other
+            .rules
+            .into_iter()
+            .map(|(k, mut v)| {
+                v.requires = v
+                    .requires
+                    .into_iter()
+                    .map(|name| rename(&func_names, name))
+                    .collect();
+                v.rejects = v
+                    .rejects
+                    .into_iter()
+                    .map(|name| rename(&func_names, name))
+                    .collect();
+                v.table_verifier = match v.table_verifier {
+                    Some(name) => Some(rename(&verifier_names, name)),
+                    None => explicit_default.clone(),
+                };
+                (k, v)
+            })
+            .collect();
+        merge_entries(&mut self.rules, rules);
+    }
+
     pub fn update_function_policy(
         self: &Self,
         hash_map: &mut HashMap<String, FunctionPolicy>,
@@ -74,6 +123,48 @@ impl PolicyFile {
     }
 }
 
+/// Returns `name` with the first `#<n>` suffix not taken in `map`.
+fn unused_name<V>(map: &HashMap<String, V>, name: &str) -> String {
+    let mut idx = 1;
+    while map.contains_key(&format!("{}#{}", name, idx)) {
+        idx += 1;
+    }
+    format!("{}#{}", name, idx)
+}
+
+/// Inserts the entries of `other` into `map` and returns the names they are
+/// kept under when that is not their own. An entry equal to one kept under its
+/// name or a suffixed variant of it is not added again, and an entry whose
+/// name is taken by a different one gets the first free suffix.
+fn merge_entries<V: PartialEq>(
+    map: &mut HashMap<String, V>,
+    other: HashMap<String, V>,
+) -> HashMap<String, String> {
+    let mut renames = HashMap::new();
+    for (k, v) in other {
+        if let Entry::Vacant(entry) = map.entry(k.clone()) {
+            entry.insert(v);
+            continue;
+        }
+        let equal = std::iter::once(k.clone())
+            .chain((1..).map(|idx| format!("{}#{}", k, idx)))
+            .take_while(|name| map.contains_key(name))
+            .find(|name| map[name] == v);
+        let name = match equal {
+            Some(name) => name,
+            None => {
+                let name = unused_name(map, &k);
+                map.insert(name.clone(), v);
+                name
+            }
+        };
+        if name != k {
+            renames.insert(k, name);
+        }
+    }
+    renames
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone, Hash, PartialEq, Eq)]
 pub struct PolicyRule {
     pub column_name: String,
@@ -83,7 +174,7 @@ pub struct PolicyRule {
 }
 jsonize!(PolicyRule);
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
 pub struct FunctionPolicy {
     pub func: String,
     #[serde(default)]
@@ -100,3 +191,87 @@ pub struct TableVerifier {
     pub arg: String,
 }
 jsonize!(TableVerifier);
+
+#[cfg(test)]
+mod tests {
+    use super::{FunctionPolicy, PolicyFile, PolicyRule, TableVerifier};
+
+    fn rule(column_name: &str) -> PolicyRule {
+        PolicyRule {
+            column_name: column_name.to_string(),
+            requires: vec!["histogram".to_string()],
+            rejects: vec![],
+            table_verifier: None,
+        }
+    }
+
+    fn mean_minimum(minimum_count: u32) -> FunctionPolicy {
+        FunctionPolicy {
+            func: "mean".to_string(),
+            reject: String::new(),
+            require: format!("minimum_count: {}", minimum_count),
+        }
+    }
+
+    fn column_policy(column_name: &str, minimum_count: u32, verifier: &str) -> PolicyFile {
+        let mut policy = PolicyFile::new();
+        let mut column_rule = rule(column_name);
+        column_rule.requires = vec!["mean_minimum".to_string()];
+        policy.rules.insert("rule".to_string(), column_rule);
+        policy
+            .func_policy
+            .insert("mean_minimum".to_string(), mean_minimum(minimum_count));
+        policy.default_table_verifier = "k_anonymity".to_string();
+        policy.table_verifiers.insert(
+            "k_anonymity".to_string(),
+            TableVerifier {
+                verifier: verifier.to_string(),
+                arg: String::new(),
+            },
+        );
+        policy
+    }
+
+    #[test]
+    fn merge_keeps_rules_of_every_column() {
+        let mut policy = PolicyFile::new();
+        policy.rules.insert("rule".to_string(), rule("a"));
+        let mut other = PolicyFile::new();
+        other.rules.insert("rule".to_string(), rule("b"));
+        other.default_table_verifier = "verifier".to_string();
+
+        policy.merge(other.clone());
+        policy.merge(other);
+
+        assert_eq!(policy.rules.len(), 2);
+        assert_eq!(policy.get_rules_from_column_name("a").len(), 1);
+        assert_eq!(policy.get_rules_from_column_name("b").len(), 1);
+        assert_eq!(policy.default_table_verifier, "verifier");
+
+        // Function policies and verifiers of the same name but different
+        // content are renamed, and the rules of their column follow them.
+        let mut policy = column_policy("a", 100, "k5");
+        policy.merge(column_policy("b", 1000, "k10"));
+
+        assert_eq!(policy.func_policy.len(), 2);
+        assert_eq!(policy.func_policy["mean_minimum"], mean_minimum(100));
+        assert_eq!(policy.func_policy["mean_minimum#1"], mean_minimum(1000));
+        assert_eq!(policy.table_verifiers["k_anonymity#1"].verifier, "k10");
+        assert_eq!(policy.default_table_verifier, "k_anonymity");
+
+        let (_, a) = policy.get_rules_from_column_name("a").remove(0);
+        assert_eq!(a.requires, vec!["mean_minimum".to_string()]);
+        assert_eq!(a.table_verifier, None);
+        let (_, b) = policy.get_rules_from_column_name("b").remove(0);
+        assert_eq!(b.requires, vec!["mean_minimum#1".to_string()]);
+        assert_eq!(b.table_verifier, Some("k_anonymity#1".to_string()));
+
+        // Identical entries are shared rather than renamed.
+        policy.merge(column_policy("c", 100, "k5"));
+        assert_eq!(policy.func_policy.len(), 2);
+        assert_eq!(policy.table_verifiers.len(), 2);
+        let (_, c) = policy.get_rules_from_column_name("c").remove(0);
+        assert_eq!(c.requires, vec!["mean_minimum".to_string()]);
+        assert_eq!(c.table_verifier, None);
+    }
+}
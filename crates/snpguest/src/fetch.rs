@@ -8,6 +8,9 @@ use serde::{Deserialize, Serialize};
 use sev::firmware::host::CertType;
 use std::{fs, path::PathBuf, str::FromStr};
 
+/// Base URL of the AMD Key Distribution Service.
+pub const KDS_CERT_SITE: &str = "https://kdsintf.amd.com";
+
 #[derive(Subcommand)]
 pub enum FetchCmd {
     /// Fetch the certificate authority (ARK & ASK) from the KDS.
@@ -15,6 +18,9 @@ pub enum FetchCmd {
 
     /// Fetch the VCEK from the KDS.
     Vcek(vcek::Args),
+
+    /// Fetch the certificate revocation list (CRL) from the KDS.
+    Crl(crl::Args),
 }
 
 #[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Hash)]
@@ -99,6 +105,7 @@ pub async fn cmd(cmd: FetchCmd) -> Result<()> {
     match cmd {
         FetchCmd::CA(args) => cert_authority::fetch_ca_async(args).await,
         FetchCmd::Vcek(args) => vcek::fetch_vcek_async(args).await,
+        FetchCmd::Crl(args) => crl::fetch_crl_async(args).await,
     }
 }
 
@@ -124,18 +131,23 @@ pub mod cert_authority {
         /// Specify which endorsement certificate chain to pull, either VCEK or VLEK.
         #[arg(short, long, value_name = "endorser", default_value_t = Endorsement::Vcek, ignore_case = true)]
         pub endorser: Endorsement,
+
+        /// Base URL of the KDS, or of a mirror serving the same paths.
+        #[arg(long, value_name = "url", default_value = KDS_CERT_SITE)]
+        pub kds_url: String,
     }
 
     pub async fn request_ca_kds_async(
+        kds_url: &str,
         processor_model: ProcType,
         endorser: &Endorsement,
     ) -> Result<Vec<X509>, anyhow::Error> {
-        const KDS_CERT_SITE: &str = "https://kdsintf.amd.com";
         const KDS_CERT_CHAIN: &str = "cert_chain";
 
         // Should make -> https://kdsintf.amd.com/vcek/v1/{SEV_PROD_NAME}/cert_chain
         let url: String = format!(
-            "{KDS_CERT_SITE}/{}/v1/{}/{KDS_CERT_CHAIN}",
+            "{}/{}/v1/{}/{KDS_CERT_CHAIN}",
+            kds_url.trim_end_matches('/'),
             endorser.to_string().to_lowercase(),
             processor_model.to_kds_url()
         );
@@ -163,7 +175,8 @@ pub mod cert_authority {
     // Fetch the ca from the kds and write it into the certs directory
     pub async fn fetch_ca_async(args: Args) -> Result<()> {
         // Get certs from kds
-        let certificates = request_ca_kds_async(args.processor_model, &args.endorser).await?;
+        let certificates =
+            request_ca_kds_async(&args.kds_url, args.processor_model, &args.endorser).await?;
 
         // Create certs directory if missing
         if !args.certs_dir.exists() {
@@ -214,15 +227,19 @@ pub mod vcek {
         /// Path to attestation report to use to request VCEK.
         #[arg(value_name = "att-report-path", required = true)]
         pub att_report_path: PathBuf,
+
+        /// Base URL of the KDS, or of a mirror serving the same paths.
+        #[arg(long, value_name = "url", default_value = KDS_CERT_SITE)]
+        pub kds_url: String,
     }
 
     // Function to request vcek from KDS. Return vcek in der format.
     pub async fn request_vcek_kds_async(
+        kds_url: &str,
         processor_model: ProcType,
         att_report_path: PathBuf,
     ) -> Result<Vec<u8>, anyhow::Error> {
         // KDS URL parameters
-        const KDS_CERT_SITE: &str = "https://kdsintf.amd.com";
         const KDS_VCEK: &str = "/vcek/v1";
 
         // Grab attestation report if path provided, request report if no path is provided
@@ -236,8 +253,9 @@ pub mod vcek {
         let hw_id: String = hex::encode(att_report.chip_id);
 
         let vcek_url: String = format!(
-            "{KDS_CERT_SITE}{KDS_VCEK}/{}/\
+            "{}{KDS_VCEK}/{}/\
             {hw_id}?blSPL={:02}&teeSPL={:02}&snpSPL={:02}&ucodeSPL={:02}",
+            kds_url.trim_end_matches('/'),
             processor_model.to_kds_url(),
             att_report.reported_tcb.bootloader,
             att_report.reported_tcb.tee,
@@ -266,7 +284,9 @@ pub mod vcek {
     // Function to request vcek from kds and write it into file
     pub async fn fetch_vcek_async(args: Args) -> Result<()> {
         // Request vcek
-        let vcek = request_vcek_kds_async(args.processor_model, args.att_report_path).await?;
+        let vcek =
+            request_vcek_kds_async(&args.kds_url, args.processor_model, args.att_report_path)
+                .await?;
 
         if !args.certs_dir.exists() {
             fs::create_dir(&args.certs_dir).context("Could not create certs folder")?;
@@ -283,6 +303,84 @@ pub mod vcek {
         Ok(())
     }
 }
+
+pub mod crl {
+    use super::*;
+    use openssl::x509::X509Crl;
+    use reqwest::StatusCode;
+
+    #[derive(Parser)]
+    pub struct Args {
+        /// Specify the processor model for the CRL.
+        #[arg(value_name = "processor-model", required = true, ignore_case = true)]
+        pub processor_model: ProcType,
+
+        /// Directory to store the CRL in.
+        #[arg(value_name = "certs-dir", required = true)]
+        pub certs_dir: PathBuf,
+
+        /// Specify which endorsement CRL to pull, either VCEK or VLEK.
+        #[arg(short, long, value_name = "endorser", default_value_t = Endorsement::Vcek, ignore_case = true)]
+        pub endorser: Endorsement,
+
+        /// Base URL of the KDS, or of a mirror serving the same paths.
+        #[arg(long, value_name = "url", default_value = KDS_CERT_SITE)]
+        pub kds_url: String,
+    }
+
+    // Function to request the CRL of a processor family from the KDS. Return the CRL in der format.
+    pub async fn request_crl_kds_async(
+        kds_url: &str,
+        processor_model: ProcType,
+        endorser: &Endorsement,
+    ) -> Result<Vec<u8>, anyhow::Error> {
+        // Should make -> https://kdsintf.amd.com/vcek/v1/{SEV_PROD_NAME}/crl
+        let url: String = format!(
+            "{}/{}/v1/{}/crl",
+            kds_url.trim_end_matches('/'),
+            endorser.to_string().to_lowercase(),
+            processor_model.to_kds_url()
+        );
+
+        let rsp = reqwest::get(url)
+            .await
+            .context("Unable to send request for CRL to URL")?;
+        match rsp.status() {
+            StatusCode::OK => {
+                let body = rsp
+                    .bytes()
+                    .await
+                    .context("Unable to parse AMD CRL")?
+                    .to_vec();
+                // Make sure the body is a CRL before handing it out
+                X509Crl::from_der(&body).context("Invalid CRL from the KDS")?;
+                Ok(body)
+            }
+            status => Err(anyhow::anyhow!("Unable to fetch CRL: {:?}", status)),
+        }
+    }
+
+    // Fetch the CRL from the kds and write it into the certs directory
+    pub async fn fetch_crl_async(args: Args) -> Result<()> {
+        let crl =
+            request_crl_kds_async(&args.kds_url, args.processor_model, &args.endorser).await?;
+
+        if !args.certs_dir.exists() {
+            fs::create_dir(&args.certs_dir).context("Could not create certs folder")?;
+        }
+
+        let (crl_path, _) = certs::get_cert_path(
+            &args.certs_dir,
+            &CertType::CRL,
+            CertFormat::Der,
+            &args.endorser,
+        );
+        fs::write(crl_path, crl).context("Unable to write the CRL")?;
+
+        Ok(())
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::ProcType;
@@ -68,11 +68,7 @@ pub fn get_derived_key(args: KeyArgs) -> Result<()> {
 
     let tcbv: u64 = args.tcbv.unwrap_or(0);
 
-    let request = DerivedKey::new(root_key_select, GuestFieldSelect(gfs), vmpl, gsvn, tcbv);
-    let mut sev_fw = Firmware::open().context("failed to open SEV firmware device.")?;
-    let derived_key: [u8; 32] = sev_fw
-        .get_derived_key(None, request)
-        .context("Failed to request derived key")?;
+    let derived_key = request_derived_key(root_key_select, gfs, vmpl, gsvn, tcbv)?;
 
     // Create derived key path
     let key_path: PathBuf = args.key_path;
@@ -94,6 +90,22 @@ pub fn get_derived_key(args: KeyArgs) -> Result<()> {
     Ok(())
 }
 
+/// Requests a key derived from the VCEK, or from the VMRK if `root_key_select` is true, mixing in the guest fields selected by `gfs`.
+pub fn request_derived_key(
+    root_key_select: bool,
+    gfs: u64,
+    vmpl: u32,
+    gsvn: u32,
+    tcbv: u64,
+) -> Result<[u8; 32]> {
+    let request = DerivedKey::new(root_key_select, GuestFieldSelect(gfs), vmpl, gsvn, tcbv);
+    let mut sev_fw = Firmware::open().context("failed to open SEV firmware device.")?;
+    let derived_key: [u8; 32] = sev_fw
+        .get_derived_key(None, request)
+        .context("Failed to request derived key")?;
+    Ok(derived_key)
+}
+
 pub fn read_key(key_path: PathBuf) -> Result<Vec<u8>, anyhow::Error> {
     let mut key_file = fs::File::open(key_path)?;
     let mut key = Vec::new();
@@ -3,8 +3,9 @@
 
 mod certs;
 pub mod fetch;
+pub mod key;
 pub mod report;
-mod verify;
+pub mod verify;
 pub mod verify2;
 
 use anyhow::{Context, Result};
@@ -11,6 +11,7 @@ use std::{
 };
 
 use openssl::{ecdsa::EcdsaSig, sha::Sha384};
+use serde::Serialize;
 use sev::certs::snp::Chain;
 
 #[derive(Subcommand)]
@@ -25,8 +26,170 @@ pub enum VerifyCmd {
 #[allow(dead_code)]
 pub fn cmd(cmd: VerifyCmd, quiet: bool) -> Result<()> {
     match cmd {
-        VerifyCmd::Certs(args) => certificate_chain::validate_cc(args, quiet),
-        VerifyCmd::Attestation(args) => attestation::verify_attestation(args, quiet),
+        VerifyCmd::Certs(args) => certificate_chain::validate_cc(args, quiet)?,
+        VerifyCmd::Attestation(args) => attestation::verify_attestation(args, quiet)?,
+    };
+    Ok(())
+}
+
+/// How the results of a verification are printed.
+#[derive(ValueEnum, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// A line for each passed check and an error for the first failed one.
+    #[default]
+    Text,
+
+    /// The `VerificationReport` as JSON, whether or not it passed.
+    Json,
+}
+
+/// What a `Check` verified.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CheckKind {
+    /// A signature of the certificate chain.
+    Chain,
+    /// The signature of the CRL.
+    Crl,
+    /// The absence of a certificate from the CRL.
+    Revocation,
+    /// A TCB component of the VEK against the reported TCB.
+    Tcb,
+    /// The hardware ID of the VCEK against the chip ID of the report.
+    ChipId,
+    /// The signature of the attestation report.
+    Signature,
+}
+
+/// The outcome of one check of a verification.
+#[derive(Debug, Clone, Serialize)]
+pub struct Check {
+    pub kind: CheckKind,
+    /// The certificate or TCB component checked, e.g. `ASK` or `snp`.
+    pub name: String,
+    pub passed: bool,
+    /// The value required by the certificate, if values were compared.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expected: Option<String>,
+    /// The value found in the report, or the serial number of a certificate
+    /// checked against the CRL.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub actual: Option<String>,
+    /// The outcome as printed by the text output.
+    pub message: String,
+}
+
+impl Check {
+    fn new(kind: CheckKind, name: &str, passed: bool, message: String) -> Self {
+        Check {
+            kind,
+            name: name.to_string(),
+            passed,
+            expected: None,
+            actual: None,
+            message,
+        }
+    }
+
+    fn compared(mut self, expected: Option<String>, actual: String) -> Self {
+        self.expected = expected;
+        self.actual = Some(actual);
+        self
+    }
+}
+
+/// The checks of a verification in the order they ran.
+#[derive(Debug, Clone, Serialize)]
+pub struct VerificationReport {
+    /// True if every check passed.
+    pub passed: bool,
+    pub checks: Vec<Check>,
+}
+
+impl Default for VerificationReport {
+    fn default() -> Self {
+        VerificationReport {
+            passed: true,
+            checks: Vec::new(),
+        }
+    }
+}
+
+impl VerificationReport {
+    fn push(&mut self, check: Check) {
+        self.passed &= check.passed;
+        self.checks.push(check);
+    }
+
+    /// Appends the checks of `other`.
+    pub fn append(&mut self, other: VerificationReport) {
+        for check in other.checks {
+            self.push(check);
+        }
+    }
+
+    pub fn to_json(&self) -> Result<String> {
+        serde_json::to_string_pretty(self).context("Failed to serialize the verification report.")
+    }
+
+    /// Returns the report, or a `VerificationFailed` error if a check failed.
+    pub fn into_result(self) -> Result<Self> {
+        let Some(check) = self.checks.iter().find(|check| !check.passed) else {
+            return Ok(self);
+        };
+        let cause: Box<dyn std::error::Error + Send + Sync> = match check.kind {
+            CheckKind::Revocation => Box::new(certificate_chain::CertificateRevoked {
+                cert_type: check.name.clone(),
+                serial: check.actual.clone().unwrap_or_default(),
+            }),
+            _ => check.message.clone().into(),
+        };
+        Err(VerificationFailed {
+            report: self,
+            cause,
+        }
+        .into())
+    }
+
+    /// Prints `self` in `format`, then returns it as `into_result` does.
+    /// Text is not printed when `quiet`.
+    pub fn output(self, format: OutputFormat, quiet: bool) -> Result<Self> {
+        match format {
+            OutputFormat::Text if !quiet => {
+                for check in self.checks.iter().filter(|check| check.passed) {
+                    println!("{}", check.message);
+                }
+            }
+            OutputFormat::Text => {}
+            OutputFormat::Json => println!("{}", self.to_json()?),
+        }
+        self.into_result()
+    }
+}
+
+/// A verification with a failed check. Its source is the first failed check,
+/// as a `CertificateRevoked` if a certificate is on the CRL.
+#[derive(Debug)]
+pub struct VerificationFailed {
+    pub report: VerificationReport,
+    cause: Box<dyn std::error::Error + Send + Sync>,
+}
+
+impl std::fmt::Display for VerificationFailed {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let failed = self.report.checks.iter().filter(|c| !c.passed).count();
+        write!(
+            f,
+            "{} of {} verification checks failed",
+            failed,
+            self.report.checks.len()
+        )
+    }
+}
+
+impl std::error::Error for VerificationFailed {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(self.cause.as_ref())
     }
 }
 
@@ -42,7 +205,8 @@ pub fn find_cert_in_dir(dir: &Path, cert: &str) -> Result<PathBuf, anyhow::Error
 }
 
 pub mod certificate_chain {
-    use sev::certs::snp::Verifiable;
+    use openssl::x509::{CrlStatus, X509Crl, X509CrlRef, X509};
+    use sev::certs::snp::{Certificate, Verifiable};
 
     use super::*;
 
@@ -51,10 +215,134 @@ pub mod certificate_chain {
         /// Path to directory containing certificate chain."
         #[arg(value_name = "certs-dir", required = true)]
         pub certs_dir: PathBuf,
+
+        /// Print the checks as text or as a JSON verification report.
+        #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+        pub format: OutputFormat,
     }
 
-    // Function to validate certificate chain
-    pub fn validate_cc(args: Args, quiet: bool) -> Result<()> {
+    /// A certificate of the chain is on the CRL of the AMD ARK.
+    #[derive(Debug)]
+    pub struct CertificateRevoked {
+        /// ASK, ASVK, VCEK or VLEK.
+        pub cert_type: String,
+        /// The serial number in hex.
+        pub serial: String,
+    }
+
+    impl std::fmt::Display for CertificateRevoked {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            write!(
+                f,
+                "The AMD {} with serial number {} has been revoked!",
+                self.cert_type, self.serial
+            )
+        }
+    }
+
+    impl std::error::Error for CertificateRevoked {}
+
+    /// Reads a CRL in DER or PEM format.
+    pub fn read_crl(path: &Path) -> Result<X509Crl> {
+        let bytes = std::fs::read(path).context("Could not read the CRL")?;
+        let crl = if bytes.starts_with(b"-----BEGIN") {
+            X509Crl::from_pem(&bytes)
+        } else {
+            X509Crl::from_der(&bytes)
+        }
+        .context("Could not parse the CRL")?;
+        Ok(crl)
+    }
+
+    /// Checks that `crl` is signed by `ark` and that none of `certs` is on it.
+    pub fn check_revocation(
+        crl: &X509CrlRef,
+        ark: &Certificate,
+        certs: &[(&str, &Certificate)],
+        verification: &mut VerificationReport,
+    ) -> Result<()> {
+        let ark = X509::from_der(&ark.to_der()?)?;
+        let signed = crl
+            .verify(&ark.public_key()?)
+            .context("Failed to verify the CRL signature")?;
+        verification.push(if signed {
+            Check::new(
+                CheckKind::Crl,
+                "CRL",
+                true,
+                "The AMD CRL was signed by the AMD ARK!".to_string(),
+            )
+        } else {
+            Check::new(
+                CheckKind::Crl,
+                "CRL",
+                false,
+                "The CRL was not signed by the AMD ARK!".to_string(),
+            )
+        });
+        if !signed {
+            return Ok(());
+        }
+        for (cert_type, cert) in certs {
+            let cert_type = cert_type.to_uppercase();
+            let cert = X509::from_der(&cert.to_der()?)?;
+            let serial = cert.serial_number().to_bn()?.to_hex_str()?.to_string();
+            let check = match crl.get_by_cert(&cert) {
+                CrlStatus::Revoked(_) => Check::new(
+                    CheckKind::Revocation,
+                    &cert_type,
+                    false,
+                    format!(
+                        "The AMD {} with serial number {} has been revoked!",
+                        cert_type, serial
+                    ),
+                ),
+                _ => Check::new(
+                    CheckKind::Revocation,
+                    &cert_type,
+                    true,
+                    format!("The AMD {} is not on the AMD CRL!", cert_type),
+                ),
+            };
+            verification.push(check.compared(None, serial));
+        }
+        Ok(())
+    }
+
+    /// Checks the result of verifying the signature of the `name`
+    /// certificate, described as `signed`, by the AMD `signer`.
+    fn signature_check(
+        result: std::io::Result<()>,
+        name: &str,
+        signed: &str,
+        signer: &str,
+    ) -> Check {
+        let self_signed = name == signer;
+        let (passed, message) = match result {
+            Ok(()) if self_signed => (true, format!("The {signed} was self-signed!")),
+            Ok(()) => (
+                true,
+                format!("The {signed} was signed by the AMD {signer}!"),
+            ),
+            Err(e) if e.kind() == ErrorKind::Other && self_signed => {
+                (false, format!("The {signed} is not self-signed!"))
+            }
+            Err(e) if e.kind() == ErrorKind::Other => (
+                false,
+                format!("The {signed} was not signed by the AMD {signer}!"),
+            ),
+            Err(e) => (
+                false,
+                format!("Failed to verify the {name} certificate: {e:?}"),
+            ),
+        };
+        Check::new(CheckKind::Chain, name, passed, message)
+    }
+
+    /// Verifies the certificate chain in `args.certs_dir`, and checks the
+    /// ASK and VEK against the CRL there if any. A check that fails is
+    /// recorded in the report; only unreadable certificates are errors.
+    pub fn chain_report(args: &Args) -> Result<VerificationReport> {
         let ark_path = find_cert_in_dir(&args.certs_dir, "ark")?;
         let (mut vek_type, mut sign_type): (&str, &str) = ("vcek", "ask");
         let (vek_path, ask_path) = match find_cert_in_dir(&args.certs_dir, "vlek") {
@@ -80,66 +368,93 @@ pub mod certificate_chain {
         let ask = cert_chain.ca.ask;
         let vek = cert_chain.vek;
 
-        // Verify each signature and print result in console
-        match (&ark, &ark).verify() {
-            Ok(()) => {
-                if !quiet {
-                    println!("The AMD ARK was self-signed!");
-                }
-            }
-            Err(e) => match e.kind() {
-                ErrorKind::Other => return Err(anyhow::anyhow!("The AMD ARK is not self-signed!")),
-                _ => {
-                    return Err(anyhow::anyhow!(
-                        "Failed to verify the ARK cerfificate: {:?}",
-                        e
-                    ))
-                }
-            },
+        // Verify each signature
+        let (ark_name, ask_name, vek_name) = (
+            "ARK".to_string(),
+            sign_type.to_uppercase(),
+            vek_type.to_uppercase(),
+        );
+        let mut verification = VerificationReport::default();
+        verification.push(signature_check(
+            (&ark, &ark).verify(),
+            &ark_name,
+            &format!("AMD {ark_name}"),
+            &ark_name,
+        ));
+        verification.push(signature_check(
+            (&ark, &ask).verify(),
+            &ask_name,
+            &format!("AMD {ask_name}"),
+            &ark_name,
+        ));
+        verification.push(signature_check(
+            (&ask, &vek).verify(),
+            &vek_name,
+            &vek_name,
+            &ask_name,
+        ));
+
+        // Check revocation if the directory has a CRL
+        if let Ok(crl_path) = find_cert_in_dir(&args.certs_dir, "crl") {
+            let crl = read_crl(&crl_path)?;
+            check_revocation(
+                &crl,
+                &ark,
+                &[(sign_type, &ask), (vek_type, &vek)],
+                &mut verification,
+            )?;
         }
+        Ok(verification)
+    }
 
-        match (&ark, &ask).verify() {
-            Ok(()) => {
-                if !quiet {
-                    println!(
-                        "The AMD {} was signed by the AMD ARK!",
-                        sign_type.to_uppercase()
-                    );
-                }
-            }
-            Err(e) => match e.kind() {
-                ErrorKind::Other => {
-                    return Err(anyhow::anyhow!(
-                        "The AMD {} was not signed by the AMD ARK!",
-                        sign_type.to_uppercase()
-                    ))
-                }
-                _ => return Err(anyhow::anyhow!("Failed to verify ASK certificate: {:?}", e)),
-            },
+    // Function to validate certificate chain
+    pub fn validate_cc(args: Args, quiet: bool) -> Result<VerificationReport> {
+        chain_report(&args)?.output(args.format, quiet)
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        /// A test ARK and an ASK with serial number 1f signed by it.
+        fn ark_and_ask() -> (Certificate, Certificate) {
+            (
+                Certificate::from_pem(include_bytes!("../test/crl/ark.pem")).unwrap(),
+                Certificate::from_pem(include_bytes!("../test/crl/ask.pem")).unwrap(),
+            )
         }
 
-        match (&ask, &vek).verify() {
-            Ok(()) => {
-                if !quiet {
-                    println!(
-                        "The {} was signed by the AMD {}!",
-                        vek_type.to_uppercase(),
-                        sign_type.to_uppercase()
-                    );
-                }
-            }
-            Err(e) => match e.kind() {
-                ErrorKind::Other => {
-                    return Err(anyhow::anyhow!(
-                        "The {} was not signed by the AMD {}!",
-                        vek_type.to_uppercase(),
-                        sign_type.to_uppercase(),
-                    ))
-                }
-                _ => return Err(anyhow::anyhow!("Failed to verify VEK certificate: {:?}", e)),
-            },
+        #[test]
+        fn revoked_certificates_fail_verification() {
+            let (ark, ask) = ark_and_ask();
+            // Signed by the ARK and revoking the ASK.
+            let crl = X509Crl::from_pem(include_bytes!("../test/crl/crl.pem")).unwrap();
+            let mut verification = VerificationReport::default();
+            check_revocation(&crl, &ark, &[("ask", &ask)], &mut verification).unwrap();
+            assert_eq!(verification.checks.len(), 2);
+            assert!(verification.checks[0].passed);
+
+            let err = verification.into_result().unwrap_err();
+            let revoked = err
+                .chain()
+                .find_map(|cause| cause.downcast_ref::<CertificateRevoked>())
+                .unwrap();
+            assert_eq!(revoked.cert_type, "ASK");
+            assert_eq!(revoked.serial, "1F");
+        }
+
+        #[test]
+        fn crls_not_signed_by_the_ark_fail_verification() {
+            let (ark, ask) = ark_and_ask();
+            // Issued in the name of the ARK but signed by another key.
+            let crl = X509Crl::from_pem(include_bytes!("../test/crl/forged_crl.pem")).unwrap();
+            let mut verification = VerificationReport::default();
+            check_revocation(&crl, &ark, &[("ask", &ask)], &mut verification).unwrap();
+            assert_eq!(verification.checks.len(), 1);
+            assert_eq!(verification.checks[0].kind, CheckKind::Crl);
+            assert!(!verification.checks[0].passed);
+            assert!(verification.into_result().is_err());
         }
-        Ok(())
     }
 }
 
@@ -203,12 +518,16 @@ pub mod attestation {
         /// Output report data to stdout
         #[arg(long, default_value_t = false)]
         pub output_report_data: bool,
+
+        /// Print the checks as text or as a JSON verification report.
+        #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+        pub format: OutputFormat,
     }
 
     fn verify_attestation_signature(
-        vcek: Certificate,
-        att_report: AttestationReport,
-        quiet: bool,
+        vcek: &Certificate,
+        att_report: &AttestationReport,
+        verification: &mut VerificationReport,
     ) -> Result<()> {
         let vek_pubkey = vcek
             .public_key()
@@ -219,7 +538,7 @@ pub mod attestation {
         // Get the attestation report signature
         let ar_signature = EcdsaSig::try_from(&att_report.signature)
             .context("Failed to get ECDSA Signature from attestation report.")?;
-        let signed_bytes = &bincode::serialize(&att_report)
+        let signed_bytes = &bincode::serialize(att_report)
             .context("Failed to get the signed bytes from the attestation report.")?[0x0..0x2A0];
 
         let mut hasher: Sha384 = Sha384::new();
@@ -229,16 +548,25 @@ pub mod attestation {
         let base_message_digest: [u8; 48] = hasher.finish();
 
         // Verify signature
-        if ar_signature
+        let check = if ar_signature
             .verify(base_message_digest.as_ref(), vek_pubkey.as_ref())
             .context("Failed to verify attestation report signature with VEK public key.")?
         {
-            if !quiet {
-                println!("VEK signed the Attestation Report!");
-            }
+            Check::new(
+                CheckKind::Signature,
+                "signature",
+                true,
+                "VEK signed the Attestation Report!".to_string(),
+            )
         } else {
-            return Err(anyhow::anyhow!("VEK did NOT sign the Attestation Report!"));
-        }
+            Check::new(
+                CheckKind::Signature,
+                "signature",
+                false,
+                "VEK did NOT sign the Attestation Report!".to_string(),
+            )
+        };
+        verification.push(check);
 
         Ok(())
     }
@@ -301,10 +629,23 @@ pub mod attestation {
         }
     }
 
+    // Value of a cert extension as compared by check_cert_bytes
+    fn cert_value(ext: &X509Extension) -> String {
+        match ext.value.first() {
+            Some(0x2) => ext
+                .value
+                .last()
+                .map(|byte| byte.to_string())
+                .unwrap_or_default(),
+            Some(0x4) => hex::encode(ext.value.get(2..).unwrap_or_default()),
+            _ => hex::encode(ext.value),
+        }
+    }
+
     fn verify_attestation_tcb(
-        vcek: Certificate,
-        att_report: AttestationReport,
-        quiet: bool,
+        vcek: &Certificate,
+        att_report: &AttestationReport,
+        verification: &mut VerificationReport,
     ) -> Result<()> {
         let vek_der = vcek.to_der().context("Could not convert VEK to der.")?;
         let (_, vek_x509) = X509Certificate::from_der(&vek_der)
@@ -317,104 +658,114 @@ pub mod attestation {
 
         let common_name: CertType = parse_common_name(vek_x509.subject())?;
 
-        // Compare bootloaders
-        if let Some(cert_bl) = extensions.get(&SnpOid::BootLoader.oid()) {
-            if !check_cert_bytes(cert_bl, &att_report.reported_tcb.bootloader.to_le_bytes()) {
-                return Err(anyhow::anyhow!(
-                    "Report TCB Boot Loader and Certificate Boot Loader mismatch encountered."
-                ));
-            }
-            if !quiet {
-                println!(
-                    "Reported TCB Boot Loader from certificate matches the attestation report."
-                );
-            }
-        }
-
-        // Compare TEE information
-        if let Some(cert_tee) = extensions.get(&SnpOid::Tee.oid()) {
-            if !check_cert_bytes(cert_tee, &att_report.reported_tcb.tee.to_le_bytes()) {
-                return Err(anyhow::anyhow!(
-                    "Report TCB TEE and Certificate TEE mismatch encountered."
-                ));
-            }
-            if !quiet {
-                println!("Reported TCB TEE from certificate matches the attestation report.");
-            }
-        }
-
-        // Compare SNP information
-        if let Some(cert_snp) = extensions.get(&SnpOid::Snp.oid()) {
-            if !check_cert_bytes(cert_snp, &att_report.reported_tcb.snp.to_le_bytes()) {
-                return Err(anyhow::anyhow!(
-                    "Report TCB SNP and Certificate SNP mismatch encountered."
-                ));
-            }
-            if !quiet {
-                println!("Reported TCB SNP from certificate matches the attestation report.");
-            }
-        }
-
-        // Compare Microcode information
-        if let Some(cert_ucode) = extensions.get(&SnpOid::Ucode.oid()) {
-            if !check_cert_bytes(cert_ucode, &att_report.reported_tcb.microcode.to_le_bytes()) {
-                return Err(anyhow::anyhow!(
-                    "Report TCB Microcode and Certificate Microcode mismatch encountered."
-                ));
-            }
-            if !quiet {
-                println!("Reported TCB Microcode from certificate matches the attestation report.");
+        // Compare each TCB component present in the VEK
+        let tcb = &att_report.reported_tcb;
+        for (oid, name, label, reported) in [
+            (
+                SnpOid::BootLoader,
+                "bootloader",
+                "Boot Loader",
+                tcb.bootloader,
+            ),
+            (SnpOid::Tee, "tee", "TEE", tcb.tee),
+            (SnpOid::Snp, "snp", "SNP", tcb.snp),
+            (SnpOid::Ucode, "microcode", "Microcode", tcb.microcode),
+        ] {
+            if let Some(cert_ext) = extensions.get(&oid.oid()) {
+                let check = if check_cert_bytes(cert_ext, &reported.to_le_bytes()) {
+                    Check::new(
+                        CheckKind::Tcb,
+                        name,
+                        true,
+                        format!(
+                            "Reported TCB {label} from certificate matches the attestation report."
+                        ),
+                    )
+                } else {
+                    Check::new(
+                        CheckKind::Tcb,
+                        name,
+                        false,
+                        format!("Report TCB {label} and Certificate {label} mismatch encountered."),
+                    )
+                };
+                verification.push(check.compared(Some(cert_value(cert_ext)), reported.to_string()));
             }
         }
 
         // Compare HWID information only on VCEK
         if common_name == CertType::VCEK {
             if let Some(cert_hwid) = extensions.get(&SnpOid::HwId.oid()) {
-                if !check_cert_bytes(cert_hwid, &att_report.chip_id) {
-                    return Err(anyhow::anyhow!(
-                        "Report TCB ID and Certificate ID mismatch encountered."
-                    ));
-                }
-                if !quiet {
-                    println!("Chip ID from certificate matches the attestation report.");
-                }
+                let check = if check_cert_bytes(cert_hwid, &att_report.chip_id) {
+                    Check::new(
+                        CheckKind::ChipId,
+                        "chip_id",
+                        true,
+                        "Chip ID from certificate matches the attestation report.".to_string(),
+                    )
+                } else {
+                    Check::new(
+                        CheckKind::ChipId,
+                        "chip_id",
+                        false,
+                        "Report TCB ID and Certificate ID mismatch encountered.".to_string(),
+                    )
+                };
+                verification.push(
+                    check.compared(Some(cert_value(cert_hwid)), hex::encode(att_report.chip_id)),
+                );
             }
         }
 
         Ok(())
     }
 
-    pub fn verify_attestation(args: Args, quiet: bool) -> Result<()> {
+    /// Returns true if the TCB and chip ID of `vek` match `att_report`.
+    pub fn vek_matches_report(vek: &Certificate, att_report: &AttestationReport) -> bool {
+        let mut verification = VerificationReport::default();
+        verify_attestation_tcb(vek, att_report, &mut verification).is_ok() && verification.passed
+    }
+
+    /// Checks `att_report` against the VEK in `args.certs_dir`: its TCB and
+    /// chip ID, its signature, or both as selected by `args`. A check that
+    /// fails is recorded in the report; only an unreadable VEK is an error.
+    pub fn verify_report(
+        args: &Args,
+        att_report: &AttestationReport,
+    ) -> Result<VerificationReport> {
+        // Get VEK and its public key.
+        let (vek_path, vek_type) = match find_cert_in_dir(&args.certs_dir, "vlek") {
+            Ok(vlek_path) => (vlek_path, "vlek"),
+            Err(_) => (find_cert_in_dir(&args.certs_dir, "vcek")?, "vcek"),
+        };
+
+        // Get VEK and grab its public key
+        let vek = convert_path_to_cert(&vek_path, vek_type)?;
+
+        let mut verification = VerificationReport::default();
+        if args.tcb || !args.signature {
+            verify_attestation_tcb(&vek, att_report, &mut verification)?;
+        }
+        if args.signature || !args.tcb {
+            verify_attestation_signature(&vek, att_report, &mut verification)?;
+        }
+        Ok(verification)
+    }
+
+    pub fn verify_attestation(args: Args, quiet: bool) -> Result<VerificationReport> {
         // Get attestation report
         let att_report = if !args.att_report_path.exists() {
             return Err(anyhow::anyhow!("No attestation report was found. Provide an attestation report to request VEK from the KDS."));
         } else {
-            report::read_report(args.att_report_path)
+            report::read_report(args.att_report_path.clone())
                 .context("Could not open attestation report")?
         };
 
-        if !args.dry {
-            // Get VEK and its public key.
-            let (vek_path, vek_type) = match find_cert_in_dir(&args.certs_dir, "vlek") {
-                Ok(vlek_path) => (vlek_path, "vlek"),
-                Err(_) => (find_cert_in_dir(&args.certs_dir, "vcek")?, "vcek"),
-            };
-
-            // Get VEK and grab its public key
-            let vek = convert_path_to_cert(&vek_path, vek_type)?;
-
-            if args.tcb || args.signature {
-                if args.tcb {
-                    verify_attestation_tcb(vek.clone(), att_report, quiet)?;
-                }
-                if args.signature {
-                    verify_attestation_signature(vek, att_report, quiet)?;
-                }
-            } else {
-                verify_attestation_tcb(vek.clone(), att_report, quiet)?;
-                verify_attestation_signature(vek, att_report, quiet)?;
-            }
-        }
+        let verification = if !args.dry {
+            verify_report(&args, &att_report)?.output(args.format, quiet)?
+        } else {
+            VerificationReport::default()
+        };
 
         if args.output_report_data {
             let out = std::io::stdout();
@@ -422,7 +773,7 @@ pub mod attestation {
             out.write(&att_report.report_data).unwrap();
         }
 
-        Ok(())
+        Ok(verification)
     }
 
     #[cfg(test)]
@@ -706,5 +1057,44 @@ pub mod attestation {
             let ext = extensions.get(&SnpOid::Ucode.oid()).unwrap();
             assert!(check_cert_bytes(ext, &val.to_ne_bytes()));
         }
+
+        #[test]
+        fn test_verification_report() {
+            let (cert_bytes, val) = cert_and_hw_id();
+            let dummy_x509: X509Certificate = X509Certificate::from_der(&cert_bytes).unwrap().1;
+            let extensions = dummy_x509.extensions_map().unwrap();
+            assert_eq!(
+                cert_value(extensions.get(&SnpOid::Ucode.oid()).unwrap()),
+                "30"
+            );
+            assert_eq!(
+                cert_value(extensions.get(&SnpOid::HwId.oid()).unwrap()),
+                hex::encode(&val)
+            );
+
+            let mut verification = VerificationReport::default();
+            verification.push(
+                Check::new(CheckKind::Tcb, "snp", true, "matches".to_string())
+                    .compared(Some("8".to_string()), "8".to_string()),
+            );
+            assert!(verification.clone().into_result().is_ok());
+            verification.push(
+                Check::new(CheckKind::Revocation, "ASK", false, "revoked".to_string())
+                    .compared(None, "1f".to_string()),
+            );
+            let json: serde_json::Value =
+                serde_json::from_str(&verification.to_json().unwrap()).unwrap();
+            assert_eq!(json["passed"], false);
+            assert_eq!(json["checks"][0]["expected"], "8");
+            assert_eq!(json["checks"][1]["kind"], "revocation");
+            assert!(json["checks"][1].get("expected").is_none());
+
+            let err = verification.into_result().unwrap_err();
+            assert!(err
+                .chain()
+                .any(|cause| cause.is::<certificate_chain::CertificateRevoked>()));
+            let failed = err.downcast_ref::<VerificationFailed>().unwrap();
+            assert_eq!(failed.report.checks.len(), 2);
+        }
     }
 }
@@ -1,8 +1,13 @@
 use super::*;
+use openssl::asn1::Asn1Time;
+use openssl::x509::{X509Crl, X509VerifyResult, X509};
+use sev::certs::snp::Certificate;
 use sev::firmware::guest::AttestationReport;
 use sev::firmware::host::CertType;
-use std::io::Write;
-use std::path::PathBuf;
+use std::collections::BTreeMap;
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
 
 #[derive(Parser)]
 pub struct Args {
@@ -13,108 +18,567 @@ pub struct Args {
     /// Path to attestation report to use for validation.
     #[arg(value_name = "ext-att-report-path", required = true)]
     pub ext_att_report_path: PathBuf,
+
+    /// PEM bundle of pre-provisioned ARK, ASK and VCEK certificates.
+    #[arg(long, value_name = "cert-bundle")]
+    pub cert_bundle: Option<PathBuf>,
+
+    /// Base URL of the KDS, or of a mirror serving the same paths.
+    #[arg(long, value_name = "url", default_value = fetch::KDS_CERT_SITE)]
+    pub kds_url: String,
+
+    /// Never fetch certificates; fail if the certs dir and bundle lack one.
+    #[arg(long, default_value_t = false)]
+    pub offline: bool,
+
+    /// Print the report data, or the checks as a JSON verification report.
+    #[arg(long, value_enum, default_value_t = verify::OutputFormat::Text)]
+    pub format: verify::OutputFormat,
+}
+
+/// Where `fetch_and_verify_async` gets the AMD certificates from.
+#[derive(Clone, Debug)]
+pub struct CertSource {
+    /// Directory the certificates are cached in.
+    pub certs_dir: PathBuf,
+    /// PEM bundle of certificates used for the ones missing from the cache.
+    pub cert_bundle: Option<PathBuf>,
+    /// Base URL of the KDS, or of a mirror serving the same paths.
+    pub kds_url: String,
+    /// Never fetch from the KDS.
+    pub offline: bool,
 }
 
 pub async fn fetch_and_verify(args: Args) -> Result<()> {
-    fetch_and_verify_async(args.certs_dir, args.ext_att_report_path, false).await?;
+    let source = CertSource {
+        certs_dir: args.certs_dir,
+        cert_bundle: args.cert_bundle,
+        kds_url: args.kds_url,
+        offline: args.offline,
+    };
+    let json = args.format == verify::OutputFormat::Json;
+    let result = fetch_and_verify_async(&source, args.ext_att_report_path, json).await;
+    if json {
+        let verification = match &result {
+            Ok((_, verification)) => Some(verification),
+            Err(e) => e
+                .downcast_ref::<verify::VerificationFailed>()
+                .map(|failed| &failed.report),
+        };
+        if let Some(verification) = verification {
+            println!("{}", verification.to_json()?);
+        }
+    }
+    result?;
     Ok(())
 }
 
-pub async fn fetch_and_verify_async(
-    cert_dir: PathBuf,
-    ext_att_path: PathBuf,
-    quiet: bool,
-) -> Result<AttestationReport> {
-    let ext_att_bin =
-        std::fs::read(&ext_att_path).context("failed to read extended attestation reoprt")?;
-    let ext_att: report::ExtendedAttestationReport =
-        bincode::deserialize(&ext_att_bin).context("failed to decode ExtendedAttestationReport")?;
-    let mut att_file = tempfile::NamedTempFile::new()?;
-    att_file.write(&bincode::serialize(&ext_att.report)?)?;
+const CRL_PEM_BEGIN: &str = "-----BEGIN X509 CRL-----";
 
+/// Certificates of a bundle, sorted by their place in the chain, and the
+/// CRLs in it in DER format.
+struct Bundle {
+    ark: Option<X509>,
+    ask: Option<X509>,
+    veks: Vec<X509>,
+    crls: Vec<Vec<u8>>,
+}
+
+/// Reads a PEM bundle and tells the ARK, the ASK (or ASVK) and the VEKs
+/// apart by who issued them, so the order in the file does not matter.
+fn read_bundle(path: &Path) -> Result<Bundle> {
+    let pem = std::fs::read(path)
+        .with_context(|| format!("failed to read cert bundle {}", path.display()))?;
+    let certs = X509::stack_from_pem(&pem)
+        .with_context(|| format!("failed to parse cert bundle {}", path.display()))?;
+    let (arks, rest): (Vec<X509>, Vec<X509>) = certs
+        .into_iter()
+        .partition(|cert| cert.issued(cert) == X509VerifyResult::OK);
+    let ark = arks.into_iter().next();
+    let (asks, rest): (Vec<X509>, Vec<X509>) = rest.into_iter().partition(|cert| {
+        ark.as_ref()
+            .is_some_and(|ark| ark.issued(cert) == X509VerifyResult::OK)
+    });
+    let ask = asks.into_iter().next();
+    let veks = rest
+        .into_iter()
+        .filter(|cert| {
+            ask.as_ref()
+                .is_some_and(|ask| ask.issued(cert) == X509VerifyResult::OK)
+        })
+        .collect();
+    let crls = String::from_utf8_lossy(&pem)
+        .split(CRL_PEM_BEGIN)
+        .skip(1)
+        .map(|block| X509Crl::from_pem(format!("{CRL_PEM_BEGIN}{block}").as_bytes())?.to_der())
+        .collect::<Result<Vec<_>, _>>()
+        .with_context(|| format!("failed to parse a CRL in cert bundle {}", path.display()))?;
+    Ok(Bundle {
+        ark,
+        ask,
+        veks,
+        crls,
+    })
+}
+
+/// PEM certificates already read from or written to the cache directory,
+/// keyed by their path in it.
+static MEMORY_CACHE: Mutex<BTreeMap<PathBuf, Vec<u8>>> = Mutex::new(BTreeMap::new());
+
+fn cache_get(path: &Path) -> Result<Option<Vec<u8>>> {
+    if let Some(pem) = MEMORY_CACHE.lock().unwrap().get(path) {
+        return Ok(Some(pem.clone()));
+    }
+    match std::fs::read(path) {
+        Ok(pem) => {
+            MEMORY_CACHE
+                .lock()
+                .unwrap()
+                .insert(path.to_path_buf(), pem.clone());
+            Ok(Some(pem))
+        }
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e).with_context(|| format!("failed to read {}", path.display())),
+    }
+}
+
+/// Writes a certificate to a temporary file that is then renamed into place,
+/// so that a concurrent verification reads either nothing or the whole file.
+fn cache_put(path: &Path, pem: &[u8]) -> Result<()> {
+    let dir = path.parent().context("cache path has no parent")?;
+    std::fs::create_dir_all(dir).context("failed to create certs dir")?;
+    let mut file = tempfile::NamedTempFile::new_in(dir)?;
+    file.write_all(pem)?;
+    file.persist(path)
+        .with_context(|| format!("failed to write {}", path.display()))?;
+    MEMORY_CACHE
+        .lock()
+        .unwrap()
+        .insert(path.to_path_buf(), pem.to_vec());
+    Ok(())
+}
+
+/// Directory of the ARK and ASK (or ASVK) of a processor type and endorsement.
+fn ca_cache_dir(certs_dir: &Path, ext_att: &report::ExtendedAttestationReport) -> PathBuf {
+    certs_dir
+        .join(ext_att.proc_type.to_string().to_lowercase())
+        .join(ext_att.endorsement.to_string().to_lowercase())
+}
+
+/// Path of the VCEK of the chip and reported TCB of a report.
+fn vcek_cache_path(certs_dir: &Path, ext_att: &report::ExtendedAttestationReport) -> PathBuf {
+    let tcb = &ext_att.report.reported_tcb;
+    certs_dir
+        .join(ext_att.proc_type.to_string().to_lowercase())
+        .join("vcek")
+        .join(hex::encode(ext_att.report.chip_id))
+        .join(format!(
+            "{:02}-{:02}-{:02}-{:02}.pem",
+            tcb.bootloader, tcb.tee, tcb.snp, tcb.microcode
+        ))
+}
+
+/// Returns the ARK and ASK (or ASVK) for a report from the cache, the bundle
+/// or the KDS, in that order.
+async fn ca_certs(
+    source: &CertSource,
+    bundle: Option<&Bundle>,
+    ext_att: &report::ExtendedAttestationReport,
+) -> Result<(Vec<u8>, Vec<u8>)> {
+    let ca_dir = ca_cache_dir(&source.certs_dir, ext_att);
     let (ark_path, _) = certs::get_cert_path(
-        &cert_dir,
+        &ca_dir,
         &CertType::ARK,
         certs::CertFormat::Pem,
         &ext_att.endorsement,
     );
     let (ask_path, _) = certs::get_cert_path(
-        &cert_dir,
+        &ca_dir,
         &CertType::ASK,
         certs::CertFormat::Pem,
         &ext_att.endorsement,
     );
+    if let (Some(ark), Some(ask)) = (cache_get(&ark_path)?, cache_get(&ask_path)?) {
+        return Ok((ark, ask));
+    }
 
-    if !ark_path.exists() || !ask_path.exists() {
-        if cert_dir.exists() {
-            std::fs::remove_dir_all(&cert_dir).context("failed to remove certs dir")?;
-        }
-        std::fs::create_dir_all(&cert_dir).context("failed to create certs dir")?;
+    let (ark, ask) = if let Some(Bundle {
+        ark: Some(ark),
+        ask: Some(ask),
+        ..
+    }) = bundle
+    {
+        (ark.to_pem()?, ask.to_pem()?)
+    } else if source.offline {
+        return Err(anyhow::anyhow!(
+            "ARK and ASK are not in {} or the cert bundle, and fetching is disabled in offline mode",
+            ca_dir.display()
+        ));
+    } else {
+        let certificates = fetch::cert_authority::request_ca_kds_async(
+            &source.kds_url,
+            ext_att.proc_type,
+            &ext_att.endorsement,
+        )
+        .await
+        .context("failed to fetch ark and ask certs")?;
+        (certificates[1].to_pem()?, certificates[0].to_pem()?)
+    };
+    cache_put(&ark_path, &ark)?;
+    cache_put(&ask_path, &ask)?;
+    Ok((ark, ask))
+}
 
-        let fetch_args = fetch::cert_authority::Args {
-            encoding: certs::CertFormat::Pem,
-            processor_model: ext_att.proc_type,
-            certs_dir: cert_dir.clone(),
-            endorser: ext_att.endorsement,
-        };
-        fetch::cert_authority::fetch_ca_async(fetch_args)
-            .await
-            .context("failed to fetch ark and ask certs")?;
+/// Returns the VCEK for the chip and reported TCB of a report from the
+/// cache, the bundle or the KDS, in that order.
+async fn vcek_cert(
+    source: &CertSource,
+    bundle: Option<&Bundle>,
+    ext_att: &report::ExtendedAttestationReport,
+    att_report_path: PathBuf,
+) -> Result<Vec<u8>> {
+    let vcek_path = vcek_cache_path(&source.certs_dir, ext_att);
+    if let Some(vcek) = cache_get(&vcek_path)? {
+        return Ok(vcek);
     }
 
-    match ext_att.endorsement {
-        fetch::Endorsement::Vcek => {
-            let (vcek_path, _) = certs::get_cert_path(
-                &cert_dir,
-                &CertType::VCEK,
-                certs::CertFormat::Pem,
-                &ext_att.endorsement,
-            );
-            if !vcek_path.exists() {
-                let fetch_args = fetch::vcek::Args {
-                    encoding: certs::CertFormat::Pem,
-                    processor_model: ext_att.proc_type,
-                    certs_dir: cert_dir.clone(),
-                    att_report_path: att_file.path().to_path_buf(),
-                };
-                fetch::vcek::fetch_vcek_async(fetch_args)
-                    .await
-                    .context("failed to fetch vcek cert")?;
+    let mut vcek = None;
+    for vek in bundle.iter().flat_map(|bundle| bundle.veks.iter()) {
+        let cert = Certificate::from_bytes(&vek.to_pem()?)?;
+        if verify::attestation::vek_matches_report(&cert, &ext_att.report) {
+            vcek = Some(vek.to_pem()?);
+            break;
+        }
+    }
+    let vcek = match vcek {
+        Some(vcek) => vcek,
+        None if source.offline => {
+            return Err(anyhow::anyhow!(
+                "no VCEK for the chip and TCB of the report is in {} or the cert bundle, and fetching is disabled in offline mode",
+                source.certs_dir.display()
+            ))
+        }
+        None => {
+            let der = fetch::vcek::request_vcek_kds_async(
+                &source.kds_url,
+                ext_att.proc_type,
+                att_report_path,
+            )
+            .await
+            .context("failed to fetch vcek cert")?;
+            let cert = Certificate::from_bytes(&der)?;
+            if !verify::attestation::vek_matches_report(&cert, &ext_att.report) {
+                return Err(anyhow::anyhow!(
+                    "the VCEK from the KDS does not match the chip and TCB of the report"
+                ));
             }
+            cert.to_pem()?
+        }
+    };
+    cache_put(&vcek_path, &vcek)?;
+    Ok(vcek)
+}
+
+/// Returns the CRL of the ARK of a report in DER format. A current CRL from
+/// the cache or the bundle is used first; otherwise the CRL is fetched from
+/// the KDS, or, in offline mode, the stale one is used with a warning. Only
+/// CRLs signed by `ark` are used or cached.
+async fn crl(
+    source: &CertSource,
+    bundle: Option<&Bundle>,
+    ext_att: &report::ExtendedAttestationReport,
+    ark: &[u8],
+) -> Result<Vec<u8>> {
+    let (crl_path, _) = certs::get_cert_path(
+        &ca_cache_dir(&source.certs_dir, ext_att),
+        &CertType::CRL,
+        certs::CertFormat::Der,
+        &ext_att.endorsement,
+    );
+    let ark_key = X509::from_pem(ark)?.public_key()?;
+    let signed = |der: &Vec<u8>| {
+        X509Crl::from_der(der)
+            .and_then(|crl| crl.verify(&ark_key))
+            .unwrap_or(false)
+    };
+    let cached = cache_get(&crl_path)?;
+    let candidates: Vec<Vec<u8>> = cached
+        .iter()
+        .chain(bundle.into_iter().flat_map(|bundle| &bundle.crls))
+        .filter(|der| signed(*der))
+        .cloned()
+        .collect();
+
+    let now = Asn1Time::days_from_now(0)?;
+    let is_current = |der: &Vec<u8>| -> Result<bool> {
+        Ok(X509Crl::from_der(der)?
+            .next_update()
+            .is_some_and(|next_update| next_update > now))
+    };
+    let mut current = None;
+    for der in &candidates {
+        if is_current(der)? {
+            current = Some(der.clone());
+            break;
         }
-        fetch::Endorsement::Vlek => {
-            let (vlek_path, _) = certs::get_cert_path(
-                &cert_dir,
-                &CertType::VLEK,
-                certs::CertFormat::Pem,
+    }
+    let crl = match current {
+        Some(crl) => crl,
+        None if source.offline => {
+            let crl = candidates.into_iter().next().ok_or_else(|| {
+                anyhow::anyhow!(
+                    "no CRL signed by the ARK is in {} or the cert bundle, and fetching is disabled in offline mode",
+                    source.certs_dir.display()
+                )
+            })?;
+            eprintln!("WARNING: using an AMD CRL past its next update in offline mode");
+            crl
+        }
+        None => {
+            let crl = fetch::crl::request_crl_kds_async(
+                &source.kds_url,
+                ext_att.proc_type,
                 &ext_att.endorsement,
-            );
-            let mut vlek_file =
-                std::fs::File::create(vlek_path).context("failed to create VLEK file")?;
-            vlek_file
-                .write(&ext_att.vlek_pem.context("VLEK is not specified")?)
-                .context("failed to write VLEK certificate")?;
+            )
+            .await
+            .context("failed to fetch crl")?;
+            if !signed(&crl) {
+                return Err(anyhow::anyhow!(
+                    "the CRL from the KDS is not signed by the ARK"
+                ));
+            }
+            crl
         }
+    };
+    if cached.as_ref() != Some(&crl) {
+        cache_put(&crl_path, &crl)?;
     }
+    Ok(crl)
+}
+
+/// Verifies an extended attestation report with the AMD certificates for
+/// its processor type, endorsement, chip ID and reported TCB, and checks
+/// the ASK and VEK against the AMD CRL. Certificates are cached under
+/// `source.certs_dir` by those keys, and each verification runs on a private
+/// copy of its own certificates.
+///
+/// Returns the report with every check performed, or an error; when a check
+/// fails, the error is a `verify::VerificationFailed` holding all the checks.
+pub async fn fetch_and_verify_async(
+    source: &CertSource,
+    ext_att_path: PathBuf,
+    quiet: bool,
+) -> Result<(AttestationReport, verify::VerificationReport)> {
+    let ext_att_bin =
+        std::fs::read(&ext_att_path).context("failed to read extended attestation reoprt")?;
+    let ext_att: report::ExtendedAttestationReport =
+        bincode::deserialize(&ext_att_bin).context("failed to decode ExtendedAttestationReport")?;
+    let mut att_file = tempfile::NamedTempFile::new()?;
+    att_file.write(&bincode::serialize(&ext_att.report)?)?;
+
+    let bundle = match &source.cert_bundle {
+        Some(path) => Some(read_bundle(path)?),
+        None => None,
+    };
+
+    let (ark, ask) = ca_certs(source, bundle.as_ref(), &ext_att).await?;
+    let crl = crl(source, bundle.as_ref(), &ext_att, &ark).await?;
+    let (vek_type, vek) = match ext_att.endorsement {
+        fetch::Endorsement::Vcek => (
+            CertType::VCEK,
+            vcek_cert(
+                source,
+                bundle.as_ref(),
+                &ext_att,
+                att_file.path().to_path_buf(),
+            )
+            .await?,
+        ),
+        fetch::Endorsement::Vlek => (
+            CertType::VLEK,
+            ext_att.vlek_pem.clone().context("VLEK is not specified")?,
+        ),
+    };
+
+    let cert_dir = tempfile::tempdir().context("failed to create certs dir")?;
+    for (cert_type, pem) in [
+        (CertType::ARK, &ark),
+        (CertType::ASK, &ask),
+        (vek_type, &vek),
+    ] {
+        let (path, cert_str) = certs::get_cert_path(
+            cert_dir.path(),
+            &cert_type,
+            certs::CertFormat::Pem,
+            &ext_att.endorsement,
+        );
+        std::fs::write(path, pem).with_context(|| format!("failed to write {cert_str} cert"))?;
+    }
+    let (crl_path, _) = certs::get_cert_path(
+        cert_dir.path(),
+        &CertType::CRL,
+        certs::CertFormat::Der,
+        &ext_att.endorsement,
+    );
+    std::fs::write(crl_path, &crl).context("failed to write crl")?;
 
     // always validate certificate chain
-    let verify_args = verify::certificate_chain::Args {
-        certs_dir: cert_dir.clone(),
+    let chain_args = verify::certificate_chain::Args {
+        certs_dir: cert_dir.path().to_path_buf(),
+        format: verify::OutputFormat::Text,
     };
-    verify::certificate_chain::validate_cc(verify_args, true)
+    let mut verification = verify::certificate_chain::chain_report(&chain_args)
         .context("failed to validate certificate chain")?;
 
     // validate attestation report
     let verify_args = verify::attestation::Args {
-        certs_dir: cert_dir.clone(),
+        certs_dir: cert_dir.path().to_path_buf(),
         att_report_path: att_file.path().to_path_buf(),
         tcb: false,
         signature: false,
         dry: false,
-        output_report_data: !quiet,
+        output_report_data: false,
+        format: verify::OutputFormat::Text,
     };
-    verify::attestation::verify_attestation(verify_args, true)
-        .context("failed to validate attestation report")?;
+    verification.append(
+        verify::attestation::verify_report(&verify_args, &ext_att.report)
+            .context("failed to validate attestation report")?,
+    );
+    let verification = verification.into_result()?;
+
+    if !quiet {
+        std::io::stdout().write_all(&ext_att.report.report_data)?;
+    }
+
+    return Ok((ext_att.report, verification));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        ca_cache_dir, cache_get, cache_put, crl, fetch_and_verify_async, vcek_cache_path, Bundle,
+        CertSource,
+    };
+    use crate::certs;
+    use crate::fetch::{Endorsement, ProcType};
+    use crate::report::ExtendedAttestationReport;
+    use openssl::x509::X509Crl;
+    use sev::firmware::guest::AttestationReport;
+    use sev::firmware::host::CertType;
+    use std::path::Path;
+
+    fn ext_att(chip_id: u8, snp: u8) -> ExtendedAttestationReport {
+        let mut report = AttestationReport::default();
+        report.chip_id = [chip_id; 64];
+        report.reported_tcb.snp = snp;
+        ExtendedAttestationReport {
+            proc_type: ProcType::Milan,
+            endorsement: Endorsement::Vcek,
+            vlek_pem: None,
+            report,
+        }
+    }
+
+    #[test]
+    fn vceks_are_cached_per_chip_and_tcb() {
+        let certs_dir = Path::new("/certs");
+        let vcek_path = vcek_cache_path(certs_dir, &ext_att(1, 8));
+        assert_eq!(vcek_path, vcek_cache_path(certs_dir, &ext_att(1, 8)));
+        assert_ne!(vcek_path, vcek_cache_path(certs_dir, &ext_att(2, 8)));
+        assert_ne!(vcek_path, vcek_cache_path(certs_dir, &ext_att(1, 9)));
+
+        let temp_dir = tempfile::tempdir().unwrap();
+        let vcek_path = vcek_cache_path(temp_dir.path(), &ext_att(1, 8));
+        assert_eq!(cache_get(&vcek_path).unwrap(), None);
+        cache_put(&vcek_path, b"vcek").unwrap();
+        assert_eq!(std::fs::read(&vcek_path).unwrap(), b"vcek");
+        // Later verifications are served from memory.
+        std::fs::remove_file(&vcek_path).unwrap();
+        assert_eq!(cache_get(&vcek_path).unwrap(), Some(b"vcek".to_vec()));
+    }
+
+    #[test]
+    fn offline_mode_fails_without_fetching_missing_certs() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let ext_att_path = temp_dir.path().join("report.bin");
+        std::fs::write(&ext_att_path, bincode::serialize(&ext_att(0, 0)).unwrap()).unwrap();
 
-    return Ok(ext_att.report);
+        let source = CertSource {
+            certs_dir: temp_dir.path().join("certs"),
+            cert_bundle: None,
+            kds_url: "http://127.0.0.1:9".to_string(),
+            offline: true,
+        };
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        let err = runtime
+            .block_on(fetch_and_verify_async(&source, ext_att_path, true))
+            .unwrap_err();
+        assert!(err.to_string().contains("offline mode"));
+        assert!(!temp_dir.path().join("certs").exists());
+    }
+
+    #[test]
+    fn only_crls_signed_by_the_ark_are_used() {
+        let der = |pem: &[u8]| X509Crl::from_pem(pem).unwrap().to_der().unwrap();
+        let signed = der(include_bytes!("../test/crl/crl.pem"));
+        let stale = der(include_bytes!("../test/crl/stale_crl.pem"));
+        let forged = der(include_bytes!("../test/crl/forged_crl.pem"));
+        let ark = include_bytes!("../test/crl/ark.pem");
+        let bundle = |crls: &[&Vec<u8>]| Bundle {
+            ark: None,
+            ask: None,
+            veks: Vec::new(),
+            crls: crls.iter().map(|crl| crl.to_vec()).collect(),
+        };
+        let ext_att = ext_att(0, 0);
+        let temp_dir = tempfile::tempdir().unwrap();
+        let source = |name: &str| CertSource {
+            certs_dir: temp_dir.path().join(name),
+            cert_bundle: None,
+            kds_url: "http://127.0.0.1:9".to_string(),
+            offline: true,
+        };
+        let crl_path = |source: &CertSource| {
+            certs::get_cert_path(
+                &ca_cache_dir(&source.certs_dir, &ext_att),
+                &CertType::CRL,
+                certs::CertFormat::Der,
+                &ext_att.endorsement,
+            )
+            .0
+        };
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+
+        // A forged CRL is neither used from the cache nor from the bundle.
+        let current = source("current");
+        cache_put(&crl_path(&current), &forged).unwrap();
+        let bundled = bundle(&[&forged, &stale, &signed]);
+        let crl_der = runtime
+            .block_on(crl(&current, Some(&bundled), &ext_att, ark))
+            .unwrap();
+        assert_eq!(crl_der, signed);
+        assert_eq!(cache_get(&crl_path(&current)).unwrap(), Some(signed));
+
+        // Offline, a stale CRL is used if no current one is signed.
+        let stale_only = source("stale");
+        let bundled = bundle(&[&forged, &stale]);
+        let crl_der = runtime
+            .block_on(crl(&stale_only, Some(&bundled), &ext_att, ark))
+            .unwrap();
+        assert_eq!(crl_der, stale);
+
+        let forged_only = source("forged");
+        let bundled = bundle(&[&forged]);
+        let err = runtime
+            .block_on(crl(&forged_only, Some(&bundled), &ext_att, ark))
+            .unwrap_err();
+        assert!(err.to_string().contains("no CRL signed by the ARK"));
+        assert_eq!(cache_get(&crl_path(&forged_only)).unwrap(), None);
+    }
 }
@@ -19,4 +19,12 @@ CREATE TABLE policy_by_item (
     item TEXT NOT NULL,
     json TEXT NOT NULL
 );
+EOF
+
+sqlite3 $DB <<EOF
+CREATE TABLE policy_by_dataset (
+    dataset TEXT NOT NULL,
+    subject TEXT NOT NULL,
+    json TEXT NOT NULL
+);
 EOF
\ No newline at end of file
@@ -0,0 +1,231 @@
+// SPDX-FileCopyrightText: 2025 SEERA Networks Corporation <info@seera-networks.com>
+// SPDX-License-Identifier: MIT
+
+//! Access control lists loaded from `--acl-file`. Each rule lets subjects,
+//! or the members of groups from the `groups` claim of their JWT, use some
+//! operations on some datasets and, optionally, only some of their columns.
+//!
+//! ```toml
+//! [[rule]]
+//! groups = ["analysts"]
+//! datasets = ["wage1"]
+//! columns = ["wage", "educ"]
+//! operations = ["get", "list"]
+//! ```
+//!
+//! `*` matches every subject, group, dataset or column, and a rule without
+//! `columns` covers every column. Stored targets form the `storage` dataset,
+//! and the `system` target is checked as the dataset it is an alias of.
+
+use serde::Deserialize;
+use tonic::Status;
+use tracing::warn;
+
+use crate::auth::Subject;
+use crate::CmdOptions;
+
+const WILDCARD: &str = "*";
+
+/// An operation checked against the ACL.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Operation {
+    /// Reading data with `do_get`.
+    Get,
+    /// Uploading data with `do_put`.
+    Put,
+    /// Listing datasets, columns, schemas and targets.
+    List,
+    /// Managing stored targets. Also allows every other operation.
+    Admin,
+}
+
+impl std::fmt::Display for Operation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Operation::Get => "get",
+            Operation::Put => "put",
+            Operation::List => "list",
+            Operation::Admin => "admin",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+fn matches(patterns: &[String], value: &str) -> bool {
+    patterns
+        .iter()
+        .any(|pattern| pattern == WILDCARD || pattern == value)
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Rule {
+    #[serde(default)]
+    subjects: Vec<String>,
+    #[serde(default)]
+    groups: Vec<String>,
+    datasets: Vec<String>,
+    columns: Option<Vec<String>>,
+    operations: Vec<Operation>,
+}
+
+impl Rule {
+    fn applies(&self, subject: &Subject, operation: Operation, dataset: &str) -> bool {
+        let is_member = matches(&self.subjects, &subject.name)
+            || subject
+                .groups
+                .iter()
+                .any(|group| matches(&self.groups, group));
+        is_member
+            && matches(&self.datasets, dataset)
+            && self
+                .operations
+                .iter()
+                .any(|op| *op == operation || *op == Operation::Admin)
+    }
+
+    fn covers(&self, column_name: &str) -> bool {
+        self.columns
+            .as_ref()
+            .is_none_or(|columns| matches(columns, column_name))
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Acl {
+    #[serde(default, rename = "rule")]
+    rules: Vec<Rule>,
+}
+
+impl Acl {
+    pub fn from_toml(text: &str) -> anyhow::Result<Self> {
+        Ok(toml::from_str(text)?)
+    }
+
+    /// Loads `--acl-file`, or returns `None` if every authenticated subject
+    /// may use everything.
+    pub fn from_cmd_opts(cmd_opts: &CmdOptions) -> anyhow::Result<Option<Self>> {
+        let Some(path) = &cmd_opts.acl_file else {
+            return Ok(None);
+        };
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| anyhow::anyhow!("failed to read ACL file {}: {}", path, e))?;
+        let acl = Self::from_toml(&text)
+            .map_err(|e| anyhow::anyhow!("invalid ACL file {}: {}", path, e))?;
+        Ok(Some(acl))
+    }
+
+    /// Returns true if a rule lets `subject` use `operation` on `dataset`,
+    /// and on `column_name` of it if given.
+    pub fn allows(
+        &self,
+        subject: &Subject,
+        operation: Operation,
+        dataset: &str,
+        column_name: Option<&str>,
+    ) -> bool {
+        self.rules.iter().any(|rule| {
+            rule.applies(subject, operation, dataset)
+                && column_name.is_none_or(|column_name| rule.covers(column_name))
+        })
+    }
+
+    /// Checks that `subject` may use `operation` on `dataset`, and on each of
+    /// `column_names` if any. A denial is logged with its reason and returned
+    /// as `permission_denied`.
+    pub fn check(
+        &self,
+        subject: &Subject,
+        operation: Operation,
+        dataset: &str,
+        column_names: &[&str],
+    ) -> Result<(), Status> {
+        let denied = if column_names.is_empty() {
+            (!self.allows(subject, operation, dataset, None))
+                .then(|| format!("no ACL rule allows {} on dataset {}", operation, dataset))
+        } else {
+            column_names
+                .iter()
+                .copied()
+                .find(|column_name| !self.allows(subject, operation, dataset, Some(*column_name)))
+                .map(|column_name| {
+                    format!(
+                        "no ACL rule allows {} on column {} of dataset {}",
+                        operation, column_name, dataset
+                    )
+                })
+        };
+        match denied {
+            Some(reason) => {
+                warn!(
+                    "access denied: subject: {}, groups: {:?}, measurement: {}, reason: {}",
+                    subject.name,
+                    subject.groups,
+                    subject.measurement_hex(),
+                    reason
+                );
+                Err(Status::permission_denied(reason))
+            }
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Acl, Operation};
+    use crate::auth::Subject;
+
+    #[test]
+    fn rules_match_subjects_groups_datasets_and_columns() {
+        let acl = Acl::from_toml(
+            r#"
+            [[rule]]
+            groups = ["analysts"]
+            datasets = ["wage1"]
+            columns = ["wage", "educ"]
+            operations = ["get", "list"]
+
+            [[rule]]
+            subjects = ["alice"]
+            datasets = ["*"]
+            operations = ["admin"]
+            "#,
+        )
+        .unwrap();
+        let analyst = Subject {
+            name: "bob".to_string(),
+            groups: vec!["analysts".to_string()],
+            ..Default::default()
+        };
+        let alice = Subject::new("alice");
+        let other = Subject::new("carol");
+
+        assert!(acl
+            .check(&analyst, Operation::Get, "wage1", &["wage"])
+            .is_ok());
+        assert!(acl
+            .check(&analyst, Operation::Get, "wage1", &["wage", "educ"])
+            .is_ok());
+        let denied = acl
+            .check(&analyst, Operation::Get, "wage1", &["wage", "female"])
+            .unwrap_err();
+        assert_eq!(denied.code(), tonic::Code::PermissionDenied);
+        assert!(denied.message().contains("female"));
+        assert!(acl.check(&analyst, Operation::Put, "storage", &[]).is_err());
+        assert!(acl
+            .check(&analyst, Operation::Get, "edinet", &["x"])
+            .is_err());
+        assert!(acl.allows(&analyst, Operation::List, "wage1", None));
+
+        assert!(acl.check(&alice, Operation::Put, "storage", &[]).is_ok());
+        assert!(acl
+            .check(&alice, Operation::Get, "wage1", &["female"])
+            .is_ok());
+        assert!(acl.check(&other, Operation::List, "wage1", &[]).is_err());
+
+        assert!(Acl::from_toml("[[rule]]\ndatasets = [\"*\"]\noperations = [\"delete\"]").is_err());
+    }
+}
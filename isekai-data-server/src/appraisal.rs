@@ -0,0 +1,374 @@
+// SPDX-FileCopyrightText: 2025 SEERA Networks Corporation <info@seera-networks.com>
+// SPDX-License-Identifier: MIT
+
+//! Appraisal of the attestation reports verified by the handshake against
+//! `--appraisal-policy`, a TOML file that is reloaded whenever it changes.
+//!
+//! ```toml
+//! [[measurement]]
+//! label = "isekai-app 1.2.0"
+//! value = "<96 hex digits>"
+//!
+//! [min_reported_tcb]
+//! bootloader = 3
+//! tee = 0
+//! snp = 8
+//! microcode = 115
+//!
+//! [guest_policy]
+//! debug_allowed = false
+//! smt_allowed = true
+//! migrate_ma_allowed = false
+//!
+//! vmpl = 0
+//! min_guest_svn = 1
+//! id_key_digests = ["<96 hex digits>"]
+//! author_key_digests = ["<96 hex digits>"]
+//! family_id = "<32 hex digits>"
+//! image_id = "<32 hex digits>"
+//! ```
+//!
+//! Every part is optional; an empty list or a missing value is not checked.
+
+use serde::{Deserialize, Deserializer};
+use sev::firmware::guest::AttestationReport;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+use tracing::{error, info};
+
+use crate::auth::hex;
+use crate::CmdOptions;
+
+const SMT_ALLOWED_BIT: u64 = 1 << 16;
+const MIGRATE_MA_ALLOWED_BIT: u64 = 1 << 18;
+const DEBUG_ALLOWED_BIT: u64 = 1 << 19;
+
+/// `N` bytes written as `2 * N` hex digits.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Hex<const N: usize>([u8; N]);
+
+impl<'de, const N: usize> Deserialize<'de> for Hex<N> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let text = String::deserialize(deserializer)?;
+        let text = text.trim();
+        if text.len() != 2 * N || !text.is_ascii() {
+            return Err(serde::de::Error::custom(format!(
+                "expected {} hex digits, got {:?}",
+                2 * N,
+                text
+            )));
+        }
+        let mut bytes = [0u8; N];
+        for (i, byte) in bytes.iter_mut().enumerate() {
+            *byte = u8::from_str_radix(&text[2 * i..2 * i + 2], 16)
+                .map_err(|_| serde::de::Error::custom(format!("invalid hex digits: {:?}", text)))?;
+        }
+        Ok(Hex(bytes))
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Measurement {
+    label: String,
+    value: Hex<48>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct MinTcb {
+    bootloader: u8,
+    tee: u8,
+    snp: u8,
+    microcode: u8,
+}
+
+/// The values required of the guest policy bits, each unchecked if unset.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct GuestPolicyRequirement {
+    debug_allowed: Option<bool>,
+    smt_allowed: Option<bool>,
+    migrate_ma_allowed: Option<bool>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AppraisalPolicy {
+    #[serde(rename = "measurement")]
+    measurements: Vec<Measurement>,
+    min_reported_tcb: Option<MinTcb>,
+    guest_policy: Option<GuestPolicyRequirement>,
+    vmpl: Option<u32>,
+    min_guest_svn: Option<u32>,
+    id_key_digests: Vec<Hex<48>>,
+    author_key_digests: Vec<Hex<48>>,
+    family_id: Option<Hex<16>>,
+    image_id: Option<Hex<16>>,
+}
+
+impl AppraisalPolicy {
+    pub fn from_toml(text: &str) -> anyhow::Result<Self> {
+        Ok(toml::from_str(text)?)
+    }
+
+    /// Checks `report` against the policy and returns the label of its
+    /// measurement, if the policy lists any, or the reason it is rejected.
+    pub fn appraise(&self, report: &AttestationReport) -> Result<Option<String>, String> {
+        let label = if self.measurements.is_empty() {
+            None
+        } else {
+            let measurement = self
+                .measurements
+                .iter()
+                .find(|measurement| measurement.value.0 == report.measurement)
+                .ok_or_else(|| {
+                    format!(
+                        "measurement {} is not in the allowlist",
+                        hex(&report.measurement)
+                    )
+                })?;
+            Some(measurement.label.clone())
+        };
+
+        if let Some(min_tcb) = &self.min_reported_tcb {
+            let tcb = &report.reported_tcb;
+            for (name, reported, min) in [
+                ("bootloader", tcb.bootloader, min_tcb.bootloader),
+                ("tee", tcb.tee, min_tcb.tee),
+                ("snp", tcb.snp, min_tcb.snp),
+                ("microcode", tcb.microcode, min_tcb.microcode),
+            ] {
+                if reported < min {
+                    return Err(format!(
+                        "reported_tcb.{} {} is below the minimum {}",
+                        name, reported, min
+                    ));
+                }
+            }
+        }
+
+        if let Some(requirement) = &self.guest_policy {
+            let bits = report.policy.0;
+            for (name, bit, required) in [
+                (
+                    "debug_allowed",
+                    DEBUG_ALLOWED_BIT,
+                    requirement.debug_allowed,
+                ),
+                ("smt_allowed", SMT_ALLOWED_BIT, requirement.smt_allowed),
+                (
+                    "migrate_ma_allowed",
+                    MIGRATE_MA_ALLOWED_BIT,
+                    requirement.migrate_ma_allowed,
+                ),
+            ] {
+                let actual = bits & bit != 0;
+                if required.is_some_and(|required| required != actual) {
+                    return Err(format!("guest policy has {} = {}", name, actual));
+                }
+            }
+        }
+
+        if let Some(vmpl) = self.vmpl {
+            if report.vmpl != vmpl {
+                return Err(format!(
+                    "report is for VMPL {}, but VMPL {} is required",
+                    report.vmpl, vmpl
+                ));
+            }
+        }
+        if let Some(min_guest_svn) = self.min_guest_svn {
+            if report.guest_svn < min_guest_svn {
+                return Err(format!(
+                    "guest SVN {} is below the minimum {}",
+                    report.guest_svn, min_guest_svn
+                ));
+            }
+        }
+        if !self.id_key_digests.is_empty()
+            && !self.id_key_digests.contains(&Hex(report.id_key_digest))
+        {
+            return Err(format!(
+                "id_key_digest {} is not allowed",
+                hex(&report.id_key_digest)
+            ));
+        }
+        if !self.author_key_digests.is_empty()
+            && !self
+                .author_key_digests
+                .contains(&Hex(report.author_key_digest))
+        {
+            return Err(format!(
+                "author_key_digest {} is not allowed",
+                hex(&report.author_key_digest)
+            ));
+        }
+        if let Some(family_id) = self.family_id {
+            if family_id.0 != report.family_id {
+                return Err(format!(
+                    "family_id {} does not match",
+                    hex(&report.family_id)
+                ));
+            }
+        }
+        if let Some(image_id) = self.image_id {
+            if image_id.0 != report.image_id {
+                return Err(format!("image_id {} does not match", hex(&report.image_id)));
+            }
+        }
+        Ok(label)
+    }
+}
+
+struct LoadedPolicy {
+    modified: Option<SystemTime>,
+    policy: Arc<AppraisalPolicy>,
+}
+
+/// Appraises attestation reports against `--appraisal-policy`, reloading the
+/// file when its modification time changes.
+pub struct Appraiser {
+    path: PathBuf,
+    loaded: Mutex<LoadedPolicy>,
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+fn load(path: &Path) -> anyhow::Result<AppraisalPolicy> {
+    let text = std::fs::read_to_string(path).map_err(|e| {
+        anyhow::anyhow!("failed to read appraisal policy {}: {}", path.display(), e)
+    })?;
+    AppraisalPolicy::from_toml(&text)
+        .map_err(|e| anyhow::anyhow!("invalid appraisal policy {}: {}", path.display(), e))
+}
+
+impl Appraiser {
+    /// Loads `--appraisal-policy`, or returns `None` if only the signature,
+    /// the challenge and `--server-ld` are checked.
+    pub fn from_cmd_opts(cmd_opts: &CmdOptions) -> anyhow::Result<Option<Self>> {
+        let Some(path) = &cmd_opts.appraisal_policy else {
+            return Ok(None);
+        };
+        let path = PathBuf::from(path);
+        let modified = modified(&path);
+        let policy = load(&path)?;
+        Ok(Some(Appraiser {
+            path,
+            loaded: Mutex::new(LoadedPolicy {
+                modified,
+                policy: Arc::new(policy),
+            }),
+        }))
+    }
+
+    /// Returns the current policy. A policy file that fails to load is
+    /// logged and the previous policy is kept until the file changes again.
+    fn policy(&self) -> Arc<AppraisalPolicy> {
+        let mut loaded = self.loaded.lock().unwrap();
+        let modified = modified(&self.path);
+        if modified != loaded.modified {
+            loaded.modified = modified;
+            match load(&self.path) {
+                Ok(policy) => {
+                    info!("reloaded appraisal policy {}", self.path.display());
+                    loaded.policy = Arc::new(policy);
+                }
+                Err(e) => error!("{:#}; keeping the previous policy", e),
+            }
+        }
+        loaded.policy.clone()
+    }
+
+    pub fn appraise(&self, report: &AttestationReport) -> Result<Option<String>, String> {
+        self.policy().appraise(report)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{AppraisalPolicy, DEBUG_ALLOWED_BIT, SMT_ALLOWED_BIT};
+    use sev::firmware::guest::{AttestationReport, GuestPolicy};
+
+    #[test]
+    fn reports_are_appraised_against_every_requirement() {
+        let policy = AppraisalPolicy::from_toml(&format!(
+            r#"
+            vmpl = 0
+            min_guest_svn = 2
+            family_id = "{family_id}"
+
+            [[measurement]]
+            label = "old"
+            value = "{old}"
+
+            [[measurement]]
+            label = "current"
+            value = "{current}"
+
+            [min_reported_tcb]
+            snp = 8
+            microcode = 115
+
+            [guest_policy]
+            debug_allowed = false
+            smt_allowed = true
+            "#,
+            family_id = "01".repeat(16),
+            old = "aa".repeat(48),
+            current = "bb".repeat(48),
+        ))
+        .unwrap();
+
+        let report = || {
+            let mut report = AttestationReport::default();
+            report.measurement = [0xbb; 48];
+            report.reported_tcb.snp = 8;
+            report.reported_tcb.microcode = 115;
+            report.policy = GuestPolicy(SMT_ALLOWED_BIT);
+            report.guest_svn = 2;
+            report.family_id = [1; 16];
+            report
+        };
+        assert_eq!(policy.appraise(&report()), Ok(Some("current".to_string())));
+
+        let mut rejected = report();
+        rejected.measurement = [0xcc; 48];
+        assert!(policy
+            .appraise(&rejected)
+            .unwrap_err()
+            .contains("allowlist"));
+        let mut rejected = report();
+        rejected.reported_tcb.snp = 7;
+        assert!(policy
+            .appraise(&rejected)
+            .unwrap_err()
+            .contains("reported_tcb.snp"));
+        let mut rejected = report();
+        rejected.policy = GuestPolicy(SMT_ALLOWED_BIT | DEBUG_ALLOWED_BIT);
+        assert!(policy
+            .appraise(&rejected)
+            .unwrap_err()
+            .contains("debug_allowed"));
+        let mut rejected = report();
+        rejected.vmpl = 1;
+        assert!(policy.appraise(&rejected).unwrap_err().contains("VMPL"));
+        let mut rejected = report();
+        rejected.guest_svn = 1;
+        assert!(policy.appraise(&rejected).unwrap_err().contains("SVN"));
+        let mut rejected = report();
+        rejected.family_id = [2; 16];
+        assert!(policy
+            .appraise(&rejected)
+            .unwrap_err()
+            .contains("family_id"));
+
+        assert_eq!(AppraisalPolicy::default().appraise(&rejected), Ok(None));
+        assert!(
+            AppraisalPolicy::from_toml("[[measurement]]\nlabel = \"x\"\nvalue = \"abc\"").is_err()
+        );
+    }
+}
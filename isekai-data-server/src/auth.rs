@@ -1,7 +1,83 @@
 // SPDX-FileCopyrightText: 2025 SEERA Networks Corporation <info@seera-networks.com>
 // SPDX-License-Identifier: MIT
 
-use crate::CmdOptions;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::Instant;
+use tonic::server::NamedService;
+use tonic::transport::server::{TcpConnectInfo, TlsConnectInfo};
+use tonic::Status;
+use tower::{Layer, Service};
+use tracing::{debug, error, warn};
+
+use crate::jwt::{Claims, JwtValidator};
+use crate::{CmdOptions, ValidTokenStore};
+
+/// The one RPC served without authentication, as it issues the handshake
+/// token every other RPC needs.
+const HANDSHAKE_PATH: &str = "/arrow.flight.protocol.FlightService/Handshake";
+
+/// An authenticated caller.
+#[derive(Clone, Debug, Default)]
+pub struct Subject {
+    /// The `sub` claim of the JWT, with `|` replaced by `_`.
+    pub name: String,
+    /// The `groups` claim of the JWT.
+    pub groups: Vec<String>,
+    /// The claims of the JWT, or `None` for the test subject.
+    pub claims: Option<Claims>,
+    /// The measurement of the attestation report verified by the handshake
+    /// that issued the token of the request.
+    pub measurement: Option<[u8; 48]>,
+    /// The chip ID of that attestation report.
+    pub chip_id: Option<[u8; 64]>,
+}
+
+impl Subject {
+    pub fn new(name: &str) -> Self {
+        Subject {
+            name: name.to_string(),
+            ..Default::default()
+        }
+    }
+
+    /// The measurement in hex for logging, or `none`.
+    pub fn measurement_hex(&self) -> String {
+        self.measurement
+            .map(|measurement| hex(&measurement))
+            .unwrap_or_else(|| "none".to_string())
+    }
+}
+
+/// What a handshake token was issued for. Requests with the token must come
+/// over a connection with the same TLS client certificate, and carry the
+/// subject of the first request that used it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TokenBinding {
+    pub measurement: [u8; 48],
+    pub chip_id: [u8; 64],
+    /// The SHA-256 fingerprint of the TLS client certificate, or `None`
+    /// without TLS.
+    pub client_cert: Option<String>,
+    /// The subject of the first request with the token.
+    pub subject: Option<String>,
+}
+
+pub fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|x| format!("{:02x}", x)).collect()
+}
+
+/// Returns the SHA-256 fingerprint of the TLS client certificate of the
+/// connection a request came over.
+pub fn client_cert_fingerprint(extensions: &http::Extensions) -> Option<String> {
+    let certs = extensions
+        .get::<TlsConnectInfo<TcpConnectInfo>>()?
+        .peer_certs()?;
+    let cert = certs.first()?;
+    Some(hex(&openssl::sha::sha256(cert)))
+}
 
 pub fn authenticate_subject(cmd_opts: &CmdOptions, subject: &str) -> bool {
     if let Some(authorized_subject) = &cmd_opts.authorized_subject {
@@ -10,3 +86,298 @@ pub fn authenticate_subject(cmd_opts: &CmdOptions, subject: &str) -> bool {
     // If no authorized subject is specified, allow all.
     return true;
 }
+
+fn bearer_token(headers: &http::HeaderMap, key: &str) -> Result<String, Status> {
+    headers
+        .get(key)
+        .ok_or_else(|| Status::unauthenticated("No token"))
+        .and_then(|value| {
+            if value.len() >= 7 {
+                let value = value
+                    .to_str()
+                    .map_err(|_| Status::unauthenticated("invalid char"))?;
+                if &value[0..7] == "Bearer " {
+                    Ok(value[7..].to_string())
+                } else {
+                    Err(Status::unauthenticated("Invalid format"))
+                }
+            } else {
+                Err(Status::unauthenticated("too short"))
+            }
+        })
+}
+
+/// Resolves the subject of a request from its handshake token and JWT.
+pub struct Authenticator {
+    cmd_opts: CmdOptions,
+    jwt: Arc<JwtValidator>,
+    valid_tokens: Arc<Mutex<ValidTokenStore>>,
+}
+
+impl Authenticator {
+    pub fn new(
+        cmd_opts: CmdOptions,
+        jwt: Arc<JwtValidator>,
+        valid_tokens: Arc<Mutex<ValidTokenStore>>,
+    ) -> Self {
+        Authenticator {
+            cmd_opts,
+            jwt,
+            valid_tokens,
+        }
+    }
+
+    /// Checks the handshake token and the JWT of a request against each
+    /// other and the TLS client certificate, and returns the authorized
+    /// subject.
+    pub async fn authenticate(
+        &self,
+        headers: &http::HeaderMap,
+        client_cert: Option<&str>,
+    ) -> Result<Subject, Status> {
+        let token = bearer_token(headers, "x-yak-authorization")?;
+        let Some(binding) = self
+            .valid_tokens
+            .lock()
+            .unwrap()
+            .get(&token, Instant::now())
+        else {
+            error!("Invalid token");
+            return Err(Status::unauthenticated("Invalid token"));
+        };
+        if binding.client_cert.as_deref() != client_cert {
+            warn!(
+                "token of measurement {} used with another client certificate",
+                hex(&binding.measurement)
+            );
+            return Err(Status::unauthenticated(
+                "token was issued to another client certificate",
+            ));
+        }
+
+        let jwt = bearer_token(headers, "authorization");
+        let mut subject = if let Ok(jwt) = jwt {
+            let claims = self.jwt.validate(&jwt).await?;
+            Subject {
+                name: claims.sub.replace("|", "_"),
+                groups: claims.groups.clone(),
+                claims: Some(claims),
+                ..Default::default()
+            }
+        } else if self.cmd_opts.allow_test_subject {
+            Subject::new("test")
+        } else {
+            return Err(Status::unauthenticated("No JWT provided"));
+        };
+        subject.measurement = Some(binding.measurement);
+        subject.chip_id = Some(binding.chip_id);
+
+        if !authenticate_subject(&self.cmd_opts, &subject.name) {
+            return Err(Status::unauthenticated(format!(
+                "Unauthorized subject: {}",
+                subject.name
+            )));
+        }
+        if !self
+            .valid_tokens
+            .lock()
+            .unwrap()
+            .bind_subject(&token, &subject.name)
+        {
+            warn!(
+                "token of measurement {} used by another subject: {}",
+                hex(&binding.measurement),
+                subject.name
+            );
+            return Err(Status::unauthenticated(
+                "token was issued to another subject",
+            ));
+        }
+        debug!(
+            "authenticated subject: {}, measurement: {}",
+            subject.name,
+            hex(&binding.measurement)
+        );
+        Ok(subject)
+    }
+}
+
+/// Returns the subject `AuthLayer` put in the extensions of a request.
+pub fn subject<T>(request: &tonic::Request<T>) -> Result<Subject, Status> {
+    request
+        .extensions()
+        .get::<Subject>()
+        .cloned()
+        .ok_or_else(|| Status::unauthenticated("request is not authenticated"))
+}
+
+/// Authenticates every RPC except the handshake before it reaches the
+/// service, rejecting it with `unauthenticated` or putting its `Subject` in
+/// the request extensions.
+#[derive(Clone)]
+pub struct AuthLayer {
+    authenticator: Arc<Authenticator>,
+}
+
+impl AuthLayer {
+    pub fn new(authenticator: Authenticator) -> Self {
+        AuthLayer {
+            authenticator: Arc::new(authenticator),
+        }
+    }
+}
+
+impl<S> Layer<S> for AuthLayer {
+    type Service = AuthService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        AuthService {
+            inner,
+            authenticator: self.authenticator.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct AuthService<S> {
+    inner: S,
+    authenticator: Arc<Authenticator>,
+}
+
+impl<S: NamedService> NamedService for AuthService<S> {
+    const NAME: &'static str = S::NAME;
+}
+
+impl<S, ReqBody, ResBody> Service<http::Request<ReqBody>> for AuthService<S>
+where
+    S: Service<http::Request<ReqBody>, Response = http::Response<ResBody>> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+    ReqBody: Send + 'static,
+    ResBody: Default,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut request: http::Request<ReqBody>) -> Self::Future {
+        // The clone is not ready yet, so the ready service is taken.
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+        let authenticator = self.authenticator.clone();
+        Box::pin(async move {
+            if request.uri().path() != HANDSHAKE_PATH {
+                let headers = request.headers().clone();
+                let client_cert = client_cert_fingerprint(request.extensions());
+                match authenticator
+                    .authenticate(&headers, client_cert.as_deref())
+                    .await
+                {
+                    Ok(subject) => {
+                        request.extensions_mut().insert(subject);
+                    }
+                    Err(status) => return Ok(status.into_http()),
+                }
+            }
+            inner.call(request).await
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{subject, AuthLayer, Authenticator, TokenBinding, HANDSHAKE_PATH};
+    use crate::jwt::JwtValidator;
+    use crate::{CmdOptions, ValidTokenStore};
+    use std::future::{ready, Ready};
+    use std::sync::{Arc, Mutex};
+    use std::task::{Context, Poll};
+    use std::time::Instant;
+    use tower::{Layer, Service};
+
+    /// Answers with the name of the subject the layer resolved, if any.
+    #[derive(Clone)]
+    struct Echo;
+
+    impl Service<http::Request<()>> for Echo {
+        type Response = http::Response<String>;
+        type Error = std::convert::Infallible;
+        type Future = Ready<Result<Self::Response, Self::Error>>;
+
+        fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, request: http::Request<()>) -> Self::Future {
+            let request = tonic::Request::from_http(request);
+            let name = subject(&request)
+                .map(|subject| format!("{}:{:?}", subject.name, subject.measurement))
+                .unwrap_or_default();
+            ready(Ok(http::Response::new(name)))
+        }
+    }
+
+    fn request(path: &str, token: Option<&str>) -> http::Request<()> {
+        let mut builder = http::Request::builder().uri(path);
+        if let Some(token) = token {
+            builder = builder.header("x-yak-authorization", format!("Bearer {}", token));
+        }
+        builder.body(()).unwrap()
+    }
+
+    #[test]
+    fn every_rpc_but_the_handshake_needs_a_valid_token() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let jwks_path = temp_dir.path().join("jwks.json");
+        std::fs::write(&jwks_path, r#"{"keys":[]}"#).unwrap();
+        let cmd_opts = test_cmd_opts(jwks_path.to_str().unwrap());
+        let valid_tokens = Arc::new(Mutex::new(ValidTokenStore::default()));
+        let binding = TokenBinding {
+            measurement: [7; 48],
+            chip_id: [8; 64],
+            client_cert: None,
+            subject: None,
+        };
+        let other_client = TokenBinding {
+            client_cert: Some("00".repeat(32)),
+            ..binding.clone()
+        };
+        let mut store = valid_tokens.lock().unwrap();
+        store.insert("token".to_string(), binding, Instant::now());
+        store.insert("other-client".to_string(), other_client, Instant::now());
+        drop(store);
+
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        runtime.block_on(async {
+            let jwt = Arc::new(JwtValidator::from_cmd_opts(&cmd_opts).await.unwrap());
+            let authenticator = Authenticator::new(cmd_opts.clone(), jwt, valid_tokens);
+            let mut service = AuthLayer::new(authenticator).layer(Echo);
+            let do_get = "/arrow.flight.protocol.FlightService/DoGet";
+
+            let response = service.call(request(HANDSHAKE_PATH, None)).await.unwrap();
+            assert_eq!(response.into_body(), "");
+
+            for token in [None, Some("unknown"), Some("other-client")] {
+                let response = service.call(request(do_get, token)).await.unwrap();
+                assert_eq!(response.headers()["grpc-status"], "16");
+                assert_eq!(response.into_body(), "");
+            }
+
+            let response = service.call(request(do_get, Some("token"))).await.unwrap();
+            assert_eq!(response.into_body(), format!("test:{:?}", Some([7u8; 48])));
+        });
+    }
+
+    fn test_cmd_opts(jwks: &str) -> CmdOptions {
+        CmdOptions {
+            jwks: jwks.to_string(),
+            ..crate::test_cmd_opts()
+        }
+    }
+}
@@ -0,0 +1,717 @@
+// SPDX-FileCopyrightText: 2025 SEERA Networks Corporation <info@seera-networks.com>
+// SPDX-License-Identifier: MIT
+
+//! Envelope encryption of the data kept at rest. Stored values and Parquet
+//! files are encrypted with AES-256-GCM under data keys, and the data keys are
+//! wrapped by a master key loaded from `--master-key-file` or derived by the
+//! SEV-SNP firmware with `--derived-master-key`. Files are encrypted in
+//! chunks, so that they are written and read without being held in memory.
+
+use anyhow::Context;
+use base64::Engine;
+use bytes::Bytes;
+use openssl::symm::{decrypt_aead, encrypt_aead, Cipher};
+use parquet::file::reader::{ChunkReader, Length};
+use rand::rngs::OsRng;
+use rand::RngCore;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::os::unix::fs::FileExt;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, OnceLock};
+
+use crate::CmdOptions;
+
+const KEY_LEN: usize = 32;
+const NONCE_LEN: usize = 12;
+const TAG_LEN: usize = 16;
+const KEY_ID_LEN: usize = 8;
+const WRAPPED_KEY_LEN: usize = NONCE_LEN + KEY_LEN + TAG_LEN;
+
+/// The associated data of a wrapped data key.
+const WRAP_AAD: &[u8] = b"isekai-data-key";
+
+/// The first bytes of a sealed file, followed by the master key ID, the
+/// wrapped data key and the contents in chunks of `CHUNK_LEN` bytes, each
+/// encrypted with its own tag.
+const FILE_MAGIC: &[u8; 8] = b"ISEKAIE1";
+const FILE_HEADER_LEN: usize = FILE_MAGIC.len() + KEY_ID_LEN + WRAPPED_KEY_LEN;
+
+/// The plaintext length of every chunk of a sealed file but the last.
+const CHUNK_LEN: usize = 64 * 1024;
+const SEALED_CHUNK_LEN: usize = CHUNK_LEN + TAG_LEN;
+
+/// Encrypts `plaintext` as `nonce || ciphertext || tag`.
+fn seal(key: &[u8; KEY_LEN], aad: &[u8], plaintext: &[u8]) -> anyhow::Result<Vec<u8>> {
+    let mut nonce = [0u8; NONCE_LEN];
+    OsRng.fill_bytes(&mut nonce);
+    let mut tag = [0u8; TAG_LEN];
+    let ciphertext = encrypt_aead(
+        Cipher::aes_256_gcm(),
+        key,
+        Some(&nonce[..]),
+        aad,
+        plaintext,
+        &mut tag,
+    )?;
+    let mut sealed = Vec::with_capacity(NONCE_LEN + ciphertext.len() + TAG_LEN);
+    sealed.extend_from_slice(&nonce);
+    sealed.extend_from_slice(&ciphertext);
+    sealed.extend_from_slice(&tag);
+    Ok(sealed)
+}
+
+/// Decrypts the output of `seal`, failing if it was modified or `aad`
+/// differs.
+fn open(key: &[u8; KEY_LEN], aad: &[u8], sealed: &[u8]) -> anyhow::Result<Vec<u8>> {
+    if sealed.len() < NONCE_LEN + TAG_LEN {
+        return Err(anyhow::anyhow!("encrypted value is truncated"));
+    }
+    let (nonce, rest) = sealed.split_at(NONCE_LEN);
+    let (ciphertext, tag) = rest.split_at(rest.len() - TAG_LEN);
+    decrypt_aead(
+        Cipher::aes_256_gcm(),
+        key,
+        Some(nonce),
+        aad,
+        ciphertext,
+        tag,
+    )
+    .map_err(|_| anyhow::anyhow!("failed to decrypt: wrong key or corrupted data"))
+}
+
+/// The key that wraps every data key.
+pub struct MasterKey {
+    id: [u8; KEY_ID_LEN],
+    key: [u8; KEY_LEN],
+}
+
+impl MasterKey {
+    /// Accepts the 32 raw bytes written by `snpguest key`, or the key in
+    /// base64.
+    pub fn from_bytes(bytes: &[u8]) -> anyhow::Result<Self> {
+        let key: [u8; KEY_LEN] = match bytes.try_into() {
+            Ok(key) => key,
+            Err(_) => base64::engine::general_purpose::STANDARD
+                .decode(String::from_utf8_lossy(bytes).trim())
+                .ok()
+                .and_then(|key| key.try_into().ok())
+                .ok_or_else(|| anyhow::anyhow!("master key must be 32 bytes, raw or in base64"))?,
+        };
+        let digest = Sha256::digest(key);
+        let mut id = [0u8; KEY_ID_LEN];
+        id.copy_from_slice(&digest[..KEY_ID_LEN]);
+        Ok(MasterKey { id, key })
+    }
+
+    pub fn load(path: &str) -> anyhow::Result<Self> {
+        let bytes =
+            std::fs::read(path).with_context(|| format!("failed to read master key {}", path))?;
+        Self::from_bytes(&bytes)
+    }
+
+    /// Returns the ID recorded next to the data keys wrapped by this key.
+    pub fn id(&self) -> String {
+        self.id.iter().map(|byte| format!("{byte:02x}")).collect()
+    }
+
+    pub fn wrap(&self, data_key: &DataKey) -> anyhow::Result<Vec<u8>> {
+        seal(&self.key, WRAP_AAD, &data_key.0)
+    }
+
+    pub fn unwrap(&self, wrapped: &[u8]) -> anyhow::Result<DataKey> {
+        let key = open(&self.key, WRAP_AAD, wrapped).context("failed to unwrap data key")?;
+        let key = key
+            .try_into()
+            .map_err(|_| anyhow::anyhow!("wrapped data key has a wrong length"))?;
+        Ok(DataKey(key))
+    }
+}
+
+/// A key that encrypts the values of one target or the contents of one file.
+pub struct DataKey([u8; KEY_LEN]);
+
+impl DataKey {
+    pub fn generate() -> Self {
+        let mut key = [0u8; KEY_LEN];
+        OsRng.fill_bytes(&mut key);
+        DataKey(key)
+    }
+
+    /// Encrypts a value bound to `aad`, which must be passed again to
+    /// `decrypt`.
+    pub fn encrypt(&self, aad: &[u8], plaintext: &[u8]) -> anyhow::Result<Vec<u8>> {
+        seal(&self.0, aad, plaintext)
+    }
+
+    pub fn decrypt(&self, aad: &[u8], ciphertext: &[u8]) -> anyhow::Result<Vec<u8>> {
+        open(&self.0, aad, ciphertext)
+    }
+
+    /// Encrypts chunk `index` of a file as `ciphertext || tag`.
+    fn seal_chunk(
+        &self,
+        aad: &[u8],
+        index: u64,
+        last: bool,
+        plaintext: &[u8],
+    ) -> anyhow::Result<Vec<u8>> {
+        let mut tag = [0u8; TAG_LEN];
+        let mut sealed = encrypt_aead(
+            Cipher::aes_256_gcm(),
+            &self.0,
+            Some(&chunk_nonce(index, last)),
+            aad,
+            plaintext,
+            &mut tag,
+        )?;
+        sealed.extend_from_slice(&tag);
+        Ok(sealed)
+    }
+
+    fn open_chunk(
+        &self,
+        aad: &[u8],
+        index: u64,
+        last: bool,
+        sealed: &[u8],
+    ) -> anyhow::Result<Vec<u8>> {
+        let (ciphertext, tag) = sealed.split_at(sealed.len() - TAG_LEN);
+        decrypt_aead(
+            Cipher::aes_256_gcm(),
+            &self.0,
+            Some(&chunk_nonce(index, last)),
+            aad,
+            ciphertext,
+            tag,
+        )
+        .map_err(|_| {
+            anyhow::anyhow!("failed to decrypt: wrong key, corrupted, truncated or moved file")
+        })
+    }
+}
+
+/// Returns the nonce of chunk `index` of a file. Every file has its own data
+/// key, so a counter is enough, and the last chunk is flagged so that a file
+/// cut at a chunk boundary does not decrypt.
+fn chunk_nonce(index: u64, last: bool) -> [u8; NONCE_LEN] {
+    let mut nonce = [0u8; NONCE_LEN];
+    nonce[..8].copy_from_slice(&index.to_be_bytes());
+    nonce[NONCE_LEN - 1] = last as u8;
+    nonce
+}
+
+/// Returns the associated data of the chunks of the file at `path`: its path
+/// under `--parquet-path`, so that a sealed file does not decrypt when it is
+/// moved over the file of another target or column.
+fn file_aad(cmd_opts: &CmdOptions, path: &Path) -> Vec<u8> {
+    let relative = path.strip_prefix(&cmd_opts.parquet_path).unwrap_or(path);
+    let mut aad = FILE_MAGIC.to_vec();
+    aad.extend_from_slice(relative.to_string_lossy().as_bytes());
+    aad
+}
+
+/// Returns the master key configured by `cmd_opts`, or `None` if data is kept
+/// in plaintext. Keys are loaded once and cached, as a derived key takes a
+/// request to the firmware.
+pub fn master_key(cmd_opts: &CmdOptions) -> anyhow::Result<Option<Arc<MasterKey>>> {
+    static MASTER_KEYS: OnceLock<Mutex<HashMap<String, Arc<MasterKey>>>> = OnceLock::new();
+
+    let source = match (&cmd_opts.master_key_file, cmd_opts.derived_master_key) {
+        (Some(_), true) => {
+            return Err(anyhow::anyhow!(
+                "--master-key-file and --derived-master-key cannot be used together"
+            ))
+        }
+        (Some(path), false) => format!("file:{}", path),
+        (None, true) => format!(
+            "derived:{}",
+            cmd_opts.derived_key_fields.as_deref().unwrap_or("000000")
+        ),
+        (None, false) => return Ok(None),
+    };
+    let mut keys = MASTER_KEYS.get_or_init(Default::default).lock().unwrap();
+    if let Some(key) = keys.get(&source) {
+        return Ok(Some(key.clone()));
+    }
+    let key = match &cmd_opts.master_key_file {
+        Some(path) => MasterKey::load(path)?,
+        None => MasterKey::from_bytes(&derive_key(cmd_opts)?)?,
+    };
+    let key = Arc::new(key);
+    keys.insert(source, key.clone());
+    Ok(Some(key))
+}
+
+/// Requests a key derived from the VCEK, mixing in the guest fields selected
+/// by `--derived-key-fields`.
+fn derive_key(cmd_opts: &CmdOptions) -> anyhow::Result<[u8; KEY_LEN]> {
+    let fields = cmd_opts.derived_key_fields.as_deref().unwrap_or("000000");
+    let gfs = u64::from_str_radix(fields, 2)
+        .ok()
+        .filter(|gfs| fields.len() == 6 && *gfs <= 63)
+        .ok_or_else(|| anyhow::anyhow!("--derived-key-fields must be 6 binary digits"))?;
+    snpguest::key::request_derived_key(false, gfs, 1, 0, 0)
+}
+
+/// Returns the master key ID and the wrapped data key of a sealed file, or
+/// `None` if the file is in plaintext.
+fn read_header(file: &File) -> anyhow::Result<Option<([u8; KEY_ID_LEN], Vec<u8>)>> {
+    let mut magic = [0u8; FILE_MAGIC.len()];
+    match file.read_exact_at(&mut magic, 0) {
+        Ok(()) if &magic == FILE_MAGIC => {}
+        Ok(()) => return Ok(None),
+        Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e.into()),
+    }
+    let mut header = [0u8; FILE_HEADER_LEN - FILE_MAGIC.len()];
+    file.read_exact_at(&mut header, FILE_MAGIC.len() as u64)
+        .context("sealed file is truncated")?;
+    let (key_id, wrapped) = header.split_at(KEY_ID_LEN);
+    Ok(Some((key_id.try_into().unwrap(), wrapped.to_vec())))
+}
+
+/// Creates a temporary file next to `path` to be persisted over it.
+fn temp_file_for(path: &Path) -> anyhow::Result<tempfile::NamedTempFile> {
+    let dir = path
+        .parent()
+        .filter(|dir| !dir.as_os_str().is_empty())
+        .unwrap_or(Path::new("."));
+    tempfile::NamedTempFile::new_in(dir)
+        .with_context(|| format!("failed to create {}", path.display()))
+}
+
+struct Sealing {
+    data_key: DataKey,
+    aad: Vec<u8>,
+    /// The plaintext of the chunk being written, sealed when full and more
+    /// data arrives, or on `finish` as the last chunk.
+    chunk: Vec<u8>,
+    index: u64,
+}
+
+/// Writes a file kept at rest: as is without a master key, and sealed under
+/// a new data key otherwise. The file is written under a temporary name and
+/// only appears at its path, complete, on `finish`.
+pub struct FileWriter {
+    file: tempfile::NamedTempFile,
+    path: PathBuf,
+    sealing: Option<Sealing>,
+}
+
+impl FileWriter {
+    pub fn create(cmd_opts: &CmdOptions, path: &Path) -> anyhow::Result<Self> {
+        let master_key = master_key(cmd_opts)?;
+        Self::new(path, file_aad(cmd_opts, path), master_key.as_deref())
+    }
+
+    fn new(path: &Path, aad: Vec<u8>, master_key: Option<&MasterKey>) -> anyhow::Result<Self> {
+        let mut file = temp_file_for(path)?;
+        let sealing = match master_key {
+            Some(master_key) => {
+                let data_key = DataKey::generate();
+                file.write_all(FILE_MAGIC)?;
+                file.write_all(&master_key.id)?;
+                file.write_all(&master_key.wrap(&data_key)?)?;
+                Some(Sealing {
+                    data_key,
+                    aad,
+                    chunk: Vec::with_capacity(CHUNK_LEN),
+                    index: 0,
+                })
+            }
+            None => None,
+        };
+        Ok(FileWriter {
+            file,
+            path: path.to_path_buf(),
+            sealing,
+        })
+    }
+
+    /// Seals the last chunk and moves the file to its path. The file is
+    /// discarded if this is not called.
+    pub fn finish(mut self) -> anyhow::Result<()> {
+        if let Some(sealing) = &self.sealing {
+            let sealed =
+                sealing
+                    .data_key
+                    .seal_chunk(&sealing.aad, sealing.index, true, &sealing.chunk)?;
+            self.file.write_all(&sealed)?;
+        }
+        self.file.flush()?;
+        self.file
+            .persist(&self.path)
+            .map_err(|e| e.error)
+            .with_context(|| format!("failed to write {}", self.path.display()))?;
+        Ok(())
+    }
+}
+
+impl Write for FileWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let Some(sealing) = &mut self.sealing else {
+            return self.file.write(buf);
+        };
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        if sealing.chunk.len() == CHUNK_LEN {
+            let sealed = sealing
+                .data_key
+                .seal_chunk(&sealing.aad, sealing.index, false, &sealing.chunk)
+                .map_err(std::io::Error::other)?;
+            self.file.write_all(&sealed)?;
+            sealing.chunk.clear();
+            sealing.index += 1;
+        }
+        let len = buf.len().min(CHUNK_LEN - sealing.chunk.len());
+        sealing.chunk.extend_from_slice(&buf[..len]);
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.file.flush()
+    }
+}
+
+/// The contents of a sealed file, decrypted a chunk at a time as they are
+/// read.
+pub struct SealedFile {
+    file: File,
+    data_key: DataKey,
+    aad: Vec<u8>,
+    file_len: u64,
+    chunks: u64,
+    len: u64,
+}
+
+impl SealedFile {
+    fn open(file: File, master_key: &MasterKey, aad: Vec<u8>) -> anyhow::Result<Self> {
+        let (key_id, wrapped) = read_header(&file)?.context("file is not sealed")?;
+        if key_id != master_key.id {
+            return Err(anyhow::anyhow!("file is encrypted with another master key"));
+        }
+        let data_key = master_key.unwrap(&wrapped)?;
+        let file_len = file.metadata()?.len();
+        let body_len = file_len - FILE_HEADER_LEN as u64;
+        let chunks = body_len.div_ceil(SEALED_CHUNK_LEN as u64);
+        if chunks == 0 || body_len - (chunks - 1) * (SEALED_CHUNK_LEN as u64) < TAG_LEN as u64 {
+            return Err(anyhow::anyhow!("sealed file is truncated"));
+        }
+        Ok(SealedFile {
+            file,
+            data_key,
+            aad,
+            file_len,
+            chunks,
+            len: body_len - chunks * TAG_LEN as u64,
+        })
+    }
+
+    /// Reads and decrypts chunk `index`.
+    fn chunk(&self, index: u64) -> anyhow::Result<Vec<u8>> {
+        let offset = FILE_HEADER_LEN as u64 + index * SEALED_CHUNK_LEN as u64;
+        let last = index + 1 == self.chunks;
+        let mut sealed = vec![0u8; (self.file_len - offset).min(SEALED_CHUNK_LEN as u64) as usize];
+        self.file.read_exact_at(&mut sealed, offset)?;
+        self.data_key.open_chunk(&self.aad, index, last, &sealed)
+    }
+}
+
+/// Reads a `SealedFile` from a position, keeping the current chunk.
+struct SealedReader {
+    file: Arc<SealedFile>,
+    pos: u64,
+    chunk: Option<(u64, Vec<u8>)>,
+}
+
+impl Read for SealedReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.pos >= self.file.len || buf.is_empty() {
+            return Ok(0);
+        }
+        let index = self.pos / CHUNK_LEN as u64;
+        if self
+            .chunk
+            .as_ref()
+            .is_none_or(|(loaded, _)| *loaded != index)
+        {
+            let chunk = self.file.chunk(index).map_err(std::io::Error::other)?;
+            self.chunk = Some((index, chunk));
+        }
+        let chunk = &self.chunk.as_ref().unwrap().1;
+        let offset = (self.pos % CHUNK_LEN as u64) as usize;
+        let len = buf.len().min(chunk.len() - offset);
+        buf[..len].copy_from_slice(&chunk[offset..offset + len]);
+        self.pos += len as u64;
+        Ok(len)
+    }
+}
+
+/// A Parquet file kept at rest, read directly if it is in plaintext and
+/// decrypted a chunk at a time if it is sealed.
+pub enum StoredFile {
+    Plain(File),
+    Sealed(Arc<SealedFile>),
+}
+
+impl StoredFile {
+    pub fn open(cmd_opts: &CmdOptions, path: &Path) -> anyhow::Result<Self> {
+        let file =
+            File::open(path).with_context(|| format!("failed to open {}", path.display()))?;
+        if read_header(&file)?.is_none() {
+            return Ok(StoredFile::Plain(file));
+        }
+        let master_key = master_key(cmd_opts)?.ok_or_else(|| {
+            anyhow::anyhow!(
+                "{} is encrypted but no master key is configured",
+                path.display()
+            )
+        })?;
+        let sealed = SealedFile::open(file, &master_key, file_aad(cmd_opts, path))
+            .with_context(|| format!("failed to decrypt {}", path.display()))?;
+        Ok(StoredFile::Sealed(Arc::new(sealed)))
+    }
+}
+
+impl Length for StoredFile {
+    fn len(&self) -> u64 {
+        match self {
+            StoredFile::Plain(file) => Length::len(file),
+            StoredFile::Sealed(file) => file.len,
+        }
+    }
+}
+
+impl ChunkReader for StoredFile {
+    type T = Box<dyn Read + Send>;
+
+    fn get_read(&self, start: u64) -> parquet::errors::Result<Self::T> {
+        Ok(match self {
+            StoredFile::Plain(file) => Box::new(ChunkReader::get_read(file, start)?),
+            StoredFile::Sealed(file) => Box::new(SealedReader {
+                file: file.clone(),
+                pos: start,
+                chunk: None,
+            }),
+        })
+    }
+
+    fn get_bytes(&self, start: u64, length: usize) -> parquet::errors::Result<Bytes> {
+        match self {
+            StoredFile::Plain(file) => ChunkReader::get_bytes(file, start, length),
+            StoredFile::Sealed(_) => {
+                let mut bytes = vec![0u8; length];
+                self.get_read(start)?.read_exact(&mut bytes)?;
+                Ok(Bytes::from(bytes))
+            }
+        }
+    }
+}
+
+/// Returns the Parquet files kept under `--parquet-path`: the EDINET cache
+/// and the files of the Parquet storage backend.
+fn parquet_files(cmd_opts: &CmdOptions) -> anyhow::Result<Vec<PathBuf>> {
+    let mut dirs = vec![PathBuf::from(&cmd_opts.parquet_path)];
+    let mut files = Vec::new();
+    while let Some(dir) = dirs.pop() {
+        let entries = match std::fs::read_dir(&dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(e.into()),
+        };
+        for entry in entries {
+            let entry = entry?;
+            let path = entry.path();
+            if entry.file_type()?.is_dir() {
+                dirs.push(path);
+            } else if path.extension().is_some_and(|ext| ext == "parquet") {
+                files.push(path);
+            }
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// Seals the Parquet files still kept in plaintext and returns how many were
+/// sealed.
+pub fn seal_plaintext_files(
+    cmd_opts: &CmdOptions,
+    master_key: &MasterKey,
+) -> anyhow::Result<usize> {
+    let mut sealed = 0;
+    for path in parquet_files(cmd_opts)? {
+        let mut file = File::open(&path)?;
+        if read_header(&file)?.is_none() {
+            let mut writer = FileWriter::new(&path, file_aad(cmd_opts, &path), Some(master_key))?;
+            std::io::copy(&mut file, &mut writer)?;
+            writer.finish()?;
+            sealed += 1;
+        }
+    }
+    Ok(sealed)
+}
+
+/// Re-wraps the data key of a sealed file under `current` if it is wrapped by
+/// `previous`, copying the encrypted contents as they are. Returns false if
+/// the file was not sealed with `previous`.
+fn rewrap_file(previous: &MasterKey, current: &MasterKey, path: &Path) -> anyhow::Result<bool> {
+    let mut file = File::open(path)?;
+    let Some((key_id, wrapped)) = read_header(&file)? else {
+        return Ok(false);
+    };
+    if key_id != previous.id {
+        return Ok(false);
+    }
+    let wrapped = current.wrap(&previous.unwrap(&wrapped)?)?;
+    let mut rewrapped = temp_file_for(path)?;
+    rewrapped.write_all(FILE_MAGIC)?;
+    rewrapped.write_all(&current.id)?;
+    rewrapped.write_all(&wrapped)?;
+    file.seek(SeekFrom::Start(FILE_HEADER_LEN as u64))?;
+    std::io::copy(&mut file, &mut rewrapped)?;
+    rewrapped
+        .persist(path)
+        .map_err(|e| e.error)
+        .with_context(|| format!("failed to replace {}", path.display()))?;
+    Ok(true)
+}
+
+/// Re-wraps the data keys of the files sealed with `previous` under `current`
+/// and returns how many were re-wrapped.
+pub fn rewrap_files(
+    cmd_opts: &CmdOptions,
+    previous: &MasterKey,
+    current: &MasterKey,
+) -> anyhow::Result<usize> {
+    let mut rewrapped = 0;
+    for path in parquet_files(cmd_opts)? {
+        if rewrap_file(previous, current, &path)? {
+            rewrapped += 1;
+        }
+    }
+    Ok(rewrapped)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        rewrap_file, DataKey, FileWriter, MasterKey, SealedFile, SealedReader, StoredFile,
+        CHUNK_LEN, FILE_HEADER_LEN, SEALED_CHUNK_LEN,
+    };
+    use parquet::file::reader::{ChunkReader, Length};
+    use std::fs::File;
+    use std::io::{Read, Write};
+    use std::path::Path;
+    use std::sync::Arc;
+
+    fn write_sealed(master_key: &MasterKey, path: &Path, aad: &[u8], contents: &[u8]) {
+        let mut writer = FileWriter::new(path, aad.to_vec(), Some(master_key)).unwrap();
+        writer.write_all(contents).unwrap();
+        writer.finish().unwrap();
+    }
+
+    fn read_sealed(master_key: &MasterKey, path: &Path, aad: &[u8]) -> anyhow::Result<Vec<u8>> {
+        let file = SealedFile::open(File::open(path)?, master_key, aad.to_vec())?;
+        let mut contents = Vec::new();
+        SealedReader {
+            file: Arc::new(file),
+            pos: 0,
+            chunk: None,
+        }
+        .read_to_end(&mut contents)?;
+        Ok(contents)
+    }
+
+    #[test]
+    fn sealed_files_survive_a_master_key_rotation() {
+        let previous = MasterKey::from_bytes(&[1u8; 32]).unwrap();
+        let current =
+            MasterKey::from_bytes(b"AgICAgICAgICAgICAgICAgICAgICAgICAgICAgICAgI=\n").unwrap();
+        assert_ne!(previous.id(), current.id());
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("item.parquet");
+        write_sealed(&previous, &path, b"item.parquet", b"PAR1 contents");
+        assert_eq!(
+            read_sealed(&previous, &path, b"item.parquet").unwrap(),
+            b"PAR1 contents"
+        );
+        assert!(read_sealed(&current, &path, b"item.parquet").is_err());
+
+        assert!(!rewrap_file(&current, &previous, &path).unwrap());
+        assert!(rewrap_file(&previous, &current, &path).unwrap());
+        assert_eq!(
+            read_sealed(&current, &path, b"item.parquet").unwrap(),
+            b"PAR1 contents"
+        );
+        assert!(read_sealed(&previous, &path, b"item.parquet").is_err());
+
+        let data_key = DataKey::generate();
+        let value = data_key.encrypt(b"table.column", b"value").unwrap();
+        assert_eq!(data_key.decrypt(b"table.column", &value).unwrap(), b"value");
+        assert!(data_key.decrypt(b"table.other", &value).is_err());
+    }
+
+    #[test]
+    fn sealed_files_are_read_a_chunk_at_a_time() {
+        let master_key = MasterKey::from_bytes(&[1u8; 32]).unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("item.parquet");
+
+        for len in [0, 1, CHUNK_LEN, 2 * CHUNK_LEN + 5] {
+            let contents: Vec<u8> = (0..len).map(|i| (i % 251) as u8).collect();
+            write_sealed(&master_key, &path, b"item.parquet", &contents);
+            assert_eq!(
+                read_sealed(&master_key, &path, b"item.parquet").unwrap(),
+                contents
+            );
+        }
+
+        // Ranges are decrypted from the chunks they cover.
+        let contents: Vec<u8> = (0..2 * CHUNK_LEN + 5).map(|i| (i % 251) as u8).collect();
+        let file = SealedFile::open(
+            File::open(&path).unwrap(),
+            &master_key,
+            b"item.parquet".to_vec(),
+        )
+        .unwrap();
+        let stored = StoredFile::Sealed(Arc::new(file));
+        assert_eq!(stored.len(), contents.len() as u64);
+        let start = CHUNK_LEN - 3;
+        let bytes = stored.get_bytes(start as u64, CHUNK_LEN + 6).unwrap();
+        assert_eq!(&bytes[..], &contents[start..start + CHUNK_LEN + 6]);
+        assert!(stored.get_bytes(contents.len() as u64 - 2, 3).is_err());
+
+        // A file moved over another one does not decrypt.
+        assert!(read_sealed(&master_key, &path, b"other.parquet").is_err());
+
+        // Neither does a file cut at a chunk boundary.
+        let file = std::fs::OpenOptions::new().write(true).open(&path).unwrap();
+        file.set_len((FILE_HEADER_LEN + 2 * SEALED_CHUNK_LEN) as u64)
+            .unwrap();
+        assert!(read_sealed(&master_key, &path, b"item.parquet").is_err());
+    }
+
+    #[test]
+    fn unfinished_files_are_discarded() {
+        let master_key = MasterKey::from_bytes(&[1u8; 32]).unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("item.parquet");
+        write_sealed(&master_key, &path, b"item.parquet", b"PAR1 contents");
+
+        let mut writer =
+            FileWriter::new(&path, b"item.parquet".to_vec(), Some(&master_key)).unwrap();
+        writer.write_all(b"PAR1 other contents").unwrap();
+        drop(writer);
+        assert_eq!(
+            read_sealed(&master_key, &path, b"item.parquet").unwrap(),
+            b"PAR1 contents"
+        );
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+}
@@ -1,164 +1,609 @@
 // SPDX-FileCopyrightText: 2025 SEERA Networks Corporation <info@seera-networks.com>
 // SPDX-License-Identifier: MIT
 
-use arrow::array::{Float32Array, StringArray};
-use arrow::datatypes::{DataType, Field, Schema};
+use anyhow::Context;
+use arrow::array::{
+    ArrayRef, BooleanArray, Date32Array, Float64Array, Int64Array, StringArray,
+    TimestampMicrosecondArray,
+};
+use arrow::datatypes::{DataType, Field, Schema, SchemaRef, TimeUnit};
 use arrow::record_batch::RecordBatch;
+use chrono::{DateTime, NaiveDate, NaiveDateTime};
+use csv::StringRecord;
 use isekai_utils::policy::PolicyFile;
-use std::collections::HashMap;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::Read;
+use std::os::unix::fs::FileExt;
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
-use std::sync::{Arc, LazyLock, Mutex};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
 use tonic::{Result, Status};
-use tracing::info;
+use tracing::{error, info};
 
+use crate::auth::Subject;
+use crate::source::{BatchSink, ColumnInfo, DataSource};
 use crate::CmdOptions;
 
-struct State {
-    data: DataStore,
+/// The modification time and size of a CSV file and its sidecar schemas,
+/// compared on each request to notice that the dataset changed.
+#[derive(Clone, PartialEq, Debug)]
+struct FileStamp {
+    csv: (SystemTime, u64),
+    schemas: Vec<Option<SystemTime>>,
 }
 
-enum Data {
-    Float32Array(Float32Array),
-    StringArray(StringArray),
+impl FileStamp {
+    fn read(path: &str) -> Result<Self> {
+        let metadata =
+            std::fs::metadata(path).map_err(|e| Status::internal(format!("open csv: {:?}", e)))?;
+        let modified = metadata
+            .modified()
+            .map_err(|e| Status::internal(format!("open csv: {:?}", e)))?;
+        let schemas = SCHEMA_EXTENSIONS
+            .iter()
+            .map(|ext| {
+                std::fs::metadata(schema_path(path, ext))
+                    .and_then(|metadata| metadata.modified())
+                    .ok()
+            })
+            .collect();
+        Ok(Self {
+            csv: (modified, metadata.len()),
+            schemas,
+        })
+    }
+}
+
+/// The CSV file a `DataStore` was read from, kept open so that its rows are
+/// still served after a new file is moved over it.
+struct LoadedFile {
+    file: File,
+    /// The modification time and size of the file when it was read.
+    stamp: (SystemTime, u64),
+}
+
+impl LoadedFile {
+    fn open(path: &str) -> Result<Self> {
+        let file = File::open(path).map_err(|e| Status::internal(format!("open csv: {:?}", e)))?;
+        let stamp = Self::read_stamp(&file)?;
+        Ok(Self { file, stamp })
+    }
+
+    fn read_stamp(file: &File) -> Result<(SystemTime, u64)> {
+        let metadata = file
+            .metadata()
+            .map_err(|e| Status::internal(format!("open csv: {:?}", e)))?;
+        let modified = metadata
+            .modified()
+            .map_err(|e| Status::internal(format!("open csv: {:?}", e)))?;
+        Ok((modified, metadata.len()))
+    }
+
+    /// Fails if the file was rewritten in place since it was read.
+    fn check_unchanged(&self, path: &str) -> Result<()> {
+        if Self::read_stamp(&self.file)? != self.stamp {
+            return Err(changed_error(path));
+        }
+        Ok(())
+    }
+}
+
+fn changed_error(path: &str) -> Status {
+    Status::aborted(format!("csv file {} changed while it was read", path))
 }
+
+/// The schema of a CSV dataset. The values themselves are read from the file
+/// on each request, so that large files are never held in memory, and a
+/// request that notices the file being rewritten fails rather than serving
+/// rows that do not match the schema.
+#[derive(Clone)]
 struct DataStore {
-    columns: HashMap<String, (Field, Data)>,
-    loaded: bool,
+    /// The fields and parse types of the columns in header order.
+    columns: Vec<(Field, ColumnType)>,
+    num_rows: usize,
+    /// The stamp of the files the schema was read from, or `None` before the
+    /// first successful load.
+    stamp: Option<FileStamp>,
+    /// The SHA-256 of the CSV file the schema was read from.
+    hash: String,
+    /// The CSV file the schema was read from, kept open by the requests
+    /// still reading it after a reload.
+    file: Option<Arc<LoadedFile>>,
 }
 
 impl DataStore {
     fn new() -> Self {
         Self {
-            columns: HashMap::new(),
-            loaded: false,
+            columns: Vec::new(),
+            num_rows: 0,
+            stamp: None,
+            hash: String::new(),
+            file: None,
         }
     }
+
+    /// Returns the index of a column in the CSV header and its field.
+    fn get_column(&self, column_name: &str) -> Result<(usize, &(Field, ColumnType))> {
+        self.columns
+            .iter()
+            .enumerate()
+            .find(|(_, (field, _))| field.name() == column_name)
+            .ok_or(Status::not_found(format!(
+                "column {} not found",
+                column_name
+            )))
+    }
+}
+
+/// A column type of a CSV dataset, in the order tried by inference.
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+enum ColumnType {
+    Boolean,
+    Int64,
+    Float64,
+    Date32,
+    /// A timestamp without time zone, such as `2024-04-01 09:30:00`.
+    Timestamp,
+    /// A timestamp with an offset, such as `2024-04-01T09:30:00+09:00`,
+    /// normalized to UTC.
+    TimestampTz,
+    Utf8,
 }
 
-static STATE: LazyLock<Mutex<State>> = LazyLock::new(|| {
-    Mutex::new(State {
-        data: DataStore::new(),
-    })
-});
+const INFERENCE_ORDER: [ColumnType; 7] = [
+    ColumnType::Boolean,
+    ColumnType::Int64,
+    ColumnType::Float64,
+    ColumnType::Date32,
+    ColumnType::Timestamp,
+    ColumnType::TimestampTz,
+    ColumnType::Utf8,
+];
 
-enum Value {
-    Float32(f32),
-    String(String),
+/// The bitmask of every type in `INFERENCE_ORDER`.
+const ALL_TYPES: u8 = (1 << INFERENCE_ORDER.len()) - 1;
+
+/// Returns the bitmask over `INFERENCE_ORDER` of the types `value` parses as.
+fn matching_types(value: &str) -> u8 {
+    INFERENCE_ORDER
+        .iter()
+        .enumerate()
+        .filter(|(_, column_type)| column_type.matches(value))
+        .fold(0, |mask, (idx, _)| mask | 1 << idx)
 }
 
-pub fn get_data(cmd_opts: &CmdOptions, column_name: &str) -> Result<Vec<RecordBatch>> {
-    let mut state = STATE.lock().unwrap();
+/// A column pinned by a sidecar schema file.
+#[derive(Deserialize)]
+struct SchemaColumn {
+    name: String,
+    #[serde(rename = "type")]
+    column_type: ColumnType,
+    #[serde(default = "default_nullable")]
+    nullable: bool,
+}
 
-    if !state.data.loaded {
-        let csv_data = std::fs::read_to_string(cmd_opts.csv_file.as_ref().unwrap())
-            .map_err(|e| Status::internal(format!("open csv: {:?}", e)))?;
-        let mut data = Vec::<(String, Vec<Option<Value>>)>::new();
-        let mut reader = csv::Reader::from_reader(csv_data.as_bytes());
-        for r in reader
-            .headers()
-            .map_err(|e| Status::internal(format!("read header: {:?}", e)))?
-        {
-            data.push((r.to_string(), vec![]));
-        }
-        for r in reader.records() {
-            let val =
-                r.map_err(|e| Status::invalid_argument(format!("invalid csv row: {:?}", e)))?;
-            if val.len() != data.len() {
+fn default_nullable() -> bool {
+    true
+}
+
+/// The sidecar schema of a CSV file, read from `<stem>.schema.json` or
+/// `<stem>.schema.toml` next to it. Columns it does not mention are inferred.
+#[derive(Deserialize)]
+struct SchemaFile {
+    columns: Vec<SchemaColumn>,
+}
+
+const SCHEMA_EXTENSIONS: [&str; 2] = ["json", "toml"];
+
+fn schema_path(path: &str, ext: &str) -> PathBuf {
+    Path::new(path).with_extension(format!("schema.{}", ext))
+}
+
+fn read_schema_file(path: &str) -> Result<Option<SchemaFile>> {
+    for ext in SCHEMA_EXTENSIONS {
+        let schema_path = schema_path(path, ext);
+        let Ok(text) = std::fs::read_to_string(&schema_path) else {
+            continue;
+        };
+        let schema = if ext == "json" {
+            serde_json::from_str(&text).map_err(|e| e.to_string())
+        } else {
+            toml::from_str(&text).map_err(|e| e.to_string())
+        }
+        .map_err(|e| {
+            Status::invalid_argument(format!(
+                "invalid csv schema {}: {}",
+                schema_path.display(),
+                e
+            ))
+        })?;
+        return Ok(Some(schema));
+    }
+    Ok(None)
+}
+
+/// Returns true for numbers written with leading zeros, such as `00123`,
+/// which are identifiers rather than quantities.
+fn is_zero_padded(value: &str) -> bool {
+    let digits = value.trim_start_matches(['+', '-']).as_bytes();
+    digits.len() > 1 && digits[0] == b'0' && digits[1].is_ascii_digit()
+}
+
+fn parse_bool(value: &str) -> Option<bool> {
+    if value.eq_ignore_ascii_case("true") {
+        Some(true)
+    } else if value.eq_ignore_ascii_case("false") {
+        Some(false)
+    } else {
+        None
+    }
+}
+
+fn parse_i64(value: &str) -> Option<i64> {
+    if is_zero_padded(value) {
+        return None;
+    }
+    i64::from_str(value).ok()
+}
+
+fn parse_f64(value: &str) -> Option<f64> {
+    if is_zero_padded(value) || !value.bytes().any(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    f64::from_str(value).ok()
+}
+
+fn parse_date32(value: &str) -> Option<i32> {
+    let date = NaiveDate::parse_from_str(value, "%Y-%m-%d").ok()?;
+    let days = date.signed_duration_since(NaiveDate::from_ymd_opt(1970, 1, 1)?);
+    i32::try_from(days.num_days()).ok()
+}
+
+fn parse_timestamp(value: &str) -> Option<i64> {
+    ["%Y-%m-%dT%H:%M:%S%.f", "%Y-%m-%d %H:%M:%S%.f"]
+        .iter()
+        .find_map(|format| NaiveDateTime::parse_from_str(value, format).ok())
+        .map(|datetime| datetime.and_utc().timestamp_micros())
+}
+
+fn parse_timestamptz(value: &str) -> Option<i64> {
+    DateTime::parse_from_rfc3339(value)
+        .ok()
+        .map(|datetime| datetime.timestamp_micros())
+}
+
+impl ColumnType {
+    fn data_type(self) -> DataType {
+        match self {
+            ColumnType::Boolean => DataType::Boolean,
+            ColumnType::Int64 => DataType::Int64,
+            ColumnType::Float64 => DataType::Float64,
+            ColumnType::Date32 => DataType::Date32,
+            ColumnType::Timestamp => DataType::Timestamp(TimeUnit::Microsecond, None),
+            ColumnType::TimestampTz => {
+                DataType::Timestamp(TimeUnit::Microsecond, Some("UTC".into()))
+            }
+            ColumnType::Utf8 => DataType::Utf8,
+        }
+    }
+
+    fn matches(self, value: &str) -> bool {
+        match self {
+            ColumnType::Boolean => parse_bool(value).is_some(),
+            ColumnType::Int64 => parse_i64(value).is_some(),
+            ColumnType::Float64 => parse_f64(value).is_some(),
+            ColumnType::Date32 => parse_date32(value).is_some(),
+            ColumnType::Timestamp => parse_timestamp(value).is_some(),
+            ColumnType::TimestampTz => parse_timestamptz(value).is_some(),
+            ColumnType::Utf8 => true,
+        }
+    }
+}
+
+fn invalid_value(field: &Field, row: usize, value: &str) -> Status {
+    Status::invalid_argument(format!(
+        "invalid csv row {}: cannot parse {:?} as {} in column {}",
+        row,
+        value,
+        field.data_type(),
+        field.name()
+    ))
+}
+
+fn missing_value(field: &Field, row: usize) -> Status {
+    Status::invalid_argument(format!(
+        "invalid csv row {}: column {} must not be empty",
+        row,
+        field.name()
+    ))
+}
+
+/// Parses the values of a column starting at data row `first_row`, rejecting
+/// values that do not match the column type and nulls in a non-nullable
+/// column.
+fn parse_values<T>(
+    field: &Field,
+    first_row: usize,
+    values: &[Option<String>],
+    parse: impl Fn(&str) -> Option<T>,
+) -> Result<Vec<Option<T>>> {
+    values
+        .iter()
+        .enumerate()
+        .map(|(idx, value)| match value {
+            Some(value) => parse(value)
+                .map(Some)
+                .ok_or_else(|| invalid_value(field, first_row + idx, value)),
+            None if field.is_nullable() => Ok(None),
+            None => Err(missing_value(field, first_row + idx)),
+        })
+        .collect()
+}
+
+fn build_array(
+    field: &Field,
+    column_type: ColumnType,
+    first_row: usize,
+    values: &[Option<String>],
+) -> Result<ArrayRef> {
+    let array: ArrayRef = match column_type {
+        ColumnType::Boolean => Arc::new(BooleanArray::from(parse_values(
+            field, first_row, values, parse_bool,
+        )?)),
+        ColumnType::Int64 => Arc::new(Int64Array::from(parse_values(
+            field, first_row, values, parse_i64,
+        )?)),
+        ColumnType::Float64 => Arc::new(Float64Array::from(parse_values(
+            field, first_row, values, parse_f64,
+        )?)),
+        ColumnType::Date32 => Arc::new(Date32Array::from(parse_values(
+            field,
+            first_row,
+            values,
+            parse_date32,
+        )?)),
+        ColumnType::Timestamp => Arc::new(TimestampMicrosecondArray::from(parse_values(
+            field,
+            first_row,
+            values,
+            parse_timestamp,
+        )?)),
+        ColumnType::TimestampTz => Arc::new(
+            TimestampMicrosecondArray::from(parse_values(
+                field,
+                first_row,
+                values,
+                parse_timestamptz,
+            )?)
+            .with_timezone("UTC"),
+        ),
+        ColumnType::Utf8 => Arc::new(StringArray::from(parse_values(
+            field,
+            first_row,
+            values,
+            |v| Some(v.to_string()),
+        )?)),
+    };
+    Ok(array)
+}
+
+/// Reads a `LoadedFile` from the start and hashes the bytes read. Reads are
+/// positioned, so that the requests sharing the file do not move each other.
+struct HashingReader {
+    file: Arc<LoadedFile>,
+    pos: u64,
+    hasher: Sha256,
+}
+
+impl HashingReader {
+    fn new(file: Arc<LoadedFile>) -> Self {
+        Self {
+            file,
+            pos: 0,
+            hasher: Sha256::new(),
+        }
+    }
+
+    fn hash(&self) -> String {
+        format!("{:x}", self.hasher.clone().finalize())
+    }
+}
+
+impl Read for HashingReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.file.file.read_at(buf, self.pos)?;
+        self.pos += n as u64;
+        self.hasher.update(&buf[..n]);
+        Ok(n)
+    }
+}
+
+/// Reads the next record, checking that it has `num_columns` fields.
+fn read_record<R: Read>(
+    reader: &mut csv::Reader<R>,
+    record: &mut StringRecord,
+    num_columns: usize,
+) -> Result<bool> {
+    let more = reader
+        .read_record(record)
+        .map_err(|e| Status::invalid_argument(format!("invalid csv row: {:?}", e)))?;
+    if more && record.len() != num_columns {
+        return Err(Status::invalid_argument(format!(
+            "invalid csv row: expected {} columns but got {}",
+            num_columns,
+            record.len()
+        )));
+    }
+    Ok(more)
+}
+
+/// Scans a CSV file into a new `DataStore`, inferring the type of every
+/// column not pinned by the sidecar schema and validating the pinned ones.
+/// The file is read in one streaming pass, and a failed scan leaves the store
+/// being served untouched.
+fn load(path: &str) -> Result<DataStore> {
+    let file = Arc::new(LoadedFile::open(path)?);
+    let mut reader = csv::Reader::from_reader(HashingReader::new(file.clone()));
+    let headers: Vec<String> = reader
+        .headers()
+        .map_err(|e| Status::internal(format!("read header: {:?}", e)))?
+        .iter()
+        .map(|r| r.to_string())
+        .collect();
+
+    let mut pinned = HashMap::new();
+    if let Some(schema) = read_schema_file(path)? {
+        for column in schema.columns {
+            if !headers.contains(&column.name) {
                 return Err(Status::invalid_argument(format!(
-                    "invalid csv row: expected {} columns but got {}",
-                    data.len(),
-                    val.len()
+                    "csv schema column {} not found in {}",
+                    column.name, path
                 )));
             }
-            for idx in 0..data.len() {
-                let str = val.get(idx).ok_or_else(|| {
-                    Status::invalid_argument(format!("missing column {} in csv row", idx))
-                })?;
-                let v = f32::from_str(str)
-                    .map(|v| Some(Value::Float32(v)))
-                    .unwrap_or_else(|_| {
-                        if !str.is_empty() {
-                            Some(Value::String(str.to_string()))
-                        } else {
-                            None
-                        }
-                    });
-                data.get_mut(idx)
-                    .ok_or_else(|| Status::internal(format!("missing destination column {}", idx)))?
-                    .1
-                    .push(v);
-            }
+            pinned.insert(column.name.clone(), column);
         }
+    }
+    let pinned_fields: Vec<Option<(Field, ColumnType)>> = headers
+        .iter()
+        .map(|name| {
+            pinned.get(name).map(|column| {
+                let field = Field::new(name, column.column_type.data_type(), column.nullable);
+                (field, column.column_type)
+            })
+        })
+        .collect();
 
-        for (col_name, value) in data {
-            let is_float_column = value
-                .iter()
-                .any(|v| matches!(v.as_ref(), Some(Value::Float32(_))));
-            if is_float_column {
-                let field = Field::new(col_name.clone(), DataType::Float32, true);
-                let new_value: Vec<Option<f32>> = value
-                    .into_iter()
-                    .map(|v| match v {
-                        Some(Value::Float32(v)) => Some(v),
-                        _ => None,
-                    })
-                    .collect();
-                let array = Float32Array::from(new_value);
-                state
-                    .data
-                    .columns
-                    .insert(col_name, (field, Data::Float32Array(array)));
-            } else {
-                let field = Field::new(col_name.clone(), DataType::Utf8, true);
-                let new_value: Vec<Option<String>> = value
-                    .into_iter()
-                    .map(|v| match v {
-                        Some(Value::String(v)) => Some(v),
-                        _ => None,
-                    })
-                    .collect();
-                let array = StringArray::from(new_value);
-                state
-                    .data
-                    .columns
-                    .insert(col_name, (field, Data::StringArray(array)));
+    // A bitmask over INFERENCE_ORDER of the types every value seen so far
+    // parses as. Utf8 comes last and matches anything, so it always survives,
+    // and a column without any value is left at ALL_TYPES and read as Utf8.
+    let mut candidates = vec![ALL_TYPES; headers.len()];
+    let mut num_rows = 0;
+    let mut record = StringRecord::new();
+    while read_record(&mut reader, &mut record, headers.len())? {
+        num_rows += 1;
+        for (idx, value) in record.iter().enumerate() {
+            match &pinned_fields[idx] {
+                Some((field, _)) if value.is_empty() => {
+                    if !field.is_nullable() {
+                        return Err(missing_value(field, num_rows));
+                    }
+                }
+                Some((field, column_type)) => {
+                    if !column_type.matches(value) {
+                        return Err(invalid_value(field, num_rows, value));
+                    }
+                }
+                None if value.is_empty() => {}
+                None => candidates[idx] &= matching_types(value),
             }
         }
-
-        info!("data loaded");
-        state.data.loaded = true
     }
 
-    let column = state
-        .data
-        .columns
-        .get(column_name)
-        .ok_or(Status::internal(format!(
-            "column {} not found",
-            column_name
-        )))?;
+    let columns = headers
+        .iter()
+        .zip(pinned_fields)
+        .zip(candidates)
+        .map(|((name, pinned), candidate)| {
+            pinned.unwrap_or_else(|| {
+                let column_type = match candidate {
+                    ALL_TYPES => ColumnType::Utf8,
+                    _ => INFERENCE_ORDER[candidate.trailing_zeros() as usize],
+                };
+                (Field::new(name, column_type.data_type(), true), column_type)
+            })
+        })
+        .collect();
 
-    Ok(vec![RecordBatch::try_new(
-        Arc::new(Schema::new(vec![column.0.clone()])),
-        match &column.1 {
-            Data::Float32Array(v) => vec![Arc::new(v.clone())],
-            Data::StringArray(v) => vec![Arc::new(v.clone())],
-        },
-    )
-    .map_err(|e| {
-        Status::internal(format!("failed to new RecordBatch: {:?}", e))
-    })?])
+    file.check_unchanged(path)?;
+    let mut data_store = DataStore::new();
+    data_store.columns = columns;
+    data_store.num_rows = num_rows;
+    data_store.hash = reader.get_ref().hash();
+    data_store.file = Some(file);
+    Ok(data_store)
 }
 
+/// Returns the `(name, path)` of every CSV dataset configured by `cmd_opts`.
+/// `--csv-file` is named `csv`, the files of `--csv-dir` are named after
+/// their file stem, and `--csv-dataset` takes explicit `name=path` pairs.
+pub fn datasets(cmd_opts: &CmdOptions) -> anyhow::Result<Vec<(String, String)>> {
+    let mut datasets = Vec::new();
+    if let Some(csv_file) = &cmd_opts.csv_file {
+        datasets.push(("csv".to_string(), csv_file.clone()));
+    }
+    if let Some(csv_dir) = &cmd_opts.csv_dir {
+        let mut paths = std::fs::read_dir(csv_dir)
+            .with_context(|| format!("failed to read csv dir {}", csv_dir))?
+            .map(|entry| entry.map(|entry| entry.path()))
+            .collect::<std::io::Result<Vec<_>>>()?;
+        paths.sort();
+        for path in paths {
+            if path.is_file() && path.extension().is_some_and(|ext| ext == "csv") {
+                let name = path
+                    .file_stem()
+                    .and_then(|stem| stem.to_str())
+                    .ok_or_else(|| anyhow::anyhow!("invalid csv file name: {:?}", path))?;
+                datasets.push((name.to_string(), path.to_string_lossy().to_string()));
+            }
+        }
+    }
+    for dataset in &cmd_opts.csv_dataset {
+        let (name, path) = dataset
+            .split_once('=')
+            .ok_or_else(|| anyhow::anyhow!("csv dataset must be name=path: {}", dataset))?;
+        datasets.push((name.to_string(), path.to_string()));
+    }
+
+    let mut names = HashSet::new();
+    for (name, _) in &datasets {
+        if name.is_empty() || name.contains('/') {
+            return Err(anyhow::anyhow!("invalid csv dataset name: {:?}", name));
+        }
+        if !names.insert(name.as_str()) {
+            return Err(anyhow::anyhow!("duplicate csv dataset name: {}", name));
+        }
+    }
+    Ok(datasets)
+}
+
+/// Returns the policy of `subject` for a dataset. A policy registered for the
+/// dataset in `policy_by_dataset` takes precedence over the subject-wide one.
 pub fn get_policy(
     cmd_opts: &CmdOptions,
     subject: &str,
+    dataset: &str,
     column_name: &str,
 ) -> rusqlite::Result<String> {
     let conn = rusqlite::Connection::open_with_flags(
         &cmd_opts.policy_db,
         rusqlite::OpenFlags::SQLITE_OPEN_READ_ONLY,
     )?;
+    let sql = r#"
+        SELECT EXISTS(
+            SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = 'policy_by_dataset'
+        );
+        "#;
+    let has_dataset_policy: bool = conn.query_row(sql, [], |row| row.get(0))?;
+    if has_dataset_policy {
+        let sql = r#"
+            SELECT json FROM policy_by_dataset
+                WHERE dataset = ? AND subject = ?;
+            "#;
+        let mut stmt = conn.prepare(sql)?;
+        let entry_iter = stmt.query_map(rusqlite::params![dataset, subject], |row| {
+            let val: String = row.get(0)?;
+            Ok(val)
+        })?;
+        if let Some(policy) = entry_iter.last().transpose()? {
+            return Ok(policy);
+        }
+    }
+
     let sql = r#"
         SELECT json FROM policy
             WHERE subject = ?;
@@ -180,26 +625,222 @@ pub fn default_policy(_column_name: &str) -> String {
     serde_json::to_string(&policy).unwrap()
 }
 
+/// Serves the columns of one CSV dataset. The file is scanned for its schema
+/// on first access, and read in chunks for each request.
+pub struct CsvSource {
+    cmd_opts: CmdOptions,
+    dataset: String,
+    path: String,
+    /// The dataset being served, replaced as a whole once a reload is done.
+    data: Mutex<Arc<DataStore>>,
+    /// Held while the file is scanned, so that one request reloads it while
+    /// the others keep being served the previous dataset.
+    loading: Mutex<()>,
+}
+
+impl CsvSource {
+    pub fn new(cmd_opts: CmdOptions, dataset: &str, path: &str) -> Self {
+        Self {
+            cmd_opts,
+            dataset: dataset.to_string(),
+            path: path.to_string(),
+            data: Mutex::new(Arc::new(DataStore::new())),
+            loading: Mutex::new(()),
+        }
+    }
+
+    /// Returns the dataset, reloading it first when the CSV file or its
+    /// sidecar schema changed since it was read. The file is scanned without
+    /// holding the dataset, and requests arriving meanwhile are served the
+    /// previous one.
+    fn data(&self) -> Result<Arc<DataStore>> {
+        let stamp = FileStamp::read(&self.path)?;
+        let current = self.data.lock().unwrap().clone();
+        if current.stamp.as_ref() == Some(&stamp) {
+            return Ok(current);
+        }
+        let _loading = match self.loading.try_lock() {
+            Ok(loading) => loading,
+            Err(_) if current.stamp.is_some() => return Ok(current),
+            Err(_) => self.loading.lock().unwrap(),
+        };
+        // Another request may have reloaded the file while this one waited.
+        let current = self.data.lock().unwrap().clone();
+        if current.stamp.as_ref() == Some(&stamp) {
+            return Ok(current);
+        }
+
+        let data = match load(&self.path) {
+            Ok(mut new_data) => {
+                let changed = current
+                    .stamp
+                    .as_ref()
+                    .is_none_or(|loaded| loaded.schemas != stamp.schemas)
+                    || new_data.hash != current.hash;
+                new_data.stamp = Some(stamp);
+                if changed {
+                    info!(
+                        "data loaded: {}, dataset: {}, rows: {}, sha256: {}",
+                        self.path, self.dataset, new_data.num_rows, new_data.hash
+                    );
+                }
+                new_data
+            }
+            Err(e) if current.stamp.is_some() => {
+                // Keep serving the previous table until the file is fixed.
+                error!(
+                    "failed to reload {}, keeping sha256: {}: {}",
+                    self.path,
+                    current.hash,
+                    e.message()
+                );
+                let mut kept = DataStore::clone(&current);
+                kept.stamp = Some(stamp);
+                kept
+            }
+            Err(e) => return Err(e),
+        };
+        let data = Arc::new(data);
+        *self.data.lock().unwrap() = data.clone();
+        Ok(data)
+    }
+}
+
+impl DataSource for CsvSource {
+    fn schema(
+        &self,
+        _subject: &Subject,
+        _target: &str,
+        columns: &[&str],
+    ) -> Result<(SchemaRef, i64)> {
+        let data = self.data()?;
+        let mut fields = Vec::new();
+        for column_name in columns {
+            let (_, (field, _)) = data.get_column(column_name)?;
+            fields.push(field.clone());
+        }
+        Ok((Arc::new(Schema::new(fields)), data.num_rows as i64))
+    }
+
+    /// Reads the CSV file the schema was read from in chunks of `batch_size`
+    /// rows. A file moved over it meanwhile does not affect the stream, but a
+    /// file rewritten in place fails it with `aborted`: before each batch if
+    /// its size or modification time changed, and once it is read to the end
+    /// if its hash did.
+    fn get_data(
+        &self,
+        _subject: &Subject,
+        _target: &str,
+        columns: &[&str],
+        batch_size: usize,
+        sink: &mut BatchSink,
+    ) -> Result<()> {
+        if columns.is_empty() {
+            return Err(Status::invalid_argument("no columns requested"));
+        }
+        let batch_size = batch_size.max(1);
+        let data = self.data()?;
+        let mut selected = Vec::new();
+        for column_name in columns {
+            let (idx, (field, column_type)) = data.get_column(column_name)?;
+            selected.push((idx, field.clone(), *column_type));
+        }
+        let num_columns = data.columns.len();
+        let file = data
+            .file
+            .clone()
+            .ok_or_else(|| Status::internal("csv dataset is not loaded"))?;
+        let schema = Arc::new(Schema::new(
+            selected
+                .iter()
+                .map(|(_, field, _)| field.clone())
+                .collect::<Vec<_>>(),
+        ));
+
+        let mut reader = csv::Reader::from_reader(HashingReader::new(file.clone()));
+        let mut record = StringRecord::new();
+        let mut values = vec![Vec::with_capacity(batch_size); selected.len()];
+        let mut first_row = 1;
+        loop {
+            let more = match read_record(&mut reader, &mut record, num_columns) {
+                Ok(more) => more,
+                Err(e) => {
+                    file.check_unchanged(&self.path)?;
+                    return Err(e);
+                }
+            };
+            if more {
+                for (column, (idx, _, _)) in values.iter_mut().zip(&selected) {
+                    let value = &record[*idx];
+                    column.push(if !value.is_empty() {
+                        Some(value.to_string())
+                    } else {
+                        None
+                    });
+                }
+            } else if reader.get_ref().hash() != data.hash {
+                return Err(changed_error(&self.path));
+            }
+
+            let len = values[0].len();
+            if len == batch_size || (!more && (len > 0 || first_row == 1)) {
+                file.check_unchanged(&self.path)?;
+                let arrays = selected
+                    .iter()
+                    .zip(&values)
+                    .map(|((_, field, column_type), values)| {
+                        build_array(field, *column_type, first_row, values)
+                    })
+                    .collect::<Result<Vec<_>>>()?;
+                let batch = RecordBatch::try_new(schema.clone(), arrays)
+                    .map_err(|e| Status::internal(format!("failed to new RecordBatch: {:?}", e)))?;
+                first_row += len;
+                values.iter_mut().for_each(Vec::clear);
+                if !sink(batch) {
+                    break;
+                }
+            }
+            if !more {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    fn get_policy(&self, subject: &Subject, _target: &str, column_name: &str) -> Result<String> {
+        get_policy(&self.cmd_opts, &subject.name, &self.dataset, column_name)
+            .map_err(|e| Status::internal(format!("failed to get policy: {:?}", e)))
+    }
+
+    /// Lists the CSV columns in header order.
+    fn list(&self, _subject: &Subject, target: &str) -> Result<Vec<ColumnInfo>> {
+        let data = self.data()?;
+        Ok(data
+            .columns
+            .iter()
+            .map(|(field, _)| ColumnInfo {
+                target: target.to_string(),
+                column_name: field.name().clone(),
+                field: Some(field.clone()),
+            })
+            .collect())
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use super::get_data;
+    use super::{datasets, CsvSource};
+    use crate::auth::Subject;
+    use crate::source::{collect, DataSource};
     use crate::CmdOptions;
+    use arrow::array::AsArray;
+    use arrow::datatypes::{DataType, Float64Type, Int64Type, TimeUnit};
+    use std::time::{Duration, UNIX_EPOCH};
 
     fn test_cmd_opts(csv_file: &str) -> CmdOptions {
         CmdOptions {
-            no_tls: true,
-            authorized_subject: None,
             csv_file: Some(csv_file.to_string()),
-            edinet_db: None,
-            parquet_path: "./parquet".to_string(),
-            storage_db: "./storage.db".to_string(),
-            policy_db: "./policy.db".to_string(),
-            cert: "./certs/server.crt".to_string(),
-            key: "./certs/server.key".to_string(),
-            port: 50053,
-            use_test_challenge: false,
-            allow_test_subject: true,
-            server_ld: None,
+            ..crate::test_cmd_opts()
         }
     }
 
@@ -208,11 +849,277 @@ mod tests {
         let temp_dir = tempfile::tempdir().unwrap();
         let csv_path = temp_dir.path().join("bad.csv");
         std::fs::write(&csv_path, "a,b\n1\n").unwrap();
-        let cmd_opts = test_cmd_opts(csv_path.to_str().unwrap());
+        let csv_path = csv_path.to_str().unwrap();
+        let cmd_opts = test_cmd_opts(csv_path);
+        let source = CsvSource::new(cmd_opts, "csv", csv_path);
 
-        let err = get_data(&cmd_opts, "a").unwrap_err();
+        let err = collect(&source, "csv", &["a"], 1024).unwrap_err();
 
         assert_eq!(err.code(), tonic::Code::InvalidArgument);
         assert!(err.message().contains("invalid csv row"));
     }
+
+    #[test]
+    fn datasets_are_loaded_independently() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let csv_dir = temp_dir.path().join("tables");
+        std::fs::create_dir(&csv_dir).unwrap();
+        std::fs::write(csv_dir.join("admissions.csv"), "ward,days\nA,3\nB,5\n").unwrap();
+        std::fs::write(csv_dir.join("README.txt"), "not a dataset").unwrap();
+        let labs_path = temp_dir.path().join("labs.csv");
+        std::fs::write(&labs_path, "hba1c\n6.1\n").unwrap();
+        let mut cmd_opts = test_cmd_opts("");
+        cmd_opts.csv_file = None;
+        cmd_opts.csv_dir = Some(csv_dir.to_str().unwrap().to_string());
+        cmd_opts.csv_dataset = vec![format!("labs={}", labs_path.to_str().unwrap())];
+
+        let datasets = datasets(&cmd_opts).unwrap();
+        assert_eq!(
+            datasets
+                .iter()
+                .map(|(name, _)| name.as_str())
+                .collect::<Vec<_>>(),
+            vec!["admissions", "labs"]
+        );
+
+        let sources = datasets
+            .iter()
+            .map(|(name, path)| CsvSource::new(cmd_opts.clone(), name, path))
+            .collect::<Vec<_>>();
+        let admissions = collect(&sources[0], "admissions", &["days"], 1024).unwrap();
+        assert_eq!(admissions[0].num_rows(), 2);
+        let labs = collect(&sources[1], "labs", &["hba1c"], 1024).unwrap();
+        assert_eq!(labs[0].num_rows(), 1);
+        assert!(collect(&sources[1], "labs", &["days"], 1024).is_err());
+    }
+
+    #[test]
+    fn schema_infers_typed_columns() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let csv_path = temp_dir.path().join("typed.csv");
+        std::fs::write(
+            &csv_path,
+            "id,count,ratio,flag,day,at,at_tz,note\n\
+             00123,1,1.5,true,2024-04-01,2024-04-01 09:30:00,2024-04-01T09:30:00+09:00,a\n\
+             00456,9007199254740993,2,FALSE,2024-04-02,2024-04-02T10:00:00.5,2024-04-02T00:00:00Z,\n",
+        )
+        .unwrap();
+        let csv_path = csv_path.to_str().unwrap();
+        let source = CsvSource::new(test_cmd_opts(csv_path), "csv", csv_path);
+
+        let (schema, num_rows) = source
+            .schema(
+                &Subject::new("test"),
+                "csv",
+                &["id", "count", "ratio", "flag", "day", "at", "at_tz", "note"],
+            )
+            .unwrap();
+
+        assert_eq!(num_rows, 2);
+        let types = schema
+            .fields()
+            .iter()
+            .map(|field| field.data_type().clone())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            types,
+            vec![
+                DataType::Utf8,
+                DataType::Int64,
+                DataType::Float64,
+                DataType::Boolean,
+                DataType::Date32,
+                DataType::Timestamp(TimeUnit::Microsecond, None),
+                DataType::Timestamp(TimeUnit::Microsecond, Some("UTC".into())),
+                DataType::Utf8,
+            ]
+        );
+
+        let batches = collect(&source, "csv", &["id", "count"], 1024).unwrap();
+        assert_eq!(batches[0].column(0).as_string::<i32>().value(0), "00123");
+        assert_eq!(
+            batches[0].column(1).as_primitive::<Int64Type>().value(1),
+            9007199254740993
+        );
+    }
+
+    #[test]
+    fn inference_checks_every_value_of_a_column() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let csv_path = temp_dir.path().join("mixed.csv");
+        std::fs::write(
+            &csv_path,
+            "flag_int,float_date,date_at,int_float,empty\n\
+             true,1.5,2024-04-01,1,\n\
+             2,2024-04-01,2024-04-01 09:30:00,1.5,\n",
+        )
+        .unwrap();
+        let csv_path = csv_path.to_str().unwrap();
+        let source = CsvSource::new(test_cmd_opts(csv_path), "csv", csv_path);
+
+        let columns = ["flag_int", "float_date", "date_at", "int_float", "empty"];
+        let (schema, _) = source
+            .schema(&Subject::new("test"), "csv", &columns)
+            .unwrap();
+        let types = schema
+            .fields()
+            .iter()
+            .map(|field| field.data_type().clone())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            types,
+            vec![
+                DataType::Utf8,
+                DataType::Utf8,
+                DataType::Utf8,
+                DataType::Float64,
+                DataType::Utf8
+            ]
+        );
+
+        let batches = collect(&source, "csv", &columns, 1024).unwrap();
+        assert_eq!(batches[0].column(0).as_string::<i32>().value(0), "true");
+        assert_eq!(
+            batches[0].column(2).as_string::<i32>().value(0),
+            "2024-04-01"
+        );
+        assert_eq!(batches[0].column(4).null_count(), 2);
+    }
+
+    #[test]
+    fn sidecar_schema_pins_types_and_rejects_mismatches() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let csv_path = temp_dir.path().join("labs.csv");
+        std::fs::write(&csv_path, "code,value\n1,2.5\n2,\n").unwrap();
+        std::fs::write(
+            temp_dir.path().join("labs.schema.toml"),
+            "[[columns]]\nname = \"code\"\ntype = \"utf8\"\n",
+        )
+        .unwrap();
+        let csv_path = csv_path.to_str().unwrap();
+        let source = CsvSource::new(test_cmd_opts(csv_path), "csv", csv_path);
+
+        let (schema, _) = source
+            .schema(&Subject::new("test"), "csv", &["code", "value"])
+            .unwrap();
+        assert_eq!(schema.field(0).data_type(), &DataType::Utf8);
+        assert_eq!(schema.field(1).data_type(), &DataType::Float64);
+
+        std::fs::write(
+            temp_dir.path().join("labs.schema.json"),
+            r#"{"columns": [{"name": "value", "type": "float64", "nullable": false}]}"#,
+        )
+        .unwrap();
+        let source = CsvSource::new(test_cmd_opts(csv_path), "csv", csv_path);
+
+        let err = collect(&source, "csv", &["value"], 1024).unwrap_err();
+        assert_eq!(err.code(), tonic::Code::InvalidArgument);
+        assert!(err.message().contains("invalid csv row 2"));
+
+        std::fs::write(
+            temp_dir.path().join("labs.schema.json"),
+            r#"{"columns": [{"name": "code", "type": "boolean"}]}"#,
+        )
+        .unwrap();
+        let source = CsvSource::new(test_cmd_opts(csv_path), "csv", csv_path);
+
+        let err = collect(&source, "csv", &["code"], 1024).unwrap_err();
+        assert_eq!(err.code(), tonic::Code::InvalidArgument);
+    }
+
+    #[test]
+    fn get_data_streams_batches_until_the_sink_stops() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let csv_path = temp_dir.path().join("rows.csv");
+        std::fs::write(&csv_path, "n\n1\n2\n3\n4\n5\n").unwrap();
+        let csv_path = csv_path.to_str().unwrap();
+        let source = CsvSource::new(test_cmd_opts(csv_path), "csv", csv_path);
+
+        let batches = collect(&source, "csv", &["n"], 2).unwrap();
+        assert_eq!(
+            batches.iter().map(|b| b.num_rows()).collect::<Vec<_>>(),
+            vec![2, 2, 1]
+        );
+        assert_eq!(batches[2].column(0).as_primitive::<Int64Type>().value(0), 5);
+
+        let mut received = 0;
+        source
+            .get_data(&Subject::new("test"), "csv", &["n"], 2, &mut |_| {
+                received += 1;
+                false
+            })
+            .unwrap();
+        assert_eq!(received, 1);
+    }
+
+    #[test]
+    fn data_is_reloaded_when_the_file_changes() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let csv_path = temp_dir.path().join("wage.csv");
+        let write = |contents: &str, secs: u64| {
+            std::fs::write(&csv_path, contents).unwrap();
+            std::fs::File::options()
+                .write(true)
+                .open(&csv_path)
+                .unwrap()
+                .set_modified(UNIX_EPOCH + Duration::from_secs(secs))
+                .unwrap();
+        };
+        write("wage\n1.5\n", 1);
+        let path = csv_path.to_str().unwrap();
+        let source = CsvSource::new(test_cmd_opts(path), "csv", path);
+        assert_eq!(
+            source
+                .schema(&Subject::new("test"), "csv", &["wage"])
+                .unwrap()
+                .1,
+            1
+        );
+
+        write("wage\n1.5\n2.5\n", 2);
+        assert_eq!(
+            source
+                .schema(&Subject::new("test"), "csv", &["wage"])
+                .unwrap()
+                .1,
+            2
+        );
+
+        // A broken file moved over the dataset keeps the last good table, and
+        // its rows are still served rather than the ones of the broken file.
+        let broken_path = temp_dir.path().join("wage.csv.new");
+        std::fs::write(&broken_path, "wage,extra\n1.5\n").unwrap();
+        std::fs::rename(&broken_path, &csv_path).unwrap();
+        assert_eq!(
+            source
+                .schema(&Subject::new("test"), "csv", &["wage"])
+                .unwrap()
+                .1,
+            2
+        );
+        let wages = || {
+            collect(&source, "csv", &["wage"], 1024).map(|batches| {
+                batches[0]
+                    .column(0)
+                    .as_primitive::<Float64Type>()
+                    .values()
+                    .to_vec()
+            })
+        };
+        assert_eq!(wages().unwrap(), vec![1.5, 2.5]);
+
+        // A file rewritten in place cannot be served any more.
+        write("wage\n3.5\n", 4);
+        assert_eq!(wages().unwrap(), vec![3.5]);
+        write("wage,extra\n4.5\n", 5);
+        assert_eq!(wages().unwrap_err().code(), tonic::Code::Aborted);
+    }
+
+    #[test]
+    fn datasets_rejects_duplicate_names() {
+        let mut cmd_opts = test_cmd_opts("./a.csv");
+        cmd_opts.csv_dataset = vec!["csv=./b.csv".to_string()];
+
+        assert!(datasets(&cmd_opts).is_err());
+    }
 }
@@ -2,21 +2,24 @@
 // SPDX-License-Identifier: MIT
 
 use arrow::array::{Float32Array, StringArray};
-use arrow::datatypes::{DataType, Field, Schema};
+use arrow::datatypes::{DataType, Field, Schema, SchemaRef};
 use arrow::record_batch::RecordBatch;
 use chrono::{Datelike, NaiveDate};
 use isekai_utils::policy::{FunctionPolicy, PolicyFile, PolicyRule};
-use parquet::arrow::{arrow_reader::ParquetRecordBatchReaderBuilder, ArrowWriter};
+use parquet::arrow::arrow_reader::{ParquetRecordBatchReader, ParquetRecordBatchReaderBuilder};
+use parquet::arrow::ArrowWriter;
 use parquet::basic::Compression;
 use parquet::file::properties::WriterProperties;
 use rusqlite::{params, Connection, OpenFlags};
 use std::collections::{HashMap, HashSet, VecDeque};
-use std::fs::OpenOptions;
 use std::path::{Component, Path};
 use std::str::FromStr;
 use std::sync::Arc;
 use tonic::{Result, Status};
 
+use crate::auth::Subject;
+use crate::crypto::{FileWriter, StoredFile};
+use crate::source::{BatchSink, ColumnInfo, DataSource};
 use crate::CmdOptions;
 
 enum Value {
@@ -58,8 +61,10 @@ const EDINET_ID_COL: usize = 0;
 const CLOSING_DATE_COL: usize = 1;
 const VALUE_COL: usize = 2;
 
-pub fn get_data(cmd_opts: &CmdOptions, column_name: &str) -> Result<Vec<RecordBatch>> {
-    // let mut state = STATE.lock().unwrap();
+/// Opens the parquet cache file of a column, materializing it from the EDINET
+/// database on first access. The cache is sealed when a master key is
+/// configured.
+fn open_parquet(cmd_opts: &CmdOptions, column_name: &str) -> Result<StoredFile> {
     let parts: Vec<&str> = column_name.split('/').collect();
     let (item, context, year) = match parts.len() {
         3 => (
@@ -83,9 +88,7 @@ pub fn get_data(cmd_opts: &CmdOptions, column_name: &str) -> Result<Vec<RecordBa
 
     let filename = parquet_filename(&cmd_opts.parquet_path, &item, &context, &year)?;
 
-    let file = if let Ok(file) = OpenOptions::new().read(true).open(&filename) {
-        file
-    } else {
+    if !Path::new(&filename).exists() {
         let conn = Connection::open_with_flags(
             cmd_opts.edinet_db.as_deref().unwrap(),
             OpenFlags::SQLITE_OPEN_READ_ONLY,
@@ -98,6 +101,7 @@ pub fn get_data(cmd_opts: &CmdOptions, column_name: &str) -> Result<Vec<RecordBa
                 ON ids.edinet_id = entries.edinet_id AND
                     entries.item = ? AND
                     entries.context = ?
+            ORDER BY ids.rowid, entries.rowid
         "#;
 
         let mut datas: VecDeque<(String, HashMap<String, Option<Value>>)> = VecDeque::new();
@@ -198,11 +202,7 @@ pub fn get_data(cmd_opts: &CmdOptions, column_name: &str) -> Result<Vec<RecordBa
             };
 
             let filename = parquet_filename(&cmd_opts.parquet_path, &item, &context, &year)?;
-            let file = OpenOptions::new()
-                .write(true)
-                .create(true)
-                .truncate(true)
-                .open(filename)
+            let file = FileWriter::create(cmd_opts, Path::new(&filename))
                 .map_err(|e| Status::internal(format!("failed to open file: {:?}", e)))?;
 
             let props = WriterProperties::builder()
@@ -217,29 +217,128 @@ pub fn get_data(cmd_opts: &CmdOptions, column_name: &str) -> Result<Vec<RecordBa
                 .map_err(|e| Status::internal(format!("failed to write: {:?}", e)))?;
 
             writer
-                .close()
+                .into_inner()
+                .map_err(|e| Status::internal(format!("failed to close: {:?}", e)))?
+                .finish()
                 .map_err(|e| Status::internal(format!("failed to close: {:?}", e)))?;
         }
+    }
+    StoredFile::open(cmd_opts, Path::new(&filename))
+        .map_err(|e| Status::internal(format!("failed to open file: {:?}", e)))
+}
 
-        OpenOptions::new()
-            .read(true)
-            .open(&filename)
-            .map_err(|e| Status::internal(format!("failed to open file: {:?}", e)))?
-    };
-
-    let mut reader = ParquetRecordBatchReaderBuilder::try_new(file)
+fn read_parquet(
+    cmd_opts: &CmdOptions,
+    column_name: &str,
+    batch_size: usize,
+) -> Result<ParquetRecordBatchReader> {
+    let file = open_parquet(cmd_opts, column_name)?;
+    ParquetRecordBatchReaderBuilder::try_new(file)
         .map_err(|e| Status::internal(format!("failed to new ParquetRecordBatchReader: {:?}", e)))?
+        .with_batch_size(batch_size)
         .build()
-        .map_err(|e| {
-            Status::internal(format!("failed to build ParquetRecordBatchReader: {:?}", e))
+        .map_err(|e| Status::internal(format!("failed to build ParquetRecordBatchReader: {:?}", e)))
+}
+
+/// Returns the schema and the row count of `item/context/year` columns.
+pub fn get_schema(cmd_opts: &CmdOptions, column_names: &[&str]) -> Result<(SchemaRef, i64)> {
+    let mut fields = Vec::new();
+    let mut num_rows = None;
+    for column_name in column_names {
+        let file = open_parquet(cmd_opts, column_name)?;
+        let builder = ParquetRecordBatchReaderBuilder::try_new(file).map_err(|e| {
+            Status::internal(format!("failed to new ParquetRecordBatchReader: {:?}", e))
         })?;
+        let rows = builder.metadata().file_metadata().num_rows();
+        if num_rows.is_some_and(|n| n != rows) {
+            return Err(Status::internal(format!(
+                "column {} is not aligned with the other columns",
+                column_name
+            )));
+        }
+        num_rows = Some(rows);
+        fields.push(builder.schema().field(0).clone());
+    }
+    Ok((Arc::new(Schema::new(fields)), num_rows.unwrap_or(0)))
+}
 
-    let mut batches = Vec::new();
-    for batch in reader.by_ref() {
-        batches
-            .push(batch.map_err(|e| Status::internal(format!("failed to read batch: {:?}", e)))?);
+/// Streams `item/context/year` columns as row-aligned batches of up to
+/// `batch_size` rows. Every column has one row per company in the `ids` table.
+pub fn get_data(
+    cmd_opts: &CmdOptions,
+    column_names: &[&str],
+    batch_size: usize,
+    sink: &mut BatchSink,
+) -> Result<()> {
+    let mut readers = Vec::new();
+    for column_name in column_names {
+        readers.push(read_parquet(cmd_opts, column_name, batch_size.max(1))?);
+    }
+    let schema = Arc::new(Schema::new(
+        readers
+            .iter()
+            .map(|reader| reader.schema().field(0).clone())
+            .collect::<Vec<_>>(),
+    ));
+
+    let mut sent = false;
+    loop {
+        let mut arrays = Vec::new();
+        for reader in readers.iter_mut() {
+            if let Some(batch) = reader.next() {
+                let batch = batch
+                    .map_err(|e| Status::internal(format!("failed to read batch: {:?}", e)))?;
+                arrays.push(batch.column(0).clone());
+            }
+        }
+        if arrays.is_empty() {
+            break;
+        }
+        let batch = RecordBatch::try_new(schema.clone(), arrays)
+            .map_err(|e| Status::internal(format!("columns are not aligned: {:?}", e)))?;
+        sent = true;
+        if !sink(batch) {
+            return Ok(());
+        }
+    }
+    if !sent {
+        sink(RecordBatch::new_empty(schema));
+    }
+    Ok(())
+}
+
+/// Returns every `item/context/year` column that has at least one value in
+/// the EDINET `entries` table.
+pub fn list_columns(cmd_opts: &CmdOptions) -> Result<Vec<String>> {
+    let conn = Connection::open_with_flags(
+        cmd_opts.edinet_db.as_deref().unwrap(),
+        OpenFlags::SQLITE_OPEN_READ_ONLY,
+    )
+    .map_err(|e| Status::internal(format!("open_with_flags: {:?}", e)))?;
+
+    let sql = r#"
+        SELECT DISTINCT item, context, substr(closing_date, 1, 4) FROM entries
+            WHERE closing_date IS NOT NULL AND closing_date != '' AND
+                value IS NOT NULL AND value != ''
+            ORDER BY item, context, 3
+        "#;
+    let mut stmt = conn
+        .prepare(sql)
+        .map_err(|e| Status::internal(format!("prepare: {:?}", e)))?;
+    let entry_iter = stmt
+        .query_map([], |row| {
+            let item: String = row.get(0)?;
+            let context: String = row.get(1)?;
+            let year: String = row.get(2)?;
+            Ok(format!("{}/{}/{}", item, context, year))
+        })
+        .map_err(|e| Status::internal(format!("query_map: {:?}", e)))?;
+
+    let mut columns = Vec::new();
+    for entry in entry_iter {
+        columns.push(entry.map_err(|e| Status::internal(format!("Invalid entry: {:?}", e)))?);
     }
-    Ok(batches)
+    Ok(columns)
 }
 
 pub fn get_policy(
@@ -312,3 +411,144 @@ pub fn default_policy(column_name: &str) -> String {
         .insert("mean_minimum_100".to_string(), func_policy);
     serde_json::to_string(&policy).unwrap()
 }
+
+/// Serves the `item/context/year` columns of the EDINET database given by
+/// `--edinet-db`.
+pub struct EdinetSource {
+    cmd_opts: CmdOptions,
+}
+
+impl EdinetSource {
+    pub fn new(cmd_opts: CmdOptions) -> Self {
+        Self { cmd_opts }
+    }
+}
+
+impl DataSource for EdinetSource {
+    fn schema(
+        &self,
+        _subject: &Subject,
+        _target: &str,
+        columns: &[&str],
+    ) -> Result<(SchemaRef, i64)> {
+        get_schema(&self.cmd_opts, columns)
+    }
+
+    fn get_data(
+        &self,
+        _subject: &Subject,
+        _target: &str,
+        columns: &[&str],
+        batch_size: usize,
+        sink: &mut BatchSink,
+    ) -> Result<()> {
+        get_data(&self.cmd_opts, columns, batch_size, sink)
+    }
+
+    fn get_policy(&self, subject: &Subject, _target: &str, column_name: &str) -> Result<String> {
+        get_policy(&self.cmd_opts, &subject.name, column_name)
+            .map_err(|e| Status::internal(format!("failed to get policy: {:?}", e)))
+    }
+
+    fn list(&self, _subject: &Subject, target: &str) -> Result<Vec<ColumnInfo>> {
+        Ok(list_columns(&self.cmd_opts)?
+            .into_iter()
+            .map(|column_name| ColumnInfo {
+                target: target.to_string(),
+                column_name,
+                field: None,
+            })
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{list_columns, EdinetSource};
+    use crate::source::collect;
+    use crate::CmdOptions;
+    use arrow::array::{AsArray, Float32Array};
+    use arrow::datatypes::Float32Type;
+    use rusqlite::Connection;
+
+    fn test_cmd_opts(edinet_db: &str, parquet_path: &str) -> CmdOptions {
+        CmdOptions {
+            edinet_db: Some(edinet_db.to_string()),
+            parquet_path: parquet_path.to_string(),
+            ..crate::test_cmd_opts()
+        }
+    }
+
+    #[test]
+    fn list_columns_returns_distinct_item_context_year() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let db_path = temp_dir.path().join("edinet.db");
+        let conn = Connection::open(&db_path).unwrap();
+        conn.execute_batch(
+            "CREATE TABLE entries (
+                edinet_id TEXT, item TEXT, context TEXT, closing_date TEXT, value TEXT
+            );
+            INSERT INTO entries VALUES ('E1', 'NetSales', 'CurrentYear', '2020-03-31', '10');
+            INSERT INTO entries VALUES ('E2', 'NetSales', 'CurrentYear', '2020-12-31', '20');
+            INSERT INTO entries VALUES ('E1', 'NetSales', 'CurrentYear', '2021-03-31', '30');
+            INSERT INTO entries VALUES ('E1', 'Assets', 'Prior1Year', '2019-03-31', '');",
+        )
+        .unwrap();
+        let cmd_opts = test_cmd_opts(db_path.to_str().unwrap(), "./parquet");
+
+        let columns = list_columns(&cmd_opts).unwrap();
+
+        assert_eq!(
+            columns,
+            vec![
+                "NetSales/CurrentYear/2020".to_string(),
+                "NetSales/CurrentYear/2021".to_string(),
+            ]
+        );
+    }
+
+    #[test]
+    fn get_data_returns_row_aligned_columns() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let db_path = temp_dir.path().join("edinet.db");
+        let conn = Connection::open(&db_path).unwrap();
+        conn.execute_batch(
+            "CREATE TABLE ids (edinet_id TEXT);
+            CREATE TABLE entries (
+                edinet_id TEXT, item TEXT, context TEXT, closing_date TEXT, value TEXT
+            );
+            INSERT INTO ids VALUES ('E1');
+            INSERT INTO ids VALUES ('E2');
+            INSERT INTO ids VALUES ('E3');
+            INSERT INTO entries VALUES ('E1', 'NetSales', 'CurrentYear', '2020-03-31', '10');
+            INSERT INTO entries VALUES ('E3', 'NetSales', 'CurrentYear', '2020-03-31', '30');
+            INSERT INTO entries VALUES ('E2', 'Assets', 'CurrentYear', '2020-03-31', '200');
+            INSERT INTO entries VALUES ('E3', 'Assets', 'CurrentYear', '2020-03-31', '300');",
+        )
+        .unwrap();
+        let cmd_opts = test_cmd_opts(db_path.to_str().unwrap(), temp_dir.path().to_str().unwrap());
+
+        let source = EdinetSource::new(cmd_opts);
+        let columns = ["NetSales/CurrentYear/2020", "Assets/CurrentYear/2020"];
+
+        let batches = collect(&source, "edinet", &columns, 1024).unwrap();
+
+        assert_eq!(batches.len(), 1);
+        assert_eq!(batches[0].num_columns(), 2);
+        assert_eq!(
+            batches[0].column(0).as_primitive::<Float32Type>(),
+            &Float32Array::from(vec![Some(10.0), None, Some(30.0)])
+        );
+        assert_eq!(
+            batches[0].column(1).as_primitive::<Float32Type>(),
+            &Float32Array::from(vec![None, Some(200.0), Some(300.0)])
+        );
+
+        let batches = collect(&source, "edinet", &columns, 2).unwrap();
+        assert_eq!(
+            batches.iter().map(|b| b.num_rows()).collect::<Vec<_>>(),
+            vec![2, 1]
+        );
+        assert_eq!(batches[1].num_columns(), 2);
+    }
+}
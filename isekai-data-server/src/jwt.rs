@@ -0,0 +1,370 @@
+// SPDX-FileCopyrightText: 2025 SEERA Networks Corporation <info@seera-networks.com>
+// SPDX-License-Identifier: MIT
+
+//! Validation of the JWTs sent in the `authorization` header against the
+//! issuer, audiences, algorithms and JWKS configured by `CmdOptions`.
+
+use jsonwebtoken::jwk::JwkSet;
+use jsonwebtoken::{decode, decode_header, Algorithm, DecodingKey, TokenData, Validation};
+use serde::{Deserialize, Deserializer, Serialize};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::RwLock;
+use tonic::Status;
+use tracing::{error, info, warn};
+
+use crate::CmdOptions;
+
+const DEFAULT_AUDIENCES: [&str; 2] = [
+    "https://yakserv.seera-networks.com",
+    "https://seera-networks.jp.auth0.com/userinfo",
+];
+
+/// Algorithms a JWT may be signed with.
+const SUPPORTED_ALGORITHMS: [Algorithm; 3] = [Algorithm::RS256, Algorithm::ES256, Algorithm::EdDSA];
+
+/// The shortest time between two fetches of the JWKS triggered by tokens with
+/// an unknown key ID, so that such tokens cannot flood the JWKS source.
+const MIN_REFRESH_INTERVAL: Duration = Duration::from_secs(30);
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Claims {
+    pub iss: String, // Issuer
+    pub sub: String, // Subject (whom token refers to)
+    #[serde(default, deserialize_with = "one_or_many")]
+    pub aud: Vec<String>, // Audience
+    pub iat: usize,  // Issued at (as UTC timestamp)
+    pub exp: usize,  // Expiration time (as UTC timestamp)
+    #[serde(default)]
+    pub scope: String,
+    #[serde(default)]
+    pub azp: String,
+    #[serde(default)]
+    pub groups: Vec<String>, // Groups the subject belongs to
+}
+
+/// Accepts the `aud` claim as a single string as well as a list.
+fn one_or_many<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<String>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany {
+        One(String),
+        Many(Vec<String>),
+    }
+    Ok(match OneOrMany::deserialize(deserializer)? {
+        OneOrMany::One(aud) => vec![aud],
+        OneOrMany::Many(aud) => aud,
+    })
+}
+
+/// Where the JWKS is loaded from.
+#[derive(Clone, Debug)]
+enum JwksSource {
+    Url(String),
+    File(PathBuf),
+}
+
+impl JwksSource {
+    fn parse(jwks: &str) -> Self {
+        if jwks.starts_with("https://") || jwks.starts_with("http://") {
+            JwksSource::Url(jwks.to_string())
+        } else {
+            JwksSource::File(PathBuf::from(jwks))
+        }
+    }
+
+    async fn load(&self) -> anyhow::Result<JwkSet> {
+        match self {
+            JwksSource::Url(url) => Ok(reqwest::get(url)
+                .await?
+                .error_for_status()?
+                .json::<JwkSet>()
+                .await?),
+            JwksSource::File(path) => {
+                let json = tokio::task::spawn_blocking({
+                    let path = path.clone();
+                    move || std::fs::read_to_string(path)
+                })
+                .await??;
+                Ok(serde_json::from_str(&json)?)
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for JwksSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            JwksSource::Url(url) => write!(f, "{}", url),
+            JwksSource::File(path) => write!(f, "{}", path.display()),
+        }
+    }
+}
+
+/// Validates JWTs with the keys of a JWKS, which is refreshed every
+/// `--jwks-refresh-secs` and when a token refers to a key it does not have.
+pub struct JwtValidator {
+    issuer: String,
+    audiences: Vec<String>,
+    algorithms: Vec<Algorithm>,
+    source: JwksSource,
+    keys: RwLock<HashMap<String, DecodingKey>>,
+    last_fetch: Mutex<Option<Instant>>,
+}
+
+impl JwtValidator {
+    /// Loads the JWKS configured by `cmd_opts`. A JWKS file that cannot be
+    /// read is an error, while a JWKS URL that cannot be fetched is logged and
+    /// fetched again when a token arrives, so that the server starts offline.
+    pub async fn from_cmd_opts(cmd_opts: &CmdOptions) -> anyhow::Result<Self> {
+        let algorithms = if cmd_opts.jwt_algorithm.is_empty() {
+            vec![Algorithm::RS256]
+        } else {
+            cmd_opts
+                .jwt_algorithm
+                .iter()
+                .map(|name| {
+                    name.parse::<Algorithm>()
+                        .ok()
+                        .filter(|alg| SUPPORTED_ALGORITHMS.contains(alg))
+                        .ok_or_else(|| {
+                            anyhow::anyhow!(
+                                "unsupported JWT algorithm {}: use RS256, ES256 or EdDSA",
+                                name
+                            )
+                        })
+                })
+                .collect::<anyhow::Result<Vec<_>>>()?
+        };
+        let audiences = if cmd_opts.jwt_audience.is_empty() {
+            DEFAULT_AUDIENCES
+                .iter()
+                .map(|aud| aud.to_string())
+                .collect()
+        } else {
+            cmd_opts.jwt_audience.clone()
+        };
+        let source = JwksSource::parse(&cmd_opts.jwks);
+        if let (true, JwksSource::Url(url)) = (cmd_opts.offline, &source) {
+            return Err(anyhow::anyhow!(
+                "--offline needs a local JWKS file, but --jwks is the URL {}",
+                url
+            ));
+        }
+        let validator = JwtValidator {
+            issuer: cmd_opts.jwt_issuer.clone(),
+            audiences,
+            algorithms,
+            source,
+            keys: RwLock::new(HashMap::new()),
+            last_fetch: Mutex::new(None),
+        };
+        match (&validator.source, validator.refresh().await) {
+            (_, Ok(())) => {}
+            (JwksSource::File(_), Err(e)) => return Err(e),
+            (JwksSource::Url(url), Err(e)) => error!("Failed to fetch JWKS from {}: {:?}", url, e),
+        }
+        Ok(validator)
+    }
+
+    /// Replaces the keys with the ones loaded from the JWKS source.
+    pub async fn refresh(&self) -> anyhow::Result<()> {
+        *self.last_fetch.lock().unwrap() = Some(Instant::now());
+        let jwk_set = self.source.load().await?;
+        let mut keys = HashMap::new();
+        for jwk in &jwk_set.keys {
+            let Some(kid) = &jwk.common.key_id else {
+                warn!("JWK without a key ID ignored in {}", self.source);
+                continue;
+            };
+            match DecodingKey::from_jwk(jwk) {
+                Ok(key) => {
+                    keys.insert(kid.clone(), key);
+                }
+                Err(e) => warn!("JWK {} ignored in {}: {:?}", kid, self.source, e),
+            }
+        }
+        info!("{} JWKs loaded from {}", keys.len(), self.source);
+        *self.keys.write().await = keys;
+        Ok(())
+    }
+
+    /// Returns the key with ID `kid`, refreshing the JWKS once if it is
+    /// unknown and the last fetch is old enough.
+    async fn find_key(&self, kid: &str) -> Option<DecodingKey> {
+        if let Some(key) = self.keys.read().await.get(kid) {
+            return Some(key.clone());
+        }
+        {
+            let mut last_fetch = self.last_fetch.lock().unwrap();
+            if last_fetch.is_some_and(|last_fetch| last_fetch.elapsed() < MIN_REFRESH_INTERVAL) {
+                return None;
+            }
+            *last_fetch = Some(Instant::now());
+        }
+        if let Err(e) = self.refresh().await {
+            error!("Failed to refresh JWKS from {}: {:?}", self.source, e);
+            return None;
+        }
+        self.keys.read().await.get(kid).cloned()
+    }
+
+    /// Checks the signature, `iss`, `aud` and expiry of `jwt` and returns its
+    /// claims.
+    pub async fn validate(&self, jwt: &str) -> Result<Claims, Status> {
+        let header = decode_header(jwt)
+            .map_err(|_| Status::unauthenticated("jwt header should be decoded"))?;
+        if !self.algorithms.contains(&header.alg) {
+            return Err(Status::unauthenticated(format!(
+                "jwt is signed with a disallowed algorithm {:?}",
+                header.alg
+            )));
+        }
+        let kid = header
+            .kid
+            .as_ref()
+            .ok_or_else(|| Status::unauthenticated("jwt header should have a kid"))?;
+        let key = self
+            .find_key(kid)
+            .await
+            .ok_or_else(|| Status::unauthenticated("jwt refer to a unknown key id"))?;
+        let mut validation = Validation::new(header.alg);
+        validation.set_audience(&self.audiences);
+        validation.set_issuer(&[&self.issuer]);
+        let decoded_token: TokenData<Claims> = decode::<Claims>(jwt, &key, &validation)
+            .map_err(|x| Status::unauthenticated(format!("jwt should be valid: {:?}", x)))?;
+        Ok(decoded_token.claims)
+    }
+}
+
+/// Refreshes the JWKS every `--jwks-refresh-secs`.
+pub async fn refresh_jwks(validator: Arc<JwtValidator>, cmd_opts: CmdOptions) {
+    let period = Duration::from_secs(cmd_opts.jwks_refresh_secs.max(1));
+    let mut interval = tokio::time::interval_at(tokio::time::Instant::now() + period, period);
+    loop {
+        interval.tick().await;
+        if let Err(e) = validator.refresh().await {
+            error!("Failed to refresh JWKS from {}: {:?}", validator.source, e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::JwtValidator;
+    use crate::CmdOptions;
+    use base64::Engine;
+    use jsonwebtoken::{encode, Algorithm, EncodingKey, Header};
+    use openssl::pkey::PKey;
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    /// Returns an Ed25519 signing key and its JWK with key ID `kid`.
+    fn ed25519_key(kid: &str) -> (EncodingKey, serde_json::Value) {
+        let pkey = PKey::generate_ed25519().unwrap();
+        let encoding_key =
+            EncodingKey::from_ed_pem(&pkey.private_key_to_pem_pkcs8().unwrap()).unwrap();
+        let x =
+            base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(pkey.raw_public_key().unwrap());
+        let jwk = serde_json::json!({
+            "kty": "OKP",
+            "crv": "Ed25519",
+            "alg": "EdDSA",
+            "kid": kid,
+            "x": x,
+        });
+        (encoding_key, jwk)
+    }
+
+    fn token(key: &EncodingKey, kid: &str, iss: &str, aud: &str) -> String {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        let mut header = Header::new(Algorithm::EdDSA);
+        header.kid = Some(kid.to_string());
+        let claims = serde_json::json!({
+            "iss": iss,
+            "sub": "auth0|user",
+            "aud": aud,
+            "iat": now,
+            "exp": now + 60,
+            "groups": ["analysts"],
+        });
+        encode(&header, &claims, key).unwrap()
+    }
+
+    #[test]
+    fn tokens_are_validated_against_a_local_jwks() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let jwks_path = temp_dir.path().join("jwks.json");
+        let (first_key, first_jwk) = ed25519_key("first");
+        std::fs::write(
+            &jwks_path,
+            serde_json::json!({ "keys": [first_jwk] }).to_string(),
+        )
+        .unwrap();
+        let mut cmd_opts = test_cmd_opts(jwks_path.to_str().unwrap());
+        cmd_opts.jwt_algorithm = vec!["EdDSA".to_string()];
+        cmd_opts.jwt_audience = vec!["https://data.example.com".to_string()];
+
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        runtime.block_on(async {
+            let validator = JwtValidator::from_cmd_opts(&cmd_opts).await.unwrap();
+            let issuer = "https://idp.example.com/";
+            let claims = validator
+                .validate(&token(
+                    &first_key,
+                    "first",
+                    issuer,
+                    "https://data.example.com",
+                ))
+                .await
+                .unwrap();
+            assert_eq!(claims.sub, "auth0|user");
+            assert_eq!(claims.aud, vec!["https://data.example.com".to_string()]);
+            assert_eq!(claims.groups, vec!["analysts".to_string()]);
+
+            let wrong_issuer = token(
+                &first_key,
+                "first",
+                "https://other.example.com/",
+                "https://data.example.com",
+            );
+            assert!(validator.validate(&wrong_issuer).await.is_err());
+            let wrong_audience = token(&first_key, "first", issuer, "https://other.example.com");
+            assert!(validator.validate(&wrong_audience).await.is_err());
+
+            // A token signed with a key added to the JWKS later is accepted
+            // once the JWKS is refreshed for its unknown key ID.
+            let (second_key, second_jwk) = ed25519_key("second");
+            std::fs::write(
+                &jwks_path,
+                serde_json::json!({ "keys": [first_jwk, second_jwk] }).to_string(),
+            )
+            .unwrap();
+            let rotated = token(&second_key, "second", issuer, "https://data.example.com");
+            *validator.last_fetch.lock().unwrap() = None;
+            assert_eq!(
+                validator.validate(&rotated).await.unwrap().sub,
+                "auth0|user"
+            );
+        });
+
+        cmd_opts.jwt_algorithm = vec!["HS256".to_string()];
+        assert!(runtime
+            .block_on(JwtValidator::from_cmd_opts(&cmd_opts))
+            .is_err());
+    }
+
+    fn test_cmd_opts(jwks: &str) -> CmdOptions {
+        CmdOptions {
+            jwks: jwks.to_string(),
+            ..crate::test_cmd_opts()
+        }
+    }
+}
@@ -6,30 +6,36 @@ use base64::Engine;
 use futures::stream::BoxStream;
 use futures::{StreamExt, TryStreamExt};
 use http::header::HeaderName;
-use jsonwebtoken::{decode, decode_header, Algorithm, TokenData, Validation};
-use jwks::Jwks;
-use serde::{Deserialize, Serialize};
 use tonic::transport::Server;
 use tonic::{Request, Response, Status, Streaming};
 use tonic_web::GrpcWebLayer;
+use tower::Layer;
 use tower_http::cors::{AllowOrigin, CorsLayer};
 
+use arrow::ipc::writer::IpcWriteOptions;
+use arrow::record_batch::RecordBatch;
 use arrow_flight::decode::{DecodedPayload, FlightDataDecoder};
 use arrow_flight::encode::FlightDataEncoderBuilder;
 use arrow_flight::error::FlightError;
+use arrow_flight::flight_descriptor::DescriptorType;
 use arrow_flight::{
     flight_service_server::FlightService, flight_service_server::FlightServiceServer, Action,
-    ActionType, Criteria, Empty, FlightData, FlightDescriptor, FlightInfo, HandshakeRequest,
-    HandshakeResponse, PollInfo, PutResult, SchemaResult, Ticket,
+    ActionType, Criteria, Empty, FlightData, FlightDescriptor, FlightEndpoint, FlightInfo,
+    HandshakeRequest, HandshakeResponse, PollInfo, PutResult, SchemaAsIpc, SchemaResult, Ticket,
 };
+use arrow_schema::{Field, Schema, SchemaRef};
 
-use isekai_utils::module::GetTicket;
+use isekai_utils::module::{
+    DeleteTargetArgs, GetTicket, GrantTargetArgs, LabelTargetArgs, PutDescriptor,
+};
+use isekai_utils::policy::PolicyFile;
 use rand::rngs::OsRng;
 use rand::RngCore;
 use std::collections::HashMap;
 use std::io::Write;
+use std::path::PathBuf;
 use std::sync::{Arc, Mutex};
-use std::time::{Duration, Instant};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 use tracing::{debug, error, info};
 use tracing_subscriber::{fmt, EnvFilter};
@@ -42,36 +48,41 @@ const DEFAULT_ALLOW_HEADERS: [&str; 4] =
 const HANDSHAKE_TOKEN_TTL: Duration = Duration::from_secs(10 * 60);
 const MAX_VALID_TOKENS: usize = 1024;
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
-pub struct Claims {
-    iss: String,      // Issuer
-    sub: String,      // Subject (whom token refers to)
-    aud: Vec<String>, // Audience
-    iat: usize,       // Issued at (as UTC timestamp)
-    exp: usize,       // Expiration time (as UTC timestamp)
-    scope: String,
-    azp: String,
-}
-
+mod acl;
+mod appraisal;
 mod auth;
+mod crypto;
 mod csv;
 mod edinet;
+mod jwt;
+mod source;
 mod storage;
 
+use acl::{Acl, Operation};
+use appraisal::Appraiser;
+use auth::{AuthLayer, Authenticator, Subject, TokenBinding};
+use jwt::JwtValidator;
+use snpguest::verify::certificate_chain::CertificateRevoked;
+use snpguest::verify::VerificationFailed;
+use snpguest::verify2::CertSource;
+use source::{Registry, STORAGE_DATASET};
+
 #[derive(Default)]
 struct ValidTokenStore {
-    tokens: HashMap<String, Instant>,
+    /// The expiry and the binding of each token.
+    tokens: HashMap<String, (Instant, TokenBinding)>,
 }
 
 impl ValidTokenStore {
-    fn insert(&mut self, token: String, now: Instant) {
+    fn insert(&mut self, token: String, binding: TokenBinding, now: Instant) {
         self.prune(now);
-        self.tokens.insert(token, now + HANDSHAKE_TOKEN_TTL);
+        self.tokens
+            .insert(token, (now + HANDSHAKE_TOKEN_TTL, binding));
         if self.tokens.len() > MAX_VALID_TOKENS {
             if let Some(expired_token) = self
                 .tokens
                 .iter()
-                .min_by_key(|(_, expires_at)| *expires_at)
+                .min_by_key(|(_, (expires_at, _))| *expires_at)
                 .map(|(token, _)| token.clone())
             {
                 self.tokens.remove(&expired_token);
@@ -79,22 +90,267 @@ impl ValidTokenStore {
         }
     }
 
-    fn is_valid(&mut self, token: &str, now: Instant) -> bool {
+    /// Returns the binding of `token` if it has not expired.
+    fn get(&mut self, token: &str, now: Instant) -> Option<TokenBinding> {
         self.prune(now);
-        self.tokens.contains_key(token)
+        self.tokens.get(token).map(|(_, binding)| binding.clone())
+    }
+
+    /// Binds `token` to the first subject that uses it. Returns false if it
+    /// has expired or is already bound to another subject.
+    fn bind_subject(&mut self, token: &str, subject: &str) -> bool {
+        match self.tokens.get_mut(token) {
+            Some((_, binding)) => match &binding.subject {
+                Some(bound_subject) => bound_subject == subject,
+                None => {
+                    binding.subject = Some(subject.to_string());
+                    true
+                }
+            },
+            None => false,
+        }
     }
 
     fn prune(&mut self, now: Instant) {
-        self.tokens.retain(|_, expires_at| *expires_at > now);
+        self.tokens.retain(|_, (expires_at, _)| *expires_at > now);
     }
 }
 
 #[derive(Clone)]
 pub struct FlightServiceImpl {
     cmd_opts: CmdOptions,
-    jwks: Jwks,
+    sources: Registry,
+    acl: Option<Arc<Acl>>,
     valid_tokens: Arc<Mutex<ValidTokenStore>>,
     server_ld: Option<[u8; 48]>,
+    appraiser: Option<Arc<Appraiser>>,
+    cert_source: CertSource,
+}
+
+impl FlightServiceImpl {
+    /// Checks `--acl-file` for `operation` by `subject` on `column_names` of
+    /// the dataset of `target`, or on the whole dataset if none are given.
+    fn authorize(
+        &self,
+        subject: &Subject,
+        operation: Operation,
+        target: &str,
+        column_names: &[&str],
+    ) -> Result<(), Status> {
+        match &self.acl {
+            Some(acl) => acl.check(
+                subject,
+                operation,
+                self.sources.dataset(target),
+                column_names,
+            ),
+            None => Ok(()),
+        }
+    }
+
+    /// Returns the policy attached to a single column for `subject`.
+    fn get_column_policy(
+        &self,
+        subject: &Subject,
+        target: &str,
+        column_name: &str,
+    ) -> Result<String, Status> {
+        match self
+            .sources
+            .resolve(target)?
+            .get_policy(subject, target, column_name)
+        {
+            Ok(policy) => {
+                info!(
+                    "subject: {}, measurement: {}, policy: {}",
+                    subject.name,
+                    subject.measurement_hex(),
+                    policy
+                );
+                Ok(policy)
+            }
+            Err(e) => {
+                error!("failed to get policy: {:?}", e);
+                Err(e)
+            }
+        }
+    }
+
+    /// Returns the policy attached to the data of `ticket` for `subject`.
+    /// When several columns are requested, their policies are merged so that
+    /// the result covers every column.
+    fn get_policy(&self, subject: &Subject, ticket: &GetTicket) -> Result<String, Status> {
+        let columns = ticket.columns();
+        if let [column_name] = columns.as_slice() {
+            return self.get_column_policy(subject, &ticket.target, column_name);
+        }
+
+        let mut merged = PolicyFile::new();
+        for column_name in columns {
+            let policy = self.get_column_policy(subject, &ticket.target, column_name)?;
+            let policy: PolicyFile = serde_json::from_str(&policy)
+                .map_err(|e| Status::internal(format!("invalid policy: {:?}", e)))?;
+            merged.merge(policy);
+        }
+        Ok(merged.to_json())
+    }
+
+    /// Returns the schema and the row count of the data of `ticket` without
+    /// reading the data itself where the source allows it.
+    fn get_schema_and_rows(
+        &self,
+        subject: &Subject,
+        ticket: &GetTicket,
+    ) -> Result<(SchemaRef, i64), Status> {
+        self.sources
+            .resolve(&ticket.target)?
+            .schema(subject, &ticket.target, &ticket.columns())
+    }
+
+    /// Starts writing the upload of `subject` on a blocking thread, appending
+    /// to the target of `descriptor` when given and creating a new target
+    /// with its TTL otherwise. Batches
+    /// sent to the returned channel are inserted as they arrive, and the
+    /// upload is committed once the channel is closed. Sending an error rolls
+    /// the upload back. The writer returns the target written to.
+    fn start_store(
+        &self,
+        subject: &str,
+        descriptor: PutDescriptor,
+        schema: SchemaRef,
+        policy: Option<String>,
+    ) -> (
+        tokio::sync::mpsc::Sender<Result<RecordBatch, Status>>,
+        tokio::task::JoinHandle<anyhow::Result<String>>,
+    ) {
+        let cmd_opts = self.cmd_opts.clone();
+        let subject = subject.to_string();
+        let (tx, mut rx) = tokio::sync::mpsc::channel(2);
+        let handle = tokio::task::spawn_blocking(move || {
+            let batches = std::iter::from_fn(|| rx.blocking_recv())
+                .map(|batch| batch.map_err(anyhow::Error::from));
+            match descriptor.target {
+                Some(target) => {
+                    storage::append_data(&cmd_opts, &subject, &target, schema, batches)?;
+                    Ok(target)
+                }
+                None => storage::store_data(
+                    &cmd_opts,
+                    &subject,
+                    schema,
+                    policy,
+                    descriptor.ttl_secs,
+                    batches,
+                ),
+            }
+        });
+        (tx, handle)
+    }
+}
+
+/// Resolves a `FlightDescriptor` to the ticket it refers to. A command
+/// descriptor carries a JSON `GetTicket`, and a path descriptor names
+/// `[target, column_name, ...]`; `list_flights` returns one column per path.
+fn ticket_from_descriptor(descriptor: &FlightDescriptor) -> Result<GetTicket, Status> {
+    match descriptor.r#type() {
+        DescriptorType::Cmd => serde_json::from_slice(&descriptor.cmd)
+            .map_err(|e| Status::invalid_argument(format!("invalid ticket command: {:?}", e))),
+        DescriptorType::Path => match descriptor.path.as_slice() {
+            [target, columns @ ..] if !columns.is_empty() => Ok(GetTicket {
+                target: target.clone(),
+                column_name: columns[0].clone(),
+                columns: columns.to_vec(),
+            }),
+            _ => Err(Status::invalid_argument(
+                "descriptor path must be [target, column_name, ...]",
+            )),
+        },
+        DescriptorType::Unknown => Err(Status::invalid_argument("unknown descriptor type")),
+    }
+}
+
+/// Parses a `do_put` descriptor. A path descriptor names `[target]` to append
+/// to, and a command descriptor carries a JSON `PutDescriptor`.
+fn put_descriptor(descriptor: &FlightDescriptor) -> Result<PutDescriptor, Status> {
+    match (descriptor.r#type(), descriptor.path.as_slice()) {
+        (DescriptorType::Path, [target]) => Ok(PutDescriptor {
+            target: Some(target.clone()),
+            ..Default::default()
+        }),
+        (DescriptorType::Cmd, _) => serde_json::from_slice(&descriptor.cmd)
+            .map_err(|e| Status::invalid_argument(format!("invalid put command: {:?}", e))),
+        _ => Err(Status::invalid_argument(
+            "do_put descriptor must be a path of [target] or a command",
+        )),
+    }
+}
+
+/// The actions served by `do_action`, with their descriptions.
+const ACTIONS: [(&str, &str); 5] = [
+    (
+        "list_targets",
+        "List the stored targets of the subject with their label, creation time and row count",
+    ),
+    (
+        "delete_target",
+        "Delete a stored target given as {\"target\": ...}",
+    ),
+    (
+        "label_target",
+        "Set or remove the label of a stored target given as {\"target\": ..., \"label\": ...}",
+    ),
+    (
+        "grant_target",
+        "Let another subject or group read a stored target, given as {\"target\": ..., \"grantee\": {\"subject\": ...} or {\"group\": ...}}",
+    ),
+    (
+        "revoke_target",
+        "Remove a grant made with grant_target, given in the same form",
+    ),
+];
+
+/// Parses the JSON body of an action.
+fn action_args<T: serde::de::DeserializeOwned>(action: &Action) -> Result<T, Status> {
+    serde_json::from_slice(&action.body).map_err(|e| {
+        Status::invalid_argument(format!("invalid {} arguments: {:?}", action.r#type, e))
+    })
+}
+
+/// Converts a storage error into a status, keeping the status of errors that
+/// already carry one, such as a missing target or an exceeded limit.
+fn storage_status(message: &str, e: anyhow::Error) -> Status {
+    match e.downcast::<Status>() {
+        Ok(status) => status,
+        Err(e) => {
+            error!("{}: {:?}", message, e);
+            Status::internal(format!("{}: {:?}", message, e))
+        }
+    }
+}
+
+/// Builds the `FlightInfo` advertised for a single column of a target.
+fn column_flight_info(
+    target: &str,
+    column_name: &str,
+    field: Option<&Field>,
+) -> Result<FlightInfo, Status> {
+    let ticket = GetTicket {
+        target: target.to_string(),
+        column_name: column_name.to_string(),
+        columns: Vec::new(),
+    };
+    let info = FlightInfo::new()
+        .with_descriptor(FlightDescriptor::new_path(vec![
+            target.to_string(),
+            column_name.to_string(),
+        ]))
+        .with_endpoint(FlightEndpoint::new().with_ticket(Ticket::new(ticket.to_json())));
+    match field {
+        Some(field) => info
+            .try_with_schema(&Schema::new(vec![field.clone()]))
+            .map_err(|e| Status::internal(format!("failed to encode schema: {:?}", e))),
+        None => Ok(info),
+    }
 }
 
 #[tonic::async_trait]
@@ -117,6 +373,7 @@ impl FlightService for FlightServiceImpl {
         // } else {
         //     println!("No client certificate presented");
         // }
+        let client_cert = auth::client_cert_fingerprint(request.extensions());
         let mut inbound = request.into_inner();
 
         let mut cnt = 0;
@@ -124,6 +381,8 @@ impl FlightService for FlightServiceImpl {
         let valid_tokens = self.valid_tokens.clone();
         let mut challenge = [0u8; 64];
         let server_ld = self.server_ld.clone();
+        let appraiser = self.appraiser.clone();
+        let cert_source = self.cert_source.clone();
         let output_stream = async_stream::try_stream! {
             while let Some(handshake_request) = inbound.next().await {
                 let req = handshake_request?;
@@ -146,16 +405,27 @@ impl FlightService for FlightServiceImpl {
                     let mut att_file = tempfile::NamedTempFile::new()?;
                     att_file.write(&req.payload)?;
                     let att_path = att_file.path().to_path_buf();
-                    let certs_dir = std::path::PathBuf::from("/tmp/ext-grpc-server/snpguest/certs");
-                    let att_res = snpguest::verify2::fetch_and_verify_async(certs_dir, att_path, true).await;
+                    let att_res = snpguest::verify2::fetch_and_verify_async(&cert_source, att_path, true).await;
                     debug!("handshake rquest2 done");
+                    if let Some(failed) = att_res.as_ref().err().and_then(|e| e.downcast_ref::<VerificationFailed>()) {
+                        if let Ok(json) = failed.report.to_json() {
+                            error!("failed verification of attestation report: {}", json);
+                        }
+                    }
                     let att_report = match att_res {
+                        Err(e) if e.chain().any(|cause| cause.is::<CertificateRevoked>()) => {
+                            error!("AMD certificate of attestation report is revoked: {:#}", e);
+                            Err(Status::permission_denied(format!("AMD certificate of attestation report is revoked: {:#}", e)))
+                        },
                         Err(e) => {
                             error!("failed to verify attestation report: {:#?}", e);
                             Err(Status::unauthenticated(format!("failed to verify attestation report: {:#?}", e)))
                         },
-                        Ok(r) => {
-                            info!("successfully verified attestation report");
+                        Ok((r, verification)) => {
+                            info!("successfully verified attestation report: {} checks passed", verification.checks.len());
+                            if let Ok(json) = verification.to_json() {
+                                debug!("verification of attestation report: {}", json);
+                            }
                             Ok(r)
                         }
                     }?;
@@ -163,6 +433,15 @@ impl FlightService for FlightServiceImpl {
                         error!("attestation report data does not match challenge");
                         return Err(Status::unauthenticated("attestation report data does not match challenge"))?
                     }
+                    if let Some(appraiser) = &appraiser {
+                        match appraiser.appraise(&att_report) {
+                            Ok(label) => info!("attestation report passed appraisal, measurement: {}", label.as_deref().unwrap_or("any")),
+                            Err(reason) => {
+                                error!("attestation report failed appraisal: {}", reason);
+                                return Err(Status::unauthenticated(format!("attestation report failed appraisal: {}", reason)))?
+                            }
+                        }
+                    }
                     if let Some(ld) = server_ld {
                         if att_report.measurement != ld {
                             error!("launch digest does not match expected value");
@@ -177,10 +456,16 @@ impl FlightService for FlightServiceImpl {
                     let token = token.into_iter()
                         .map(|x| format!("{:02x}", x))
                         .collect::<String>();
+                    let binding = TokenBinding {
+                        measurement: att_report.measurement,
+                        chip_id: att_report.chip_id,
+                        client_cert: client_cert.clone(),
+                        subject: None,
+                    };
                     valid_tokens
                         .lock()
                         .unwrap()
-                        .insert(token.clone(), Instant::now());
+                        .insert(token.clone(), binding, Instant::now());
                     HandshakeResponse {
                         protocol_version: 1,
                         payload: bytes::Bytes::from(token),
@@ -199,23 +484,98 @@ impl FlightService for FlightServiceImpl {
 
     async fn list_flights(
         &self,
-        _request: Request<Criteria>,
+        request: Request<Criteria>,
     ) -> Result<Response<Self::ListFlightsStream>, Status> {
-        Err(Status::unimplemented("Implement list_flights"))
+        debug!("list_flights");
+
+        let subject = auth::subject(&request)?;
+
+        // A non-empty criteria expression restricts the listing to one target.
+        let expression = request.into_inner().expression;
+        let target_filter = if expression.is_empty() {
+            None
+        } else {
+            Some(String::from_utf8_lossy(&expression).to_string())
+        };
+        let wants = |target: &str| target_filter.as_deref().is_none_or(|t| t == target);
+
+        let listable = |target: &str, column_name: &str| {
+            self.acl.as_ref().is_none_or(|acl| {
+                acl.allows(
+                    &subject,
+                    Operation::List,
+                    self.sources.dataset(target),
+                    Some(column_name),
+                )
+            })
+        };
+
+        let mut infos = Vec::new();
+        let mut hidden = 0;
+        for column in self.sources.list(&subject)? {
+            if !wants(&column.target) {
+                continue;
+            }
+            if !listable(&column.target, &column.column_name) {
+                hidden += 1;
+                continue;
+            }
+            infos.push(column_flight_info(
+                &column.target,
+                &column.column_name,
+                column.field.as_ref(),
+            )?);
+        }
+        if hidden > 0 {
+            info!(
+                "subject: {}, {} columns hidden by the ACL",
+                subject.name, hidden
+            );
+        }
+        info!("subject: {}, listed {} flights", subject.name, infos.len());
+
+        let stream = futures::stream::iter(infos.into_iter().map(Ok));
+        Ok(Response::new(Box::pin(stream)))
     }
 
     async fn get_flight_info(
         &self,
-        _request: Request<FlightDescriptor>,
+        request: Request<FlightDescriptor>,
     ) -> Result<Response<FlightInfo>, Status> {
-        Err(Status::unimplemented("Implement get_flight_info"))
+        debug!("get_flight_info");
+
+        let subject = auth::subject(&request)?;
+
+        let descriptor = request.into_inner();
+        let ticket = ticket_from_descriptor(&descriptor)?;
+        self.authorize(&subject, Operation::List, &ticket.target, &ticket.columns())?;
+        let (schema, num_rows) = self.get_schema_and_rows(&subject, &ticket)?;
+        let policy = self.get_policy(&subject, &ticket)?;
+
+        let info = FlightInfo::new()
+            .try_with_schema(&schema)
+            .map_err(|e| Status::internal(format!("failed to encode schema: {:?}", e)))?
+            .with_descriptor(descriptor)
+            .with_endpoint(FlightEndpoint::new().with_ticket(Ticket::new(ticket.to_json())))
+            .with_total_records(num_rows)
+            .with_app_metadata(policy.into_bytes());
+        Ok(Response::new(info))
     }
 
     async fn get_schema(
         &self,
-        _request: Request<FlightDescriptor>,
+        request: Request<FlightDescriptor>,
     ) -> Result<Response<SchemaResult>, Status> {
-        Err(Status::unimplemented("Implement get_schema"))
+        debug!("get_schema");
+
+        let subject = auth::subject(&request)?;
+
+        let ticket = ticket_from_descriptor(request.get_ref())?;
+        self.authorize(&subject, Operation::List, &ticket.target, &ticket.columns())?;
+        let (schema, _) = self.get_schema_and_rows(&subject, &ticket)?;
+        let result = SchemaResult::try_from(SchemaAsIpc::new(&schema, &IpcWriteOptions::default()))
+            .map_err(|e| Status::internal(format!("failed to encode schema: {:?}", e)))?;
+        Ok(Response::new(result))
     }
 
     async fn do_get(
@@ -224,135 +584,37 @@ impl FlightService for FlightServiceImpl {
     ) -> Result<Response<Self::DoGetStream>, Status> {
         debug!("do_get");
 
-        let token = request
-            .metadata()
-            .get("x-yak-authorization")
-            .ok_or_else(|| Status::unauthenticated("No token"))
-            .and_then(|value| {
-                if value.len() >= 7 {
-                    let value = value
-                        .to_str()
-                        .map_err(|_| Status::unauthenticated("invalid char"))?;
-                    if &value[0..7] == "Bearer " {
-                        Ok(value[7..].to_string())
-                    } else {
-                        Err(Status::unauthenticated("Invalid format"))
-                    }
-                } else {
-                    Err(Status::unauthenticated("too short"))
-                }
-            })?;
-        if !self
-            .valid_tokens
-            .lock()
-            .unwrap()
-            .is_valid(&token, Instant::now())
-        {
-            error!("Invalid token");
-            return Err(Status::unauthenticated("Invalid token"));
-        }
-
-        let jwt = request
-            .metadata()
-            .get("authorization")
-            .ok_or_else(|| Status::unauthenticated("No token"))
-            .and_then(|value| {
-                if value.len() >= 7 {
-                    let value = value
-                        .to_str()
-                        .map_err(|_| Status::unauthenticated("invalid char"))?;
-                    if &value[0..7] == "Bearer " {
-                        Ok(value[7..].to_string())
-                    } else {
-                        Err(Status::unauthenticated("Invalid format"))
-                    }
-                } else {
-                    Err(Status::unauthenticated("too short"))
-                }
-            });
-        let subject = if let Ok(jwt) = jwt {
-            let header = decode_header(&jwt)
-                .map_err(|_| Status::unauthenticated("jwt header should be decoded"))?;
-            let kid = header
-                .kid
-                .as_ref()
-                .ok_or_else(|| Status::unauthenticated("jwt header should have a kid"))?;
-            let jwk = self
-                .jwks
-                .keys
-                .get(kid)
-                .ok_or_else(|| Status::unauthenticated("jwt refer to a unknown key id"))?;
-            let mut validation = Validation::new(Algorithm::RS256);
-            validation.set_audience(&[
-                "https://yakserv.seera-networks.com",
-                "https://seera-networks.jp.auth0.com/userinfo",
-            ]);
-            let decoded_token: TokenData<Claims> =
-                decode::<Claims>(&jwt, &jwk.decoding_key, &validation).map_err(|x| {
-                    Status::unauthenticated(format!("jwt should be valid: {:?}", x))
-                })?;
-            decoded_token.claims.sub.replace("|", "_")
-        } else if self.cmd_opts.allow_test_subject {
-            "test".to_string()
-        } else {
-            return Err(Status::unauthenticated("No JWT provided"));
-        };
-
-        if !auth::authenticate_subject(&self.cmd_opts, &subject) {
-            return Err(Status::unauthenticated(format!(
-                "Unauthorized subject: {}",
-                subject
-            )));
-        }
+        let subject = auth::subject(&request)?;
 
         let ticket = GetTicket::from_json(
             &String::from_utf8_lossy(&request.into_inner().ticket).to_string(),
         );
-        let (input_stream, policy) = if ticket.target == "system" {
-            let batches = if self.cmd_opts.csv_file.is_some() {
-                csv::get_data(&self.cmd_opts, &ticket.column_name)?
-            } else if self.cmd_opts.edinet_db.is_some() {
-                edinet::get_data(&self.cmd_opts, &ticket.column_name)?
-            } else {
-                return Err(Status::internal("no data source"));
-            };
-            let input_stream = futures::stream::iter(batches.into_iter().map(Ok));
-
-            let res = if self.cmd_opts.csv_file.is_some() {
-                csv::get_policy(&self.cmd_opts, &subject, &ticket.column_name)
-            } else {
-                edinet::get_policy(&self.cmd_opts, &subject, &ticket.column_name)
-            };
-
-            let policy = match res {
-                Ok(policy) => {
-                    info!("subject: {}, policy: {}", subject, policy);
-                    policy
-                }
-                Err(e) => {
-                    error!("failed to get policy: {:?}", e);
-                    return Err(Status::internal(format!("failed to get policy: {:?}", e)));
-                }
-            };
-            (input_stream, policy)
-        } else {
-            let batches = storage::get_data(
-                &self.cmd_opts,
-                &subject,
-                &ticket.target,
-                &ticket.column_name,
-            )
-            .map_err(|e| Status::internal(format!("failed to get data: {:?}", e)))?;
-            let input_stream = futures::stream::iter(batches.into_iter().map(Ok));
-            let policy = storage::get_policy(
-                &self.cmd_opts,
+        self.authorize(&subject, Operation::Get, &ticket.target, &ticket.columns())?;
+        let source = self.sources.resolve(&ticket.target)?;
+        let policy = self.get_policy(&subject, &ticket)?;
+
+        // Batches are read on a blocking thread and handed over one at a time.
+        // When the client goes away the receiver is dropped, the send fails and
+        // the source stops reading.
+        let batch_size = self.cmd_opts.batch_size.max(1);
+        let (tx, rx) = tokio::sync::mpsc::channel(2);
+        tokio::task::spawn_blocking(move || {
+            let res = source.get_data(
                 &subject,
                 &ticket.target,
-                &ticket.column_name,
-            )
-            .map_err(|e| Status::internal(format!("failed to get policy: {:?}", e)))?;
-            (input_stream, policy)
-        };
+                &ticket.columns(),
+                batch_size,
+                &mut |batch| tx.blocking_send(Ok(batch)).is_ok(),
+            );
+            if let Err(e) = res {
+                error!("failed to get data: {:?}", e);
+                let _ = tx.blocking_send(Err(e));
+            }
+        });
+        let input_stream = futures::stream::unfold(rx, |mut rx| async move {
+            rx.recv().await.map(|item| (item, rx))
+        })
+        .map_err(FlightError::from);
         let flight_data_stream = FlightDataEncoderBuilder::new()
             .with_metadata(policy.as_bytes().to_vec().into())
             .build(input_stream)
@@ -367,126 +629,101 @@ impl FlightService for FlightServiceImpl {
     ) -> Result<Response<Self::DoPutStream>, Status> {
         debug!("do_put");
 
-        let token = request
-            .metadata()
-            .get("x-yak-authorization")
-            .ok_or_else(|| Status::unauthenticated("No token"))
-            .and_then(|value| {
-                if value.len() >= 7 {
-                    let value = value
-                        .to_str()
-                        .map_err(|_| Status::unauthenticated("invalid char"))?;
-                    if &value[0..7] == "Bearer " {
-                        Ok(value[7..].to_string())
-                    } else {
-                        Err(Status::unauthenticated("Invalid format"))
-                    }
-                } else {
-                    Err(Status::unauthenticated("too short"))
-                }
-            })?;
-        if !self
-            .valid_tokens
-            .lock()
-            .unwrap()
-            .is_valid(&token, Instant::now())
-        {
-            return Err(Status::unauthenticated("Invalid token"));
-        }
+        let subject = auth::subject(&request)?;
 
-        let jwt = request
-            .metadata()
-            .get("authorization")
-            .ok_or_else(|| Status::unauthenticated("No token"))
-            .and_then(|value| {
-                if value.len() >= 7 {
-                    let value = value
-                        .to_str()
-                        .map_err(|_| Status::unauthenticated("invalid char"))?;
-                    if &value[0..7] == "Bearer " {
-                        Ok(value[7..].to_string())
-                    } else {
-                        Err(Status::unauthenticated("Invalid format"))
-                    }
-                } else {
-                    Err(Status::unauthenticated("too short"))
-                }
-            });
-
-        let subject = if let Ok(jwt) = jwt {
-            let header = decode_header(&jwt)
-                .map_err(|_| Status::unauthenticated("jwt header should be decoded"))?;
-            let kid = header
-                .kid
-                .as_ref()
-                .ok_or_else(|| Status::unauthenticated("jwt header should have a kid"))?;
-            let jwk = self
-                .jwks
-                .keys
-                .get(kid)
-                .ok_or_else(|| Status::unauthenticated("jwt refer to a unknown key id"))?;
-            let mut validation = Validation::new(Algorithm::RS256);
-            validation.set_audience(&[
-                "https://yakserv.seera-networks.com",
-                "https://seera-networks.jp.auth0.com/userinfo",
-            ]);
-            let decoded_token: TokenData<Claims> =
-                decode::<Claims>(&jwt, &jwk.decoding_key, &validation).map_err(|x| {
-                    Status::unauthenticated(format!("jwt should be valid: {:?}", x))
-                })?;
-            decoded_token.claims.sub.replace("|", "_")
-        } else if self.cmd_opts.allow_test_subject {
-            "test".to_string()
-        } else {
-            return Err(Status::unauthenticated("No JWT provided"));
-        };
-
-        if !auth::authenticate_subject(&self.cmd_opts, &subject) {
-            return Err(Status::unauthenticated(format!(
-                "Unauthorized subject: {}",
-                subject
-            )));
-        }
-
-        let mut policy = None;
-        let mut schema = None;
-        let mut batches = Vec::new();
         let mut stream = FlightDataDecoder::new(request.into_inner().map_err(FlightError::from));
-        while let Some(data) = stream.next().await {
-            let data = data.map_err(|e| {
-                error!("Failed to decode FlightData: {:?}", e);
-                Status::invalid_argument(format!("Failed to decode FlightData: {:?}", e))
-            })?;
-            if !data.inner.app_metadata.is_empty() {
-                policy = Some(String::from_utf8_lossy(&data.inner.app_metadata).to_string());
-            }
-            match data.payload {
-                DecodedPayload::None => {
-                    error!("Received empty payload");
-                    return Err(Status::invalid_argument("Received empty payload"));
-                }
-                DecodedPayload::Schema(decoded_schema) => {
-                    if schema.is_some() {
+        let mut writer = None;
+        let res = async {
+            let mut policy = None;
+            let mut descriptor = PutDescriptor::default();
+            while let Some(data) = stream.next().await {
+                let data = data.map_err(|e| {
+                    error!("Failed to decode FlightData: {:?}", e);
+                    Status::invalid_argument(format!("Failed to decode FlightData: {:?}", e))
+                })?;
+                if let Some(flight_descriptor) = &data.inner.flight_descriptor {
+                    if writer.is_some() {
                         return Err(Status::invalid_argument(
-                            "Multiple schemas are not supported",
+                            "Descriptor must be sent with the schema",
                         ));
                     }
-                    schema = Some(decoded_schema);
+                    descriptor = put_descriptor(flight_descriptor)?;
                 }
-                DecodedPayload::RecordBatch(batch) => {
-                    if schema.is_none() {
+                if !data.inner.app_metadata.is_empty() {
+                    if writer.is_some() {
                         return Err(Status::invalid_argument(
-                            "Schema must be sent before RecordBatch",
+                            "Policy must be sent with the schema",
                         ));
                     }
-                    batches.push(batch);
+                    policy = Some(String::from_utf8_lossy(&data.inner.app_metadata).to_string());
+                }
+                match data.payload {
+                    DecodedPayload::None => {
+                        error!("Received empty payload");
+                        return Err(Status::invalid_argument("Received empty payload"));
+                    }
+                    DecodedPayload::Schema(schema) => {
+                        if writer.is_some() {
+                            return Err(Status::invalid_argument(
+                                "Multiple schemas are not supported",
+                            ));
+                        }
+                        if descriptor.target.is_some() && policy.is_some() {
+                            return Err(Status::invalid_argument(
+                                "The policy of an existing target cannot be changed",
+                            ));
+                        }
+                        if descriptor.target.is_some() && descriptor.ttl_secs.is_some() {
+                            return Err(Status::invalid_argument(
+                                "The TTL of an existing target cannot be changed",
+                            ));
+                        }
+                        let column_names = schema
+                            .fields()
+                            .iter()
+                            .map(|field| field.name().as_str())
+                            .collect::<Vec<_>>();
+                        self.authorize(&subject, Operation::Put, STORAGE_DATASET, &column_names)?;
+                        writer = Some(self.start_store(
+                            &subject.name,
+                            std::mem::take(&mut descriptor),
+                            schema,
+                            policy.take(),
+                        ));
+                    }
+                    DecodedPayload::RecordBatch(batch) => {
+                        let Some((tx, _)) = &writer else {
+                            return Err(Status::invalid_argument(
+                                "Schema must be sent before RecordBatch",
+                            ));
+                        };
+                        if tx.send(Ok(batch)).await.is_err() {
+                            // The writer stopped early; its error is returned below.
+                            break;
+                        }
+                    }
                 }
             }
+            Ok::<(), Status>(())
         }
+        .await;
 
-        let schema = schema.ok_or_else(|| Status::invalid_argument("No schema was provided"))?;
-        let target_name = storage::store_data(&self.cmd_opts, &subject, schema, policy, batches)
-            .map_err(|e| Status::internal(format!("Failed to store data: {:?}", e)))?;
+        let Some((tx, handle)) = writer else {
+            res?;
+            return Err(Status::invalid_argument("No schema was provided"));
+        };
+        if let Err(e) = res {
+            // Makes the writer roll back before the error is returned.
+            let _ = tx.send(Err(e.clone())).await;
+            drop(tx);
+            let _ = handle.await;
+            return Err(e);
+        }
+        drop(tx);
+        let target_name = handle
+            .await
+            .map_err(|e| Status::internal(format!("Failed to store data: {:?}", e)))?
+            .map_err(|e| storage_status("Failed to store data", e))?;
         let results = vec![Ok(PutResult {
             app_metadata: bytes::Bytes::from(target_name),
         })];
@@ -496,16 +733,92 @@ impl FlightService for FlightServiceImpl {
 
     async fn do_action(
         &self,
-        _request: Request<Action>,
+        request: Request<Action>,
     ) -> Result<Response<Self::DoActionStream>, Status> {
-        Err(Status::unimplemented("Implement do_action"))
+        debug!("do_action");
+
+        let subject = auth::subject(&request)?;
+
+        let action = request.into_inner();
+        let operation = match action.r#type.as_str() {
+            "list_targets" => Operation::List,
+            _ => Operation::Admin,
+        };
+        self.authorize(&subject, operation, STORAGE_DATASET, &[])?;
+        let results = match action.r#type.as_str() {
+            "list_targets" => storage::list_targets(&self.cmd_opts, &subject.name)
+                .map_err(|e| storage_status("Failed to list targets", e))?
+                .iter()
+                .map(|target| arrow_flight::Result {
+                    body: target.to_json().into(),
+                })
+                .collect(),
+            "delete_target" => {
+                let args: DeleteTargetArgs = action_args(&action)?;
+                storage::delete_target(&self.cmd_opts, &subject.name, &args.target)
+                    .map_err(|e| storage_status("Failed to delete target", e))?;
+                info!("target deleted: {}, subject: {}", args.target, subject.name);
+                Vec::new()
+            }
+            "label_target" => {
+                let args: LabelTargetArgs = action_args(&action)?;
+                storage::label_target(
+                    &self.cmd_opts,
+                    &subject.name,
+                    &args.target,
+                    args.label.as_deref(),
+                )
+                .map_err(|e| storage_status("Failed to label target", e))?;
+                Vec::new()
+            }
+            "grant_target" => {
+                let args: GrantTargetArgs = action_args(&action)?;
+                storage::grant_target(&self.cmd_opts, &subject.name, &args.target, &args.grantee)
+                    .map_err(|e| storage_status("Failed to grant target", e))?;
+                info!(
+                    "target granted: {}, subject: {}, grantee: {:?}",
+                    args.target, subject.name, args.grantee
+                );
+                Vec::new()
+            }
+            "revoke_target" => {
+                let args: GrantTargetArgs = action_args(&action)?;
+                storage::revoke_target(&self.cmd_opts, &subject.name, &args.target, &args.grantee)
+                    .map_err(|e| storage_status("Failed to revoke target", e))?;
+                info!(
+                    "target revoked: {}, subject: {}, grantee: {:?}",
+                    args.target, subject.name, args.grantee
+                );
+                Vec::new()
+            }
+            _ => {
+                return Err(Status::invalid_argument(format!(
+                    "unknown action: {}",
+                    action.r#type
+                )))
+            }
+        };
+        let result_stream = futures::stream::iter(results.into_iter().map(Ok));
+        Ok(Response::new(Box::pin(result_stream)))
     }
 
     async fn list_actions(
         &self,
-        _request: Request<Empty>,
+        request: Request<Empty>,
     ) -> Result<Response<Self::ListActionsStream>, Status> {
-        Err(Status::unimplemented("Implement list_actions"))
+        debug!("list_actions");
+
+        let subject = auth::subject(&request)?;
+        self.authorize(&subject, Operation::List, STORAGE_DATASET, &[])?;
+
+        let actions = ACTIONS.iter().map(|(r#type, description)| {
+            Ok(ActionType {
+                r#type: r#type.to_string(),
+                description: description.to_string(),
+            })
+        });
+        let result_stream = futures::stream::iter(actions.collect::<Vec<_>>());
+        Ok(Response::new(Box::pin(result_stream)))
     }
 
     async fn poll_flight_info(
@@ -536,6 +849,12 @@ pub struct CmdOptions {
     /// CSV file path
     #[argh(option)]
     csv_file: Option<String>,
+    /// directory of CSV files, each served as a dataset named after the file
+    #[argh(option)]
+    csv_dir: Option<String>,
+    /// CSV dataset in name=path form (can be repeated)
+    #[argh(option)]
+    csv_dataset: Vec<String>,
     /// EDINET db path
     #[argh(option)]
     edinet_db: Option<String>,
@@ -568,6 +887,224 @@ pub struct CmdOptions {
     #[argh(option)]
     /// expected server launch digest in base64 format
     server_ld: Option<String>,
+
+    /// rows per batch returned by do_get
+    #[argh(option, default = "8192")]
+    batch_size: usize,
+
+    /// maximum number of rows accepted by one do_put
+    #[argh(option)]
+    max_put_rows: Option<u64>,
+
+    /// maximum bytes of Arrow data accepted by one do_put
+    #[argh(option)]
+    max_put_bytes: Option<u64>,
+
+    /// maximum number of columns accepted by one do_put
+    #[argh(option)]
+    max_put_columns: Option<usize>,
+
+    /// store uploaded targets as Parquet files under --parquet-path
+    #[argh(switch)]
+    parquet_storage: bool,
+
+    /// maximum TTL in seconds of an uploaded target, also applied to uploads
+    /// that do not set one
+    #[argh(option)]
+    max_ttl_secs: Option<u64>,
+
+    /// seconds between purges of expired targets
+    #[argh(option, default = "60")]
+    purge_interval_secs: u64,
+
+    /// maximum bytes of Arrow data stored per subject
+    #[argh(option)]
+    max_subject_bytes: Option<u64>,
+
+    /// maximum number of targets stored per subject
+    #[argh(option)]
+    max_subject_targets: Option<usize>,
+
+    /// file holding the 32-byte master key that encrypts stored data at rest,
+    /// raw or in base64
+    #[argh(option)]
+    master_key_file: Option<String>,
+
+    /// encrypt stored data at rest with a master key derived by the SEV-SNP
+    /// firmware
+    #[argh(switch)]
+    derived_master_key: bool,
+
+    /// guest fields mixed into the derived master key as 6 binary digits, in
+    /// the order of snpguest --guest_file_select
+    #[argh(option)]
+    derived_key_fields: Option<String>,
+
+    /// master key file used before a rotation; data keys wrapped by it are
+    /// re-wrapped at startup
+    #[argh(option)]
+    previous_master_key_file: Option<String>,
+
+    /// expected `iss` claim of JWTs
+    #[argh(
+        option,
+        default = "String::from(\"https://seera-networks.jp.auth0.com/\")"
+    )]
+    jwt_issuer: String,
+
+    /// accepted `aud` claim of JWTs (can be repeated); the yakserv audiences
+    /// if not given
+    #[argh(option)]
+    jwt_audience: Vec<String>,
+
+    /// accepted JWT signing algorithm, RS256, ES256 or EdDSA (can be
+    /// repeated); RS256 if not given
+    #[argh(option)]
+    jwt_algorithm: Vec<String>,
+
+    /// URL of the JWKS, or path of a local JWKS file
+    #[argh(
+        option,
+        default = "String::from(\"https://seera-networks.jp.auth0.com/.well-known/jwks.json\")"
+    )]
+    jwks: String,
+
+    /// seconds between refreshes of the JWKS
+    #[argh(option, default = "3600")]
+    jwks_refresh_secs: u64,
+
+    /// ACL file in TOML mapping subjects and groups to the datasets, columns
+    /// and operations they may use; everything is allowed without it
+    #[argh(option)]
+    acl_file: Option<String>,
+
+    /// TOML policy of the measurements, TCB, guest policy, keys and IDs the
+    /// attestation report of a handshake must match; reloaded on change
+    #[argh(option)]
+    appraisal_policy: Option<String>,
+
+    /// directory the AMD certificates for attestation are kept in
+    #[argh(
+        option,
+        default = "String::from(\"/tmp/ext-grpc-server/snpguest/certs\")"
+    )]
+    certs_dir: String,
+
+    /// PEM bundle of pre-provisioned ARK, ASK and VCEK certificates, used
+    /// for the ones missing from --certs-dir
+    #[argh(option)]
+    cert_bundle: Option<String>,
+
+    /// base URL of the AMD KDS, or of a mirror serving the same paths
+    #[argh(option, default = "String::from(snpguest::fetch::KDS_CERT_SITE)")]
+    kds_url: String,
+
+    /// never use the network: certificates must be in --certs-dir or
+    /// --cert-bundle, and --jwks must be a local file
+    #[argh(switch)]
+    offline: bool,
+}
+
+/// The default options, for tests to override with struct update syntax.
+#[cfg(test)]
+pub(crate) fn test_cmd_opts() -> CmdOptions {
+    CmdOptions {
+        no_tls: true,
+        authorized_subject: None,
+        csv_file: None,
+        csv_dir: None,
+        csv_dataset: Vec::new(),
+        edinet_db: None,
+        parquet_path: "./parquet".to_string(),
+        storage_db: "./storage.db".to_string(),
+        policy_db: "./policy.db".to_string(),
+        cert: "./certs/server.crt".to_string(),
+        key: "./certs/server.key".to_string(),
+        port: 50053,
+        use_test_challenge: false,
+        allow_test_subject: true,
+        server_ld: None,
+        batch_size: 8192,
+        max_put_rows: None,
+        max_put_bytes: None,
+        max_put_columns: None,
+        parquet_storage: false,
+        max_ttl_secs: None,
+        purge_interval_secs: 60,
+        max_subject_bytes: None,
+        max_subject_targets: None,
+        master_key_file: None,
+        derived_master_key: false,
+        derived_key_fields: None,
+        previous_master_key_file: None,
+        jwt_issuer: "https://idp.example.com/".to_string(),
+        jwt_audience: Vec::new(),
+        jwt_algorithm: Vec::new(),
+        jwks: "./jwks.json".to_string(),
+        jwks_refresh_secs: 3600,
+        acl_file: None,
+        appraisal_policy: None,
+        certs_dir: "./certs".to_string(),
+        cert_bundle: None,
+        kds_url: "https://kdsintf.amd.com".to_string(),
+        offline: false,
+    }
+}
+
+/// Re-wraps the data keys after a master key rotation and encrypts the data
+/// still kept in plaintext.
+fn encrypt_stored_data(cmd_opts: &CmdOptions) -> anyhow::Result<()> {
+    let Some(master_key) = crypto::master_key(cmd_opts)? else {
+        if cmd_opts.previous_master_key_file.is_some() {
+            return Err(anyhow!(
+                "--previous-master-key-file requires a master key to rotate to"
+            ));
+        }
+        return Ok(());
+    };
+    if let Some(path) = &cmd_opts.previous_master_key_file {
+        let previous = crypto::MasterKey::load(path)?;
+        let keys = storage::rewrap_keys(cmd_opts, &previous, &master_key)?;
+        let files = crypto::rewrap_files(cmd_opts, &previous, &master_key)?;
+        info!(
+            "data keys re-wrapped with master key {}: {} targets, {} files",
+            master_key.id(),
+            keys,
+            files
+        );
+    }
+    let targets = storage::encrypt_plaintext(cmd_opts, &master_key)?;
+    let files = crypto::seal_plaintext_files(cmd_opts, &master_key)?;
+    if targets > 0 || files > 0 {
+        info!(
+            "plaintext data encrypted: {} targets, {} files",
+            targets, files
+        );
+    }
+    Ok(())
+}
+
+/// Deletes expired targets every `--purge-interval-secs`.
+async fn purge_expired_targets(cmd_opts: CmdOptions) {
+    let period = Duration::from_secs(cmd_opts.purge_interval_secs.max(1));
+    let mut interval = tokio::time::interval(period);
+    loop {
+        interval.tick().await;
+        let cmd_opts = cmd_opts.clone();
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("Time went backwards")
+            .as_secs();
+        match tokio::task::spawn_blocking(move || storage::purge_expired(&cmd_opts, now)).await {
+            Ok(Ok(purged)) => {
+                for tbl_name in purged {
+                    info!("expired target purged: {}", tbl_name);
+                }
+            }
+            Ok(Err(e)) => error!("Failed to purge expired targets: {:?}", e),
+            Err(e) => error!("Failed to purge expired targets: {:?}", e),
+        }
+    }
 }
 
 #[tokio::main]
@@ -590,8 +1127,7 @@ async fn main() -> anyhow::Result<()> {
 
     let addr = format!("0.0.0.0:{}", cmd_opts.port).parse()?;
 
-    let jwks_url = "https://seera-networks.jp.auth0.com/.well-known/jwks.json";
-    let jwks = Jwks::from_jwks_url(jwks_url).await?;
+    let jwt = Arc::new(JwtValidator::from_cmd_opts(&cmd_opts).await?);
 
     let server_ld = if let Some(server_ld) = &cmd_opts.server_ld {
         let base64_engine = base64::engine::general_purpose::STANDARD;
@@ -606,14 +1142,29 @@ async fn main() -> anyhow::Result<()> {
     } else {
         None
     };
+    encrypt_stored_data(&cmd_opts)?;
+
+    let valid_tokens = Arc::new(Mutex::new(ValidTokenStore::default()));
     let service = FlightServiceImpl {
         cmd_opts: cmd_opts.clone(),
-        jwks,
-        valid_tokens: Arc::new(Mutex::new(ValidTokenStore::default())),
+        sources: Registry::from_cmd_opts(&cmd_opts)?,
+        acl: Acl::from_cmd_opts(&cmd_opts)?.map(Arc::new),
+        valid_tokens: valid_tokens.clone(),
         server_ld,
+        appraiser: Appraiser::from_cmd_opts(&cmd_opts)?.map(Arc::new),
+        cert_source: CertSource {
+            certs_dir: PathBuf::from(&cmd_opts.certs_dir),
+            cert_bundle: cmd_opts.cert_bundle.as_ref().map(PathBuf::from),
+            kds_url: cmd_opts.kds_url.clone(),
+            offline: cmd_opts.offline,
+        },
     };
 
-    let svc = FlightServiceServer::new(service);
+    let authenticator = Authenticator::new(cmd_opts.clone(), jwt.clone(), valid_tokens);
+    let svc = AuthLayer::new(authenticator).layer(FlightServiceServer::new(service));
+
+    tokio::spawn(purge_expired_targets(cmd_opts.clone()));
+    tokio::spawn(jwt::refresh_jwks(jwt, cmd_opts.clone()));
 
     let server = if cmd_opts.no_tls {
         Server::builder()
@@ -665,17 +1216,31 @@ async fn main() -> anyhow::Result<()> {
 
 #[cfg(test)]
 mod tests {
-    use super::{ValidTokenStore, HANDSHAKE_TOKEN_TTL, MAX_VALID_TOKENS};
+    use super::{TokenBinding, ValidTokenStore, HANDSHAKE_TOKEN_TTL, MAX_VALID_TOKENS};
     use std::time::{Duration, Instant};
 
+    fn binding(measurement: [u8; 48]) -> TokenBinding {
+        TokenBinding {
+            measurement,
+            chip_id: [2; 64],
+            client_cert: None,
+            subject: None,
+        }
+    }
+
     #[test]
     fn token_store_prunes_expired_tokens() {
         let mut store = ValidTokenStore::default();
         let now = Instant::now();
 
-        store.insert("valid".to_string(), now);
-        assert!(store.is_valid("valid", now + Duration::from_secs(1)));
-        assert!(!store.is_valid("valid", now + HANDSHAKE_TOKEN_TTL + Duration::from_secs(1),));
+        store.insert("valid".to_string(), binding([1; 48]), now);
+        assert_eq!(
+            store.get("valid", now + Duration::from_secs(1)),
+            Some(binding([1; 48]))
+        );
+        assert!(store
+            .get("valid", now + HANDSHAKE_TOKEN_TTL + Duration::from_secs(1))
+            .is_none());
     }
 
     #[test]
@@ -684,17 +1249,41 @@ mod tests {
         let now = Instant::now();
 
         for i in 0..=MAX_VALID_TOKENS {
-            store.insert(format!("token-{i}"), now + Duration::from_millis(i as u64));
+            store.insert(
+                format!("token-{i}"),
+                binding([0; 48]),
+                now + Duration::from_millis(i as u64),
+            );
         }
 
         assert_eq!(store.tokens.len(), MAX_VALID_TOKENS);
-        assert!(!store.is_valid(
-            "token-0",
-            now + Duration::from_millis(MAX_VALID_TOKENS as u64),
-        ));
-        assert!(store.is_valid(
-            &format!("token-{MAX_VALID_TOKENS}"),
-            now + Duration::from_millis(MAX_VALID_TOKENS as u64),
-        ));
+        assert!(store
+            .get(
+                "token-0",
+                now + Duration::from_millis(MAX_VALID_TOKENS as u64),
+            )
+            .is_none());
+        assert!(store
+            .get(
+                &format!("token-{MAX_VALID_TOKENS}"),
+                now + Duration::from_millis(MAX_VALID_TOKENS as u64),
+            )
+            .is_some());
+    }
+
+    #[test]
+    fn token_store_binds_the_first_subject() {
+        let mut store = ValidTokenStore::default();
+        let now = Instant::now();
+
+        store.insert("token".to_string(), binding([1; 48]), now);
+        assert!(store.bind_subject("token", "alice"));
+        assert!(store.bind_subject("token", "alice"));
+        assert!(!store.bind_subject("token", "mallory"));
+        assert!(!store.bind_subject("unknown", "alice"));
+        assert_eq!(
+            store.get("token", now).unwrap().subject.as_deref(),
+            Some("alice")
+        );
     }
 }
@@ -0,0 +1,242 @@
+// SPDX-FileCopyrightText: 2025 SEERA Networks Corporation <info@seera-networks.com>
+// SPDX-License-Identifier: MIT
+
+use arrow::record_batch::RecordBatch;
+use arrow_schema::{Field, SchemaRef};
+use std::sync::Arc;
+use tonic::{Result, Status};
+
+use crate::auth::Subject;
+use crate::CmdOptions;
+
+/// Receives the batches of `DataSource::get_data` one at a time. Returning
+/// `false` stops the source, e.g. when the client has gone away.
+pub type BatchSink<'a> = dyn FnMut(RecordBatch) -> bool + 'a;
+
+/// A column advertised by `DataSource::list`.
+pub struct ColumnInfo {
+    pub target: String,
+    pub column_name: String,
+    /// The Arrow field of the column, if the source knows it without reading
+    /// the data.
+    pub field: Option<Field>,
+}
+
+/// A backend serving the data of one or more ticket targets.
+pub trait DataSource: Send + Sync {
+    /// Returns the schema and the row count of `columns` of `target`.
+    fn schema(&self, subject: &Subject, target: &str, columns: &[&str])
+        -> Result<(SchemaRef, i64)>;
+
+    /// Passes `columns` of `target` to `sink` as row-aligned batches of up to
+    /// `batch_size` rows.
+    fn get_data(
+        &self,
+        subject: &Subject,
+        target: &str,
+        columns: &[&str],
+        batch_size: usize,
+        sink: &mut BatchSink,
+    ) -> Result<()>;
+
+    /// Returns the policy JSON attached to a column of `target`.
+    fn get_policy(&self, subject: &Subject, target: &str, column_name: &str) -> Result<String>;
+
+    /// Returns the columns visible to `subject`. `target` is the name the
+    /// source is registered under.
+    fn list(&self, subject: &Subject, target: &str) -> Result<Vec<ColumnInfo>>;
+}
+
+/// The dataset stored targets belong to in the ACL.
+pub const STORAGE_DATASET: &str = "storage";
+
+/// Maps ticket targets to the sources serving them. Targets without a named
+/// source are served by the fallback source, which is the subject storage.
+#[derive(Default)]
+pub struct Registry {
+    named: Vec<(String, Arc<dyn DataSource>)>,
+    fallback: Option<Arc<dyn DataSource>>,
+}
+
+impl Registry {
+    /// Builds the registry of the sources configured by `cmd_opts`. Every CSV
+    /// dataset is served under its own name. The `system` target is kept as an
+    /// alias of `--csv-file`, or of the EDINET source when no CSV file is given.
+    pub fn from_cmd_opts(cmd_opts: &CmdOptions) -> anyhow::Result<Self> {
+        let mut registry = Registry::default();
+        let mut csv_sources = Vec::new();
+        for (name, path) in crate::csv::datasets(cmd_opts)? {
+            if matches!(name.as_str(), "system" | "edinet" | STORAGE_DATASET) {
+                return Err(anyhow::anyhow!("reserved csv dataset name: {}", name));
+            }
+            let source: Arc<dyn DataSource> =
+                Arc::new(crate::csv::CsvSource::new(cmd_opts.clone(), &name, &path));
+            csv_sources.push((name, source));
+        }
+        let edinet = cmd_opts.edinet_db.as_ref().map(|_| {
+            Arc::new(crate::edinet::EdinetSource::new(cmd_opts.clone())) as Arc<dyn DataSource>
+        });
+
+        let csv_file = cmd_opts
+            .csv_file
+            .as_ref()
+            .and_then(|_| csv_sources.first().map(|(_, source)| source));
+        if let Some(system) = csv_file.or(edinet.as_ref()) {
+            registry.register("system", system.clone());
+        }
+        for (name, source) in csv_sources {
+            registry.register(&name, source);
+        }
+        if let Some(edinet) = edinet {
+            registry.register("edinet", edinet);
+        }
+        registry.set_fallback(Arc::new(crate::storage::StorageSource::new(
+            cmd_opts.clone(),
+        )));
+        Ok(registry)
+    }
+
+    pub fn register(&mut self, target: &str, source: Arc<dyn DataSource>) {
+        self.named.retain(|(name, _)| name != target);
+        self.named.push((target.to_string(), source));
+    }
+
+    pub fn set_fallback(&mut self, source: Arc<dyn DataSource>) {
+        self.fallback = Some(source);
+    }
+
+    /// Returns the source serving `target`.
+    pub fn resolve(&self, target: &str) -> Result<Arc<dyn DataSource>> {
+        self.named
+            .iter()
+            .find(|(name, _)| name == target)
+            .map(|(_, source)| source)
+            .or(self.fallback.as_ref())
+            .cloned()
+            .ok_or_else(|| Status::not_found(format!("no data source for target {}", target)))
+    }
+
+    /// Returns the dataset `target` belongs to in the ACL: the name of its
+    /// source, or `storage` for a stored target. A source registered under
+    /// several names belongs to the last one, so the `system` alias is
+    /// checked as the CSV or EDINET dataset it stands for.
+    pub fn dataset<'a>(&'a self, target: &'a str) -> &'a str {
+        let Some((_, source)) = self.named.iter().find(|(name, _)| name == target) else {
+            return STORAGE_DATASET;
+        };
+        self.named
+            .iter()
+            .rev()
+            .find(|(_, other)| Arc::ptr_eq(other, source))
+            .map_or(target, |(name, _)| name.as_str())
+    }
+
+    /// Returns the columns of every source visible to `subject`. A source
+    /// registered under several names is listed under the first one only.
+    pub fn list(&self, subject: &Subject) -> Result<Vec<ColumnInfo>> {
+        let mut columns = Vec::new();
+        for (idx, (name, source)) in self.named.iter().enumerate() {
+            let is_alias = self.named[..idx]
+                .iter()
+                .any(|(_, other)| Arc::ptr_eq(other, source));
+            if !is_alias {
+                columns.extend(source.list(subject, name)?);
+            }
+        }
+        if let Some(fallback) = &self.fallback {
+            columns.extend(fallback.list(subject, "")?);
+        }
+        Ok(columns)
+    }
+}
+
+/// Reads every batch of `columns` of `target` into memory.
+#[cfg(test)]
+pub fn collect(
+    source: &dyn DataSource,
+    target: &str,
+    columns: &[&str],
+    batch_size: usize,
+) -> Result<Vec<RecordBatch>> {
+    let mut batches = Vec::new();
+    source.get_data(
+        &Subject::new("test"),
+        target,
+        columns,
+        batch_size,
+        &mut |batch| {
+            batches.push(batch);
+            true
+        },
+    )?;
+    Ok(batches)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BatchSink, ColumnInfo, DataSource, Registry};
+    use crate::auth::Subject;
+    use arrow_schema::SchemaRef;
+    use std::sync::Arc;
+    use tonic::{Result, Status};
+
+    struct NamedSource(&'static str);
+
+    impl DataSource for NamedSource {
+        fn schema(&self, _: &Subject, _: &str, _: &[&str]) -> Result<(SchemaRef, i64)> {
+            Err(Status::unimplemented("schema"))
+        }
+
+        fn get_data(
+            &self,
+            _: &Subject,
+            _: &str,
+            _: &[&str],
+            _: usize,
+            _: &mut BatchSink,
+        ) -> Result<()> {
+            Err(Status::unimplemented("get_data"))
+        }
+
+        fn get_policy(&self, _: &Subject, _: &str, _: &str) -> Result<String> {
+            Ok(self.0.to_string())
+        }
+
+        fn list(&self, _: &Subject, target: &str) -> Result<Vec<ColumnInfo>> {
+            Ok(vec![ColumnInfo {
+                target: target.to_string(),
+                column_name: self.0.to_string(),
+                field: None,
+            }])
+        }
+    }
+
+    #[test]
+    fn registry_resolves_named_targets_before_fallback() {
+        let mut registry = Registry::default();
+        let csv: Arc<dyn DataSource> = Arc::new(NamedSource("csv"));
+        registry.register("system", csv.clone());
+        registry.register("csv", csv);
+        registry.set_fallback(Arc::new(NamedSource("storage")));
+
+        let policy = |target: &str| {
+            registry
+                .resolve(target)
+                .unwrap()
+                .get_policy(&Subject::new("subject"), target, "column")
+                .unwrap()
+        };
+        assert_eq!(policy("system"), "csv");
+        assert_eq!(policy("csv"), "csv");
+        assert_eq!(policy("1_2_0123456789abcdef"), "storage");
+        assert_eq!(registry.dataset("system"), "csv");
+        assert_eq!(registry.dataset("csv"), "csv");
+        assert_eq!(registry.dataset("1_2_0123456789abcdef"), "storage");
+
+        let columns = registry.list(&Subject::new("subject")).unwrap();
+        assert_eq!(columns.len(), 2);
+        assert_eq!(columns[0].target, "system");
+        assert_eq!(columns[0].column_name, "csv");
+        assert_eq!(columns[1].column_name, "storage");
+    }
+}
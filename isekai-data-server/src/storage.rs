@@ -1,17 +1,32 @@
 // SPDX-FileCopyrightText: 2025 SEERA Networks Corporation <info@seera-networks.com>
 // SPDX-License-Identifier: MIT
 
-use crate::CmdOptions;
+use crate::auth::Subject;
+use crate::crypto::{self, DataKey, MasterKey, StoredFile};
+use crate::source::{BatchSink, ColumnInfo, DataSource};
+use crate::{storage_status, CmdOptions};
 use anyhow::Context;
-use arrow::array::{self, AsArray};
+use arrow::array::{self, ArrayRef, AsArray};
+use arrow::compute::{cast_with_options, CastOptions};
+use arrow::datatypes::{Decimal128Type, Float64Type, Int64Type, UInt64Type};
 use arrow::record_batch::RecordBatch;
-use arrow_schema::{DataType, SchemaRef};
+use arrow::util::display::FormatOptions;
+use arrow_schema::{DataType, Field, SchemaRef, TimeUnit};
+use isekai_utils::module::{Grantee, StoredTarget};
 use isekai_utils::policy::PolicyFile;
+use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+use parquet::arrow::{ArrowWriter, ProjectionMask};
+use parquet::basic::Compression;
+use parquet::file::properties::WriterProperties;
 use rand::rngs::OsRng;
 use rand::RngCore;
-use rusqlite::{Connection, OpenFlags, OptionalExtension, Transaction};
+use rusqlite::types::Value;
+use rusqlite::{Connection, OpenFlags, OptionalExtension, Transaction, TransactionBehavior};
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::error;
 
 fn is_valid_sqlid(name: &str) -> bool {
     // A valid SQL identifier must start with a letter and can contain letters, digits, and underscores
@@ -21,6 +36,16 @@ fn is_valid_sqlid(name: &str) -> bool {
 
 const MAX_TARGET_GENERATION_ATTEMPTS: usize = 16;
 
+/// How long a connection to `storage.db` waits for another one to release its
+/// lock before failing with `SQLITE_BUSY`.
+const STORAGE_BUSY_TIMEOUT: Duration = Duration::from_secs(30);
+
+fn open_storage_db(cmd_opts: &CmdOptions, flags: OpenFlags) -> anyhow::Result<Connection> {
+    let conn = Connection::open_with_flags(cmd_opts.storage_db.as_str(), flags)?;
+    conn.busy_timeout(STORAGE_BUSY_TIMEOUT)?;
+    Ok(conn)
+}
+
 fn ensure_storage_metadata_tables(conn: &Connection) -> rusqlite::Result<()> {
     conn.execute(
         "CREATE TABLE IF NOT EXISTS policy (
@@ -38,9 +63,135 @@ fn ensure_storage_metadata_tables(conn: &Connection) -> rusqlite::Result<()> {
         )",
         [],
     )?;
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS storage_target (
+            table_name TEXT PRIMARY KEY,
+            created_at INTEGER NOT NULL,
+            label TEXT
+        )",
+        [],
+    )?;
+    ensure_column(
+        conn,
+        "storage_target",
+        "backend",
+        "TEXT NOT NULL DEFAULT 'sqlite'",
+    )?;
+    ensure_column(conn, "storage_target", "expires_at", "INTEGER")?;
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS storage_grant (
+            table_name TEXT NOT NULL,
+            grantee_type TEXT NOT NULL,
+            grantee TEXT NOT NULL,
+            PRIMARY KEY (table_name, grantee_type, grantee)
+        )",
+        [],
+    )?;
+    ensure_column(
+        conn,
+        "storage_target",
+        "num_bytes",
+        "INTEGER NOT NULL DEFAULT 0",
+    )?;
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS storage_key (
+            table_name TEXT PRIMARY KEY,
+            key_id TEXT NOT NULL,
+            wrapped_key BLOB NOT NULL
+        )",
+        [],
+    )?;
+    Ok(())
+}
+
+fn column_exists(conn: &Connection, table_name: &str, column_name: &str) -> rusqlite::Result<bool> {
+    let mut stmt = conn.prepare(&format!("PRAGMA table_info({})", table_name))?;
+    let names = stmt
+        .query_map([], |row| row.get::<_, String>(1))?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    Ok(names.iter().any(|name| name == column_name))
+}
+
+/// Adds a column to a metadata table created by an older version.
+fn ensure_column(
+    conn: &Connection,
+    table_name: &str,
+    column_name: &str,
+    definition: &str,
+) -> rusqlite::Result<()> {
+    if !column_exists(conn, table_name, column_name)? {
+        conn.execute(
+            &format!(
+                "ALTER TABLE {} ADD COLUMN {} {}",
+                table_name, column_name, definition
+            ),
+            [],
+        )?;
+    }
     Ok(())
 }
 
+/// Where the rows of a target are kept. The metadata of every target stays in
+/// `storage.db` either way.
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Backend {
+    /// A `<subject>_<target>` table in `storage.db`.
+    Sqlite,
+    /// Parquet files under `--parquet-path`, one per upload.
+    Parquet,
+}
+
+impl Backend {
+    /// Returns the backend new targets are stored with.
+    fn from_cmd_opts(cmd_opts: &CmdOptions) -> Self {
+        if cmd_opts.parquet_storage {
+            Backend::Parquet
+        } else {
+            Backend::Sqlite
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Backend::Sqlite => "sqlite",
+            Backend::Parquet => "parquet",
+        }
+    }
+
+    fn from_name(name: &str) -> anyhow::Result<Self> {
+        match name {
+            "sqlite" => Ok(Backend::Sqlite),
+            "parquet" => Ok(Backend::Parquet),
+            _ => Err(anyhow::anyhow!("Unknown storage backend: {}", name)),
+        }
+    }
+}
+
+/// Returns the backend of an existing table, or `None` if there is no such
+/// target or it has expired. Tables stored before `storage_target` existed are
+/// SQLite tables.
+fn target_backend(conn: &Connection, tbl_name: &str) -> anyhow::Result<Option<Backend>> {
+    if table_exists(conn, "storage_target")? && column_exists(conn, "storage_target", "backend")? {
+        let sql = if column_exists(conn, "storage_target", "expires_at")? {
+            "SELECT backend, expires_at FROM storage_target WHERE table_name = ?"
+        } else {
+            "SELECT backend, NULL FROM storage_target WHERE table_name = ?"
+        };
+        let metadata = conn
+            .query_row(sql, rusqlite::params![tbl_name], |row| {
+                Ok((row.get::<_, String>(0)?, row.get::<_, Option<u64>>(1)?))
+            })
+            .optional()?;
+        if let Some((backend, expires_at)) = metadata {
+            if expires_at.is_some_and(|expires_at| expires_at <= now_secs()) {
+                return Ok(None);
+            }
+            return Ok(Some(Backend::from_name(&backend)?));
+        }
+    }
+    Ok(table_exists(conn, tbl_name)?.then_some(Backend::Sqlite))
+}
+
 fn generate_target() -> String {
     let now = SystemTime::now()
         .duration_since(UNIX_EPOCH)
@@ -54,6 +205,22 @@ fn generate_target() -> String {
     format!("{}_{}_{}", now.as_secs(), now.subsec_nanos(), random)
 }
 
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("Time went backwards")
+        .as_secs()
+}
+
+/// Returns the creation time encoded in a target made by `generate_target`.
+fn created_at(target: &str) -> u64 {
+    target
+        .split('_')
+        .next()
+        .and_then(|secs| secs.parse().ok())
+        .unwrap_or(0)
+}
+
 fn table_exists(conn: &Connection, table_name: &str) -> anyhow::Result<bool> {
     let mut stmt =
         conn.prepare("SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = ? LIMIT 1")?;
@@ -64,90 +231,393 @@ fn table_exists(conn: &Connection, table_name: &str) -> anyhow::Result<bool> {
     Ok(exists)
 }
 
-fn arrow_to_sql_type(field_type: &DataType) -> &'static str {
+/// Returns the type of the array a column is converted to before it is
+/// written to SQLite. Integer and temporal types are stored as 64-bit
+/// integers, floats as doubles, strings as text and binaries as blobs, so that
+/// every value survives a round trip. Decimals keep their own type and are
+/// stored as the text of the unscaled integer. Dictionaries are stored as
+/// their values.
+fn storage_type(field_type: &DataType) -> anyhow::Result<DataType> {
+    match field_type {
+        DataType::Boolean => Ok(DataType::Boolean),
+        DataType::Int8
+        | DataType::Int16
+        | DataType::Int32
+        | DataType::Int64
+        | DataType::UInt8
+        | DataType::UInt16
+        | DataType::UInt32
+        | DataType::UInt64
+        | DataType::Date32
+        | DataType::Date64
+        | DataType::Time32(_)
+        | DataType::Time64(_)
+        | DataType::Timestamp(_, _)
+        | DataType::Duration(_) => Ok(DataType::Int64),
+        DataType::Float32 | DataType::Float64 => Ok(DataType::Float64),
+        DataType::Utf8 | DataType::LargeUtf8 => Ok(DataType::Utf8),
+        DataType::Binary | DataType::LargeBinary => Ok(DataType::Binary),
+        DataType::Decimal128(precision, scale) => Ok(DataType::Decimal128(*precision, *scale)),
+        DataType::Dictionary(key_type, value_type) if key_type.is_dictionary_key_type() => {
+            storage_type(value_type)
+        }
+        _ => Err(anyhow::anyhow!("Unsupported column type: {:?}", field_type)),
+    }
+}
+
+fn arrow_to_sql_type(field_type: &DataType) -> anyhow::Result<&'static str> {
+    match storage_type(field_type)? {
+        DataType::Boolean | DataType::Int64 => Ok("INTEGER"),
+        DataType::Float64 => Ok("REAL"),
+        DataType::Utf8 | DataType::Decimal128(_, _) => Ok("TEXT"),
+        _ => Ok("BLOB"),
+    }
+}
+
+const CAST_OPTIONS: CastOptions<'static> = CastOptions {
+    safe: false,
+    format_options: FormatOptions::new(),
+};
+
+/// Converts `array` of `field_type` into an array of `storage_type`.
+fn to_storage_array(array: &ArrayRef, field_type: &DataType) -> anyhow::Result<ArrayRef> {
     match field_type {
-        DataType::Boolean | DataType::Int32 => "INTEGER",
-        DataType::Float32 => "REAL",
-        DataType::Utf8 => "TEXT",
-        _ => "BLOB",
+        // Reinterpreted rather than cast so that values over i64::MAX survive.
+        DataType::UInt64 => Ok(Arc::new(
+            array
+                .as_primitive::<UInt64Type>()
+                .unary::<_, Int64Type>(|v| v as i64),
+        )),
+        DataType::Dictionary(_, value_type) => to_storage_array(
+            &cast_with_options(array, value_type, &CAST_OPTIONS)?,
+            value_type,
+        ),
+        _ => Ok(cast_with_options(
+            array,
+            &storage_type(field_type)?,
+            &CAST_OPTIONS,
+        )?),
     }
 }
 
-fn arrow_type_name(field_type: &DataType) -> anyhow::Result<&'static str> {
+/// Converts an array of `storage_type(field_type)` read from SQLite back into
+/// `field_type`.
+fn from_storage_array(array: ArrayRef, field_type: &DataType) -> anyhow::Result<ArrayRef> {
     match field_type {
-        DataType::Boolean => Ok("bool"),
-        DataType::Int32 => Ok("int32"),
-        DataType::Float32 => Ok("float32"),
-        DataType::Utf8 => Ok("utf8"),
-        DataType::Binary => Ok("binary"),
-        _ => Err(anyhow::anyhow!("Unsupported column type: {:?}", field_type)),
+        DataType::UInt64 => Ok(Arc::new(
+            array
+                .as_primitive::<Int64Type>()
+                .unary::<_, UInt64Type>(|v| v as u64),
+        )),
+        // Only 32-bit integers cast to these types.
+        DataType::Date32 | DataType::Time32(_) => {
+            let array = cast_with_options(&array, &DataType::Int32, &CAST_OPTIONS)?;
+            Ok(cast_with_options(&array, field_type, &CAST_OPTIONS)?)
+        }
+        DataType::Dictionary(_, value_type) => {
+            let array = from_storage_array(array, value_type)?;
+            Ok(cast_with_options(&array, field_type, &CAST_OPTIONS)?)
+        }
+        _ => Ok(cast_with_options(&array, field_type, &CAST_OPTIONS)?),
+    }
+}
+
+fn time_unit_name(unit: &TimeUnit) -> &'static str {
+    match unit {
+        TimeUnit::Second => "s",
+        TimeUnit::Millisecond => "ms",
+        TimeUnit::Microsecond => "us",
+        TimeUnit::Nanosecond => "ns",
+    }
+}
+
+fn time_unit_from_name(name: &str) -> anyhow::Result<TimeUnit> {
+    match name {
+        "s" => Ok(TimeUnit::Second),
+        "ms" => Ok(TimeUnit::Millisecond),
+        "us" => Ok(TimeUnit::Microsecond),
+        "ns" => Ok(TimeUnit::Nanosecond),
+        _ => Err(anyhow::anyhow!("Unsupported time unit: {}", name)),
+    }
+}
+
+/// Returns the name `field_type` is recorded under in `storage_schema`.
+/// Parameterized types carry their parameters after colons, such as
+/// `timestamp:us:UTC`, `decimal128:10:2` and `dictionary:int32:utf8`.
+fn arrow_type_name(field_type: &DataType) -> anyhow::Result<String> {
+    // Rejects types that cannot be stored.
+    storage_type(field_type)?;
+    let name = match field_type {
+        DataType::Boolean => "bool".to_string(),
+        DataType::Int8 => "int8".to_string(),
+        DataType::Int16 => "int16".to_string(),
+        DataType::Int32 => "int32".to_string(),
+        DataType::Int64 => "int64".to_string(),
+        DataType::UInt8 => "uint8".to_string(),
+        DataType::UInt16 => "uint16".to_string(),
+        DataType::UInt32 => "uint32".to_string(),
+        DataType::UInt64 => "uint64".to_string(),
+        DataType::Float32 => "float32".to_string(),
+        DataType::Float64 => "float64".to_string(),
+        DataType::Utf8 => "utf8".to_string(),
+        DataType::LargeUtf8 => "large_utf8".to_string(),
+        DataType::Binary => "binary".to_string(),
+        DataType::LargeBinary => "large_binary".to_string(),
+        DataType::Date32 => "date32".to_string(),
+        DataType::Date64 => "date64".to_string(),
+        DataType::Time32(unit) => format!("time32:{}", time_unit_name(unit)),
+        DataType::Time64(unit) => format!("time64:{}", time_unit_name(unit)),
+        DataType::Timestamp(unit, None) => format!("timestamp:{}", time_unit_name(unit)),
+        DataType::Timestamp(unit, Some(tz)) => {
+            format!("timestamp:{}:{}", time_unit_name(unit), tz)
+        }
+        DataType::Duration(unit) => format!("duration:{}", time_unit_name(unit)),
+        DataType::Decimal128(precision, scale) => format!("decimal128:{}:{}", precision, scale),
+        DataType::Dictionary(key_type, value_type) => format!(
+            "dictionary:{}:{}",
+            arrow_type_name(key_type)?,
+            arrow_type_name(value_type)?
+        ),
+        _ => return Err(anyhow::anyhow!("Unsupported column type: {:?}", field_type)),
+    };
+    Ok(name)
+}
+
+fn arrow_type_from_name(arrow_type: &str) -> anyhow::Result<DataType> {
+    let unsupported = || anyhow::anyhow!("Unsupported stored column type: {}", arrow_type);
+    let data_type = match arrow_type.split_once(':') {
+        None => match arrow_type {
+            "bool" => DataType::Boolean,
+            "int8" => DataType::Int8,
+            "int16" => DataType::Int16,
+            "int32" => DataType::Int32,
+            "int64" => DataType::Int64,
+            "uint8" => DataType::UInt8,
+            "uint16" => DataType::UInt16,
+            "uint32" => DataType::UInt32,
+            "uint64" => DataType::UInt64,
+            "float32" => DataType::Float32,
+            "float64" => DataType::Float64,
+            "utf8" => DataType::Utf8,
+            "large_utf8" => DataType::LargeUtf8,
+            "binary" => DataType::Binary,
+            "large_binary" => DataType::LargeBinary,
+            "date32" => DataType::Date32,
+            "date64" => DataType::Date64,
+            _ => return Err(unsupported()),
+        },
+        Some(("time32", unit)) => DataType::Time32(time_unit_from_name(unit)?),
+        Some(("time64", unit)) => DataType::Time64(time_unit_from_name(unit)?),
+        Some(("duration", unit)) => DataType::Duration(time_unit_from_name(unit)?),
+        Some(("timestamp", params)) => match params.split_once(':') {
+            None => DataType::Timestamp(time_unit_from_name(params)?, None),
+            Some((unit, tz)) => DataType::Timestamp(time_unit_from_name(unit)?, Some(tz.into())),
+        },
+        Some(("decimal128", params)) => {
+            let (precision, scale) = params.split_once(':').ok_or_else(unsupported)?;
+            DataType::Decimal128(precision.parse()?, scale.parse()?)
+        }
+        Some(("dictionary", params)) => {
+            let (key_type, value_type) = params.split_once(':').ok_or_else(unsupported)?;
+            DataType::Dictionary(
+                Box::new(arrow_type_from_name(key_type)?),
+                Box::new(arrow_type_from_name(value_type)?),
+            )
+        }
+        Some(_) => return Err(unsupported()),
+    };
+    Ok(data_type)
+}
+
+/// Returns the number of targets of `subject` and the bytes uploaded to them.
+fn subject_usage(conn: &Connection, subject: &str) -> anyhow::Result<(usize, u64)> {
+    let prefix = format!("{}_", subject);
+    let mut stmt = conn.prepare(
+        "SELECT s.table_name, COALESCE(t.num_bytes, 0)
+        FROM (SELECT DISTINCT table_name FROM storage_schema) s
+        LEFT JOIN storage_target t ON t.table_name = s.table_name",
+    )?;
+    let rows = stmt.query_map([], |row| {
+        Ok((row.get::<_, String>(0)?, row.get::<_, u64>(1)?))
+    })?;
+    let mut num_targets = 0;
+    let mut num_bytes = 0;
+    for row in rows {
+        let (tbl_name, bytes) = row?;
+        if tbl_name
+            .strip_prefix(&prefix)
+            .is_some_and(is_generated_target)
+        {
+            num_targets += 1;
+            num_bytes += bytes;
+        }
+    }
+    Ok((num_targets, num_bytes))
+}
+
+/// Checks that `subject` may create another target under
+/// `--max-subject-targets` and `--max-subject-bytes`.
+fn check_quota(cmd_opts: &CmdOptions, conn: &Connection, subject: &str) -> anyhow::Result<()> {
+    if cmd_opts.max_subject_targets.is_none() && cmd_opts.max_subject_bytes.is_none() {
+        return Ok(());
+    }
+    let (num_targets, num_bytes) = subject_usage(conn, subject)?;
+    if let Some(max_targets) = cmd_opts
+        .max_subject_targets
+        .filter(|max| num_targets >= *max)
+    {
+        return Err(resource_exhausted(format!(
+            "subject {} is over quota: {} of {} targets used",
+            subject, num_targets, max_targets
+        )));
+    }
+    if let Some(max_bytes) = cmd_opts.max_subject_bytes.filter(|max| num_bytes >= *max) {
+        return Err(resource_exhausted(format!(
+            "subject {} is over quota: {} of {} bytes used",
+            subject, num_bytes, max_bytes
+        )));
+    }
+    Ok(())
+}
+
+/// Returns when a target created now with the TTL requested by the client
+/// expires. The TTL is capped at `--max-ttl-secs`, which also applies to
+/// uploads that do not request one.
+fn expires_at(cmd_opts: &CmdOptions, created_at: u64, ttl_secs: Option<u64>) -> Option<u64> {
+    let ttl_secs = match (ttl_secs, cmd_opts.max_ttl_secs) {
+        (Some(ttl_secs), Some(max_ttl_secs)) => Some(ttl_secs.min(max_ttl_secs)),
+        (ttl_secs, max_ttl_secs) => ttl_secs.or(max_ttl_secs),
+    };
+    ttl_secs.map(|ttl_secs| created_at.saturating_add(ttl_secs))
+}
+
+/// A target about to be created, named and given its data key before the
+/// upload into it is received.
+struct NewTarget {
+    target: String,
+    tbl_name: String,
+    backend: Backend,
+    /// The key of the values of a SQLite target when there is a master key.
+    data_key: Option<DataKey>,
+}
+
+impl NewTarget {
+    fn generate(cmd_opts: &CmdOptions, conn: &Connection, subject: &str) -> anyhow::Result<Self> {
+        if !is_valid_sqlid(subject) {
+            return Err(anyhow::anyhow!("Invalid subject name: {}", subject));
+        }
+        let backend = Backend::from_cmd_opts(cmd_opts);
+        let data_key = match backend {
+            Backend::Sqlite if crypto::master_key(cmd_opts)?.is_some() => Some(DataKey::generate()),
+            _ => None,
+        };
+        for _ in 0..MAX_TARGET_GENERATION_ATTEMPTS {
+            let target = generate_target();
+            let tbl_name = format!("{}_{}", subject, target);
+            if target_backend(conn, &tbl_name)?.is_none() {
+                return Ok(NewTarget {
+                    target,
+                    tbl_name,
+                    backend,
+                    data_key,
+                });
+            }
+        }
+
+        Err(anyhow::anyhow!(
+            "Failed to generate a unique storage target after {} attempts",
+            MAX_TARGET_GENERATION_ATTEMPTS
+        ))
+    }
+}
+
+/// Returns the statement creating a SQLite table with the columns of `schema`.
+fn create_table_sql(tbl_name: &str, schema: &SchemaRef) -> anyhow::Result<String> {
+    let mut sql = format!("CREATE TABLE {} (", tbl_name);
+    for (i, field) in schema.fields.iter().enumerate() {
+        let field_name = field.name();
+        if !is_valid_sqlid(field_name) {
+            return Err(anyhow::anyhow!("Invalid field name: {}", field_name));
+        }
+        if i > 0 {
+            sql.push_str(", ");
+        }
+        sql.push_str(&format!(
+            "{} {}",
+            field_name,
+            arrow_to_sql_type(field.data_type())?
+        ));
     }
+    sql.push(')');
+    Ok(sql)
 }
 
+/// Creates the metadata of `new_target`, and its table for the SQLite
+/// backend, after checking the quota of `subject`. Going over the quota is
+/// `tonic::Status::resource_exhausted`.
 fn create_storage_in_tx(
+    cmd_opts: &CmdOptions,
     tx: &Transaction<'_>,
     subject: &str,
+    new_target: &NewTarget,
     schema: SchemaRef,
     policy: Option<String>,
-) -> anyhow::Result<String> {
+    ttl_secs: Option<u64>,
+) -> anyhow::Result<()> {
     ensure_storage_metadata_tables(tx)?;
-    if !is_valid_sqlid(subject) {
-        return Err(anyhow::anyhow!("Invalid subject name: {}", subject));
+    check_quota(cmd_opts, tx, subject)?;
+    let tbl_name = &new_target.tbl_name;
+    if target_backend(tx, tbl_name)?.is_some() {
+        return Err(anyhow::anyhow!(
+            "storage target {} already exists",
+            new_target.target
+        ));
     }
-    for _ in 0..MAX_TARGET_GENERATION_ATTEMPTS {
-        let target = generate_target();
-        let tbl_name = format!("{}_{}", subject, target);
-        if table_exists(tx, &tbl_name)? {
-            continue;
-        }
-
-        let mut sql = format!("CREATE TABLE {} (", tbl_name);
-        for (i, field) in schema.fields.iter().enumerate() {
-            let field_name = field.name();
-            if !is_valid_sqlid(field_name) {
-                return Err(anyhow::anyhow!("Invalid field name: {}", field_name));
-            }
-            if i > 0 {
-                sql.push_str(", ");
-            }
-            sql.push_str(&format!(
-                "{} {}",
-                field_name,
-                arrow_to_sql_type(field.data_type())
-            ));
-        }
-        sql.push(')');
 
+    let sql = create_table_sql(tbl_name, &schema)?;
+    if new_target.backend == Backend::Sqlite {
         tx.execute(&sql, [])
             .with_context(|| format!("failed to create storage table {}", tbl_name))?;
-        for field in schema.fields.iter() {
-            let arrow_type = arrow_type_name(field.data_type())?;
-            tx.execute(
-                "INSERT INTO storage_schema (table_name, column_name, arrow_type) VALUES (?, ?, ?)",
-                rusqlite::params![&tbl_name, field.name(), arrow_type],
-            )
-            .with_context(|| {
-                format!(
-                    "failed to persist schema metadata for {}.{}",
-                    tbl_name,
-                    field.name()
-                )
-            })?;
+        if let (Some(data_key), Some(master_key)) =
+            (&new_target.data_key, crypto::master_key(cmd_opts)?)
+        {
+            insert_table_key(tx, tbl_name, &master_key, data_key)?;
         }
-        if let Some(policy) = policy {
-            tx.execute(
-                "INSERT INTO policy (table_name, json) VALUES (?, ?)",
-                rusqlite::params![&tbl_name, &policy],
+    }
+    for field in schema.fields.iter() {
+        let arrow_type = arrow_type_name(field.data_type())?;
+        tx.execute(
+            "INSERT INTO storage_schema (table_name, column_name, arrow_type) VALUES (?, ?, ?)",
+            rusqlite::params![tbl_name, field.name(), arrow_type],
+        )
+        .with_context(|| {
+            format!(
+                "failed to persist schema metadata for {}.{}",
+                tbl_name,
+                field.name()
             )
-            .with_context(|| format!("failed to persist policy for table {}", tbl_name))?;
-        }
-        return Ok(target);
+        })?;
     }
-
-    Err(anyhow::anyhow!(
-        "Failed to generate a unique storage target after {} attempts",
-        MAX_TARGET_GENERATION_ATTEMPTS
-    ))
+    if let Some(policy) = policy {
+        tx.execute(
+            "INSERT INTO policy (table_name, json) VALUES (?, ?)",
+            rusqlite::params![tbl_name, &policy],
+        )
+        .with_context(|| format!("failed to persist policy for table {}", tbl_name))?;
+    }
+    let created_at = created_at(&new_target.target);
+    tx.execute(
+        "INSERT INTO storage_target (table_name, created_at, backend, expires_at)
+        VALUES (?, ?, ?, ?)",
+        rusqlite::params![
+            tbl_name,
+            created_at,
+            new_target.backend.name(),
+            expires_at(cmd_opts, created_at, ttl_secs)
+        ],
+    )
+    .with_context(|| format!("failed to persist target metadata for {}", tbl_name))?;
+    Ok(())
 }
 
 pub fn create_storage(
@@ -156,195 +626,657 @@ pub fn create_storage(
     schema: SchemaRef,
     policy: Option<String>,
 ) -> anyhow::Result<String> {
-    let mut conn = Connection::open_with_flags(
-        cmd_opts.storage_db.as_str(),
+    let mut conn = open_storage_db(
+        cmd_opts,
         OpenFlags::SQLITE_OPEN_READ_WRITE | OpenFlags::SQLITE_OPEN_CREATE,
     )?;
-    let tx = conn.transaction()?;
-    let target = create_storage_in_tx(&tx, subject, schema, policy)?;
+    let new_target = NewTarget::generate(cmd_opts, &conn, subject)?;
+    let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+    create_storage_in_tx(cmd_opts, &tx, subject, &new_target, schema, policy, None)?;
     tx.commit()?;
-    Ok(target)
+    Ok(new_target.target)
 }
 
 enum StorageValue {
     Null,
     Boolean(bool),
-    Int32(i32),
-    Float32(f32),
+    Int64(i64),
+    Float64(f64),
     Utf8(String),
     Blob(Vec<u8>),
 }
 
-impl rusqlite::types::ToSql for StorageValue {
-    fn to_sql(&self) -> rusqlite::Result<rusqlite::types::ToSqlOutput<'_>> {
-        match self {
-            StorageValue::Null => Ok(rusqlite::types::ToSqlOutput::from(rusqlite::types::Null)),
-            StorageValue::Boolean(v) => {
-                Ok(rusqlite::types::ToSqlOutput::from(if *v { 1 } else { 0 }))
-            }
-            StorageValue::Int32(v) => Ok(rusqlite::types::ToSqlOutput::from(*v)),
-            StorageValue::Float32(v) => Ok(rusqlite::types::ToSqlOutput::from(*v)),
-            StorageValue::Utf8(v) => Ok(rusqlite::types::ToSqlOutput::from(v.as_str())),
-            StorageValue::Blob(v) => Ok(rusqlite::types::ToSqlOutput::from(v.as_slice())),
+impl From<StorageValue> for Value {
+    fn from(value: StorageValue) -> Self {
+        match value {
+            StorageValue::Null => Value::Null,
+            StorageValue::Boolean(v) => Value::Integer(if v { 1 } else { 0 }),
+            StorageValue::Int64(v) => Value::Integer(v),
+            StorageValue::Float64(v) => Value::Real(v),
+            StorageValue::Utf8(v) => Value::Text(v),
+            StorageValue::Blob(v) => Value::Blob(v),
         }
     }
 }
 
-fn insert_batch_in_tx(
+/// Stores the data key of the values of a SQLite table wrapped by
+/// `master_key`.
+fn insert_table_key(
     tx: &Transaction<'_>,
-    subject: &str,
-    target: &str,
-    batch: RecordBatch,
+    tbl_name: &str,
+    master_key: &MasterKey,
+    data_key: &DataKey,
 ) -> anyhow::Result<()> {
-    let tbl_name = format!("{}_{}", subject, target);
-    if !is_valid_sqlid(&tbl_name) {
-        return Err(anyhow::anyhow!("Invalid table name"));
+    tx.execute(
+        "INSERT INTO storage_key (table_name, key_id, wrapped_key) VALUES (?, ?, ?)",
+        rusqlite::params![tbl_name, master_key.id(), master_key.wrap(data_key)?],
+    )
+    .with_context(|| format!("failed to persist data key for {}", tbl_name))?;
+    Ok(())
+}
+
+/// Returns the data key that encrypts the values of a SQLite table, or `None`
+/// if the table is kept in plaintext.
+fn table_key(
+    cmd_opts: &CmdOptions,
+    conn: &Connection,
+    tbl_name: &str,
+) -> anyhow::Result<Option<DataKey>> {
+    if !table_exists(conn, "storage_key")? {
+        return Ok(None);
+    }
+    let Some((key_id, wrapped_key)) = conn
+        .query_row(
+            "SELECT key_id, wrapped_key FROM storage_key WHERE table_name = ?",
+            rusqlite::params![tbl_name],
+            |row| Ok((row.get::<_, String>(0)?, row.get::<_, Vec<u8>>(1)?)),
+        )
+        .optional()?
+    else {
+        return Ok(None);
+    };
+    let master_key = crypto::master_key(cmd_opts)?.ok_or_else(|| {
+        anyhow::anyhow!("{} is encrypted but no master key is configured", tbl_name)
+    })?;
+    if key_id != master_key.id() {
+        return Err(anyhow::anyhow!(
+            "{} is encrypted with master key {}, not {}",
+            tbl_name,
+            key_id,
+            master_key.id()
+        ));
     }
+    Ok(Some(master_key.unwrap(&wrapped_key)?))
+}
 
-    for i in 0..batch.num_rows() {
-        let mut params = Vec::new();
+/// Returns the associated data binding an encrypted value to its column.
+fn value_aad(tbl_name: &str, column_name: &str) -> String {
+    format!("{}.{}", tbl_name, column_name)
+}
 
-        let mut sql = format!("INSERT INTO {} (", tbl_name);
-        for (field_idx, field) in batch.schema_ref().fields.iter().enumerate() {
-            let field_name = field.name();
-            if !is_valid_sqlid(field_name) {
-                return Err(anyhow::anyhow!("Invalid field name: {}", field_name));
-            }
-            if field_idx > 0 {
-                sql.push_str(", ");
-            }
-            sql.push_str(field_name);
-        }
-        sql.push_str(") VALUES (");
+/// Encrypts a value into a blob whose first plaintext byte records the SQLite
+/// type of the value. NULLs are kept as is.
+fn seal_value(data_key: &DataKey, aad: &str, value: Value) -> anyhow::Result<Value> {
+    let (tag, payload) = match value {
+        Value::Null => return Ok(Value::Null),
+        Value::Integer(v) => (1u8, v.to_le_bytes().to_vec()),
+        Value::Real(v) => (2, v.to_le_bytes().to_vec()),
+        Value::Text(v) => (3, v.into_bytes()),
+        Value::Blob(v) => (4, v),
+    };
+    let mut plaintext = Vec::with_capacity(1 + payload.len());
+    plaintext.push(tag);
+    plaintext.extend_from_slice(&payload);
+    Ok(Value::Blob(data_key.encrypt(aad.as_bytes(), &plaintext)?))
+}
 
-        for (col_idx, column) in batch.columns().iter().enumerate() {
-            let field = batch.schema_ref().field(col_idx);
-            let field_type = field.data_type();
+/// Decrypts a value written by `seal_value`.
+fn open_value(data_key: &DataKey, aad: &str, value: Value) -> anyhow::Result<Value> {
+    let sealed = match value {
+        Value::Null => return Ok(Value::Null),
+        Value::Blob(sealed) => sealed,
+        _ => return Err(anyhow::anyhow!("{} holds a value in plaintext", aad)),
+    };
+    let plaintext = data_key
+        .decrypt(aad.as_bytes(), &sealed)
+        .with_context(|| format!("failed to decrypt a value of {}", aad))?;
+    let Some((tag, payload)) = plaintext.split_first() else {
+        return Err(anyhow::anyhow!("{} holds an empty encrypted value", aad));
+    };
+    match tag {
+        1 => Ok(Value::Integer(i64::from_le_bytes(payload.try_into()?))),
+        2 => Ok(Value::Real(f64::from_le_bytes(payload.try_into()?))),
+        3 => Ok(Value::Text(String::from_utf8(payload.to_vec())?)),
+        4 => Ok(Value::Blob(payload.to_vec())),
+        tag => Err(anyhow::anyhow!(
+            "{} holds an encrypted value of unknown type {}",
+            aad,
+            tag
+        )),
+    }
+}
 
-            if col_idx > 0 {
-                sql.push_str(", ");
-            }
-            sql.push('?');
+/// Inserts the rows of `batch` for the table `tbl_name` into `table` with one
+/// prepared statement, which is cached on the connection and reused for every
+/// batch sent to the same table. Values are encrypted if the table has a data
+/// key.
+fn insert_batch_in_tx(
+    tx: &Transaction<'_>,
+    table: &str,
+    tbl_name: &str,
+    data_key: Option<&DataKey>,
+    batch: RecordBatch,
+) -> anyhow::Result<()> {
+    let mut sql = format!("INSERT INTO {} (", table);
+    for (field_idx, field) in batch.schema_ref().fields.iter().enumerate() {
+        let field_name = field.name();
+        if !is_valid_sqlid(field_name) {
+            return Err(anyhow::anyhow!("Invalid field name: {}", field_name));
+        }
+        if field_idx > 0 {
+            sql.push_str(", ");
+        }
+        sql.push_str(field_name);
+    }
+    sql.push_str(") VALUES (");
+    for col_idx in 0..batch.num_columns() {
+        if col_idx > 0 {
+            sql.push_str(", ");
+        }
+        sql.push('?');
+    }
+    sql.push(')');
+    let mut stmt = tx.prepare_cached(&sql)?;
+
+    let columns = batch
+        .columns()
+        .iter()
+        .zip(batch.schema_ref().fields())
+        .map(|(column, field)| {
+            to_storage_array(column, field.data_type())
+                .with_context(|| format!("failed to convert column {}", field.name()))
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    let aads = batch
+        .schema_ref()
+        .fields()
+        .iter()
+        .map(|field| value_aad(tbl_name, field.name()))
+        .collect::<Vec<_>>();
 
+    for i in 0..batch.num_rows() {
+        let mut params = Vec::with_capacity(columns.len());
+        for column in &columns {
             if column.is_null(i) {
                 params.push(StorageValue::Null);
                 continue;
             }
 
-            match field_type {
+            match column.data_type() {
                 DataType::Boolean => {
                     let value = column.as_boolean().value(i);
                     params.push(StorageValue::Boolean(value));
                 }
-                DataType::Int32 => {
-                    let value = column
-                        .as_primitive::<arrow::datatypes::Int32Type>()
-                        .value(i);
-                    params.push(StorageValue::Int32(value));
+                DataType::Int64 => {
+                    let value = column.as_primitive::<Int64Type>().value(i);
+                    params.push(StorageValue::Int64(value));
                 }
-                DataType::Float32 => {
-                    let value = column
-                        .as_primitive::<arrow::datatypes::Float32Type>()
-                        .value(i);
-                    params.push(StorageValue::Float32(value));
+                DataType::Float64 => {
+                    let value = column.as_primitive::<Float64Type>().value(i);
+                    params.push(StorageValue::Float64(value));
                 }
                 DataType::Utf8 => {
                     let value = array::as_string_array(column).value(i);
                     params.push(StorageValue::Utf8(value.to_string()));
                 }
                 DataType::Binary => {
-                    let value = column
-                        .as_any()
-                        .downcast_ref::<arrow::array::BinaryArray>()
-                        .unwrap()
-                        .value(i);
+                    let value = column.as_binary::<i32>().value(i);
                     params.push(StorageValue::Blob(value.to_vec()));
                 }
-                _ => {
-                    return Err(anyhow::anyhow!(
-                        "Unsupported column type: {:?} for field: {}",
-                        field_type,
-                        field.name()
-                    ));
+                DataType::Decimal128(_, _) => {
+                    let value = column.as_primitive::<Decimal128Type>().value(i);
+                    params.push(StorageValue::Utf8(value.to_string()));
+                }
+                data_type => {
+                    return Err(anyhow::anyhow!("Unexpected storage type: {:?}", data_type));
                 }
             }
         }
-        sql.push(')');
-
-        let mut stmt = tx.prepare(&sql)?;
+        let params = params
+            .into_iter()
+            .zip(&aads)
+            .map(|(value, aad)| match data_key {
+                Some(data_key) => seal_value(data_key, aad, value.into()),
+                None => Ok(value.into()),
+            })
+            .collect::<anyhow::Result<Vec<Value>>>()?;
         stmt.execute(rusqlite::params_from_iter(params.iter()))?;
     }
     Ok(())
 }
 
-pub fn store_data(
-    cmd_opts: &CmdOptions,
-    subject: &str,
-    schema: SchemaRef,
-    policy: Option<String>,
-    batches: Vec<RecordBatch>,
-) -> anyhow::Result<String> {
-    let mut conn = Connection::open_with_flags(
-        cmd_opts.storage_db.as_str(),
-        OpenFlags::SQLITE_OPEN_READ_WRITE | OpenFlags::SQLITE_OPEN_CREATE,
-    )?;
-    let tx = conn.transaction()?;
-    let target = create_storage_in_tx(&tx, subject, schema, policy)?;
-    for batch in batches {
-        insert_batch_in_tx(&tx, subject, &target, batch)?;
+fn resource_exhausted(message: String) -> anyhow::Error {
+    tonic::Status::resource_exhausted(message).into()
+}
+
+/// Checks `schema` against the upload limits of `cmd_opts`.
+fn check_columns(cmd_opts: &CmdOptions, schema: &SchemaRef) -> anyhow::Result<()> {
+    if let Some(max_columns) = cmd_opts.max_put_columns {
+        if schema.fields().len() > max_columns {
+            return Err(resource_exhausted(format!(
+                "upload has {} columns, more than the limit of {}",
+                schema.fields().len(),
+                max_columns
+            )));
+        }
     }
-    tx.commit()?;
-    Ok(target)
+    Ok(())
 }
 
-pub fn insert_data(
+/// The rows and bytes of an upload so far, checked against `--max-put-rows`,
+/// `--max-put-bytes` and what is left of `--max-subject-bytes` as each batch
+/// arrives.
+struct UploadUsage {
+    num_rows: u64,
+    num_bytes: u64,
+    /// The bytes the subject may still store, if it has a quota.
+    quota_bytes: Option<u64>,
+}
+
+impl UploadUsage {
+    fn new(cmd_opts: &CmdOptions, conn: &Connection, subject: &str) -> anyhow::Result<Self> {
+        let quota_bytes = match cmd_opts.max_subject_bytes {
+            Some(max_bytes) => Some(max_bytes.saturating_sub(subject_usage(conn, subject)?.1)),
+            None => None,
+        };
+        Ok(UploadUsage {
+            num_rows: 0,
+            num_bytes: 0,
+            quota_bytes,
+        })
+    }
+
+    fn add(&mut self, cmd_opts: &CmdOptions, batch: &RecordBatch) -> anyhow::Result<()> {
+        self.num_rows += batch.num_rows() as u64;
+        if let Some(max_rows) = cmd_opts.max_put_rows.filter(|max| self.num_rows > *max) {
+            return Err(resource_exhausted(format!(
+                "upload exceeds the limit of {} rows",
+                max_rows
+            )));
+        }
+        for column in batch.columns() {
+            self.num_bytes += column.to_data().get_slice_memory_size()? as u64;
+        }
+        if let Some(max_bytes) = cmd_opts.max_put_bytes.filter(|max| self.num_bytes > *max) {
+            return Err(resource_exhausted(format!(
+                "upload exceeds the limit of {} bytes",
+                max_bytes
+            )));
+        }
+        if let Some(quota_bytes) = self.quota_bytes.filter(|quota| self.num_bytes > *quota) {
+            return Err(resource_exhausted(format!(
+                "upload exceeds the {} bytes left in the quota of the subject",
+                quota_bytes
+            )));
+        }
+        Ok(())
+    }
+
+    /// Checks the upload against the quota of `subject` again, now that the
+    /// storage database is locked, and adds its bytes to the usage of the
+    /// target.
+    fn record(
+        &self,
+        cmd_opts: &CmdOptions,
+        tx: &Transaction<'_>,
+        subject: &str,
+        tbl_name: &str,
+    ) -> anyhow::Result<()> {
+        if let Some(max_bytes) = cmd_opts.max_subject_bytes {
+            let quota_bytes = max_bytes.saturating_sub(subject_usage(tx, subject)?.1);
+            if self.num_bytes > quota_bytes {
+                return Err(resource_exhausted(format!(
+                    "upload exceeds the {} bytes left in the quota of the subject",
+                    quota_bytes
+                )));
+            }
+        }
+        tx.execute(
+            "INSERT INTO storage_target (table_name, created_at, num_bytes) VALUES (?, ?, ?)
+            ON CONFLICT (table_name) DO UPDATE SET num_bytes = num_bytes + excluded.num_bytes",
+            rusqlite::params![tbl_name, now_secs(), self.num_bytes],
+        )?;
+        Ok(())
+    }
+}
+
+/// The temporary table an upload to a SQLite target is received into.
+const UPLOAD_TABLE: &str = "temp.upload";
+
+/// Receives `batches` for the SQLite table `tbl_name` as they arrive into
+/// `UPLOAD_TABLE`, encrypted with `data_key` if the table has one. The
+/// temporary table is private to `conn`, so that `storage.db` is not locked
+/// while the client is sending.
+fn spool_batches(
     cmd_opts: &CmdOptions,
-    subject: &str,
-    target: &str,
-    batch: RecordBatch,
+    conn: &mut Connection,
+    tbl_name: &str,
+    schema: &SchemaRef,
+    data_key: Option<&DataKey>,
+    usage: &mut UploadUsage,
+    batches: impl IntoIterator<Item = anyhow::Result<RecordBatch>>,
 ) -> anyhow::Result<()> {
-    let mut conn = Connection::open_with_flags(
-        cmd_opts.storage_db.as_str(),
-        OpenFlags::SQLITE_OPEN_READ_WRITE,
-    )?;
+    conn.execute(&create_table_sql(UPLOAD_TABLE, schema)?, [])?;
     let tx = conn.transaction()?;
-    insert_batch_in_tx(&tx, subject, target, batch)?;
+    for batch in batches {
+        let batch = batch?;
+        usage.add(cmd_opts, &batch)?;
+        insert_batch_in_tx(&tx, UPLOAD_TABLE, tbl_name, data_key, batch)?;
+    }
     tx.commit()?;
     Ok(())
 }
 
-fn get_column_type(
-    conn: &Connection,
+/// Moves the rows received by `spool_batches` into their table.
+fn copy_spooled_rows(
+    tx: &Transaction<'_>,
     tbl_name: &str,
-    column_name: &str,
-) -> anyhow::Result<DataType> {
-    let mut stmt = conn.prepare(
-        "SELECT arrow_type FROM storage_schema WHERE table_name = ? AND column_name = ?",
+    schema: &SchemaRef,
+) -> anyhow::Result<()> {
+    let columns = schema
+        .fields()
+        .iter()
+        .map(|field| field.name().as_str())
+        .collect::<Vec<_>>()
+        .join(", ");
+    tx.execute(
+        &format!(
+            "INSERT INTO {} ({}) SELECT {} FROM {}",
+            tbl_name, columns, columns, UPLOAD_TABLE
+        ),
+        [],
     )?;
-    let arrow_type = stmt
-        .query_row(rusqlite::params![tbl_name, column_name], |row| {
-            row.get::<_, String>(0)
-        })
-        .optional()?;
-    if let Some(arrow_type) = arrow_type {
-        return match arrow_type.as_str() {
-            "bool" => Ok(DataType::Boolean),
-            "int32" => Ok(DataType::Int32),
-            "float32" => Ok(DataType::Float32),
-            "utf8" => Ok(DataType::Utf8),
-            "binary" => Ok(DataType::Binary),
-            _ => Err(anyhow::anyhow!(
-                "Unsupported stored column type: {}",
-                arrow_type
-            )),
-        };
+    Ok(())
+}
+
+/// Returns the directory holding the Parquet files of a target.
+fn parquet_dir(cmd_opts: &CmdOptions, tbl_name: &str) -> PathBuf {
+    Path::new(&cmd_opts.parquet_path)
+        .join("storage")
+        .join(tbl_name)
+}
+
+/// Returns the Parquet files of a target in the order they were written.
+fn parquet_parts(dir: &Path) -> anyhow::Result<Vec<PathBuf>> {
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e.into()),
+    };
+    let mut parts = Vec::new();
+    for entry in entries {
+        let path = entry?.path();
+        let name = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .unwrap_or("");
+        if name.starts_with("part-") && name.ends_with(".parquet") {
+            parts.push(path);
+        }
     }
+    parts.sort();
+    Ok(parts)
+}
 
-    let pragma = format!("PRAGMA table_info({})", tbl_name);
-    let mut stmt = conn.prepare(&pragma)?;
-    let declared_type = stmt
-        .query_map([], |row| {
-            Ok((row.get::<_, String>(1)?, row.get::<_, String>(2)?))
+/// Writes `batches` as a new Parquet file of a target as they arrive and
+/// returns its path. `FileWriter` only moves the file to its path once
+/// complete, so that readers never see a partial upload.
+fn write_parquet_part(
+    cmd_opts: &CmdOptions,
+    tbl_name: &str,
+    schema: SchemaRef,
+    usage: &mut UploadUsage,
+    batches: impl IntoIterator<Item = anyhow::Result<RecordBatch>>,
+) -> anyhow::Result<PathBuf> {
+    let dir = parquet_dir(cmd_opts, tbl_name);
+    std::fs::create_dir_all(&dir).with_context(|| format!("failed to create {}", dir.display()))?;
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("Time went backwards");
+    let mut random_bytes = [0u8; 4];
+    OsRng.fill_bytes(&mut random_bytes);
+    let path = dir.join(format!(
+        "part-{:020}-{:08x}.parquet",
+        now.as_nanos(),
+        u32::from_be_bytes(random_bytes)
+    ));
+
+    let props = WriterProperties::builder()
+        .set_compression(Compression::SNAPPY)
+        .build();
+    let file = crypto::FileWriter::create(cmd_opts, &path)?;
+    let mut writer = ArrowWriter::try_new(file, schema, Some(props))?;
+    for batch in batches {
+        let batch = batch?;
+        usage.add(cmd_opts, &batch)?;
+        writer.write(&batch)?;
+    }
+    writer.into_inner()?.finish()?;
+    Ok(path)
+}
+
+/// Runs `commit` in a write transaction once an upload was received, so that
+/// `storage.db` is only locked for as long as it takes to record it. The
+/// Parquet file written for the upload is removed again if it fails.
+fn commit_upload(
+    conn: &mut Connection,
+    part: Option<PathBuf>,
+    commit: impl FnOnce(&Transaction<'_>) -> anyhow::Result<()>,
+) -> anyhow::Result<()> {
+    let commit_in_tx = || -> anyhow::Result<()> {
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        commit(&tx)?;
+        tx.commit()?;
+        Ok(())
+    };
+    let result = commit_in_tx();
+    if result.is_err() {
+        if let Some(part) = part {
+            let _ = std::fs::remove_file(part);
+        }
+    }
+    result
+}
+
+/// Creates a new target and stores `batches` in it as they arrive. An upload
+/// that goes over `--max-put-rows`, `--max-put-bytes` or `--max-put-columns`,
+/// or a batch that is an error, fails without leaving a partial target
+/// behind. So does going over the quota of `subject`. Limit and quota errors
+/// are `tonic::Status::resource_exhausted`. The target expires after
+/// `ttl_secs`, capped at `--max-ttl-secs`. The upload is received before the
+/// storage database is locked for writing, and recorded in one transaction.
+pub fn store_data(
+    cmd_opts: &CmdOptions,
+    subject: &str,
+    schema: SchemaRef,
+    policy: Option<String>,
+    ttl_secs: Option<u64>,
+    batches: impl IntoIterator<Item = anyhow::Result<RecordBatch>>,
+) -> anyhow::Result<String> {
+    check_columns(cmd_opts, &schema)?;
+    let mut conn = open_storage_db(
+        cmd_opts,
+        OpenFlags::SQLITE_OPEN_READ_WRITE | OpenFlags::SQLITE_OPEN_CREATE,
+    )?;
+    ensure_storage_metadata_tables(&conn)?;
+    let new_target = NewTarget::generate(cmd_opts, &conn, subject)?;
+    check_quota(cmd_opts, &conn, subject)?;
+    let tbl_name = &new_target.tbl_name;
+    let mut usage = UploadUsage::new(cmd_opts, &conn, subject)?;
+    let part = match new_target.backend {
+        Backend::Sqlite => {
+            spool_batches(
+                cmd_opts,
+                &mut conn,
+                tbl_name,
+                &schema,
+                new_target.data_key.as_ref(),
+                &mut usage,
+                batches,
+            )?;
+            None
+        }
+        Backend::Parquet => Some(write_parquet_part(
+            cmd_opts,
+            tbl_name,
+            schema.clone(),
+            &mut usage,
+            batches,
+        )?),
+    };
+    commit_upload(&mut conn, part, |tx| {
+        create_storage_in_tx(
+            cmd_opts,
+            tx,
+            subject,
+            &new_target,
+            schema.clone(),
+            policy,
+            ttl_secs,
+        )?;
+        if new_target.backend == Backend::Sqlite {
+            copy_spooled_rows(tx, tbl_name, &schema)?;
+        }
+        usage.record(cmd_opts, tx, subject, tbl_name)
+    })?;
+    Ok(new_target.target)
+}
+
+/// Appends `batches` to an existing target of `subject`, with the same limits
+/// as `store_data`. `schema` must have the columns and types recorded in
+/// `storage_schema` for the target; a mismatch is
+/// `tonic::Status::invalid_argument` and a target of another subject is
+/// `tonic::Status::not_found`.
+pub fn append_data(
+    cmd_opts: &CmdOptions,
+    subject: &str,
+    target: &str,
+    schema: SchemaRef,
+    batches: impl IntoIterator<Item = anyhow::Result<RecordBatch>>,
+) -> anyhow::Result<()> {
+    check_columns(cmd_opts, &schema)?;
+    let mut conn = open_storage_db(cmd_opts, OpenFlags::SQLITE_OPEN_READ_WRITE)?;
+    ensure_storage_metadata_tables(&conn)?;
+    let (tbl_name, _) = owned_table(&conn, subject, target)?;
+
+    let mut stmt = conn.prepare(
+        "SELECT column_name, arrow_type FROM storage_schema WHERE table_name = ? ORDER BY rowid",
+    )?;
+    let stored = stmt
+        .query_map(rusqlite::params![&tbl_name], |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+        })?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    drop(stmt);
+    let incoming = schema
+        .fields()
+        .iter()
+        .map(|field| {
+            Ok((
+                field.name().clone(),
+                arrow_type_name(field.data_type())?.to_string(),
+            ))
+        })
+        .collect::<anyhow::Result<Vec<_>>>()
+        .map_err(|e| tonic::Status::invalid_argument(e.to_string()))?;
+    if stored != incoming {
+        return Err(tonic::Status::invalid_argument(format!(
+            "schema does not match target {}: expected {:?}, got {:?}",
+            target, stored, incoming
+        ))
+        .into());
+    }
+
+    add_to_target(cmd_opts, conn, subject, target, schema, batches)
+}
+
+pub fn insert_data(
+    cmd_opts: &CmdOptions,
+    subject: &str,
+    target: &str,
+    batch: RecordBatch,
+) -> anyhow::Result<()> {
+    let conn = open_storage_db(cmd_opts, OpenFlags::SQLITE_OPEN_READ_WRITE)?;
+    ensure_storage_metadata_tables(&conn)?;
+    add_to_target(cmd_opts, conn, subject, target, batch.schema(), [Ok(batch)])
+}
+
+/// Receives `batches` for an existing target of `subject` and records them
+/// once the storage database is locked, checking again that the target was
+/// not deleted in the meantime.
+fn add_to_target(
+    cmd_opts: &CmdOptions,
+    mut conn: Connection,
+    subject: &str,
+    target: &str,
+    schema: SchemaRef,
+    batches: impl IntoIterator<Item = anyhow::Result<RecordBatch>>,
+) -> anyhow::Result<()> {
+    let (tbl_name, backend) = owned_table(&conn, subject, target)?;
+    let mut usage = UploadUsage::new(cmd_opts, &conn, subject)?;
+    let part = match backend {
+        Backend::Sqlite => {
+            let data_key = table_key(cmd_opts, &conn, &tbl_name)?;
+            spool_batches(
+                cmd_opts,
+                &mut conn,
+                &tbl_name,
+                &schema,
+                data_key.as_ref(),
+                &mut usage,
+                batches,
+            )?;
+            None
+        }
+        Backend::Parquet => Some(write_parquet_part(
+            cmd_opts,
+            &tbl_name,
+            schema.clone(),
+            &mut usage,
+            batches,
+        )?),
+    };
+    commit_upload(&mut conn, part, |tx| {
+        owned_table(tx, subject, target)?;
+        if backend == Backend::Sqlite {
+            copy_spooled_rows(tx, &tbl_name, &schema)?;
+        }
+        usage.record(cmd_opts, tx, subject, &tbl_name)
+    })
+}
+
+fn get_column_type(
+    conn: &Connection,
+    tbl_name: &str,
+    column_name: &str,
+) -> anyhow::Result<DataType> {
+    let mut stmt = conn.prepare(
+        "SELECT arrow_type FROM storage_schema WHERE table_name = ? AND column_name = ?",
+    )?;
+    let arrow_type = stmt
+        .query_row(rusqlite::params![tbl_name, column_name], |row| {
+            row.get::<_, String>(0)
+        })
+        .optional()?;
+    if let Some(arrow_type) = arrow_type {
+        return arrow_type_from_name(&arrow_type);
+    }
+
+    let pragma = format!("PRAGMA table_info({})", tbl_name);
+    let mut stmt = conn.prepare(&pragma)?;
+    let declared_type = stmt
+        .query_map([], |row| {
+            Ok((row.get::<_, String>(1)?, row.get::<_, String>(2)?))
         })?
         .find_map(|row| match row {
             Ok((name, ty)) if name == column_name => Some(Ok(ty)),
@@ -366,176 +1298,934 @@ fn get_column_type(
     }
 }
 
-pub fn get_data(
+/// Opens the table of `target` read-only after checking that the requested
+/// column names are valid SQL identifiers, and returns the backend its rows
+/// are kept in. See `readable_table` for how `target` is resolved.
+fn open_table(
     cmd_opts: &CmdOptions,
-    subject: &str,
+    subject: &Subject,
     target: &str,
-    column_name: &str,
-) -> anyhow::Result<Vec<RecordBatch>> {
-    let conn = Connection::open_with_flags(
-        cmd_opts.storage_db.as_str(),
-        OpenFlags::SQLITE_OPEN_READ_ONLY,
-    )?;
+    column_names: &[&str],
+) -> anyhow::Result<(Connection, String, Backend)> {
+    let conn = open_storage_db(cmd_opts, OpenFlags::SQLITE_OPEN_READ_ONLY)?;
 
-    let tbl_name = format!("{}_{}", subject, target);
-    if !is_valid_sqlid(&tbl_name) {
-        return Err(anyhow::anyhow!("Invalid table name: {}", tbl_name));
+    for column_name in column_names {
+        if !is_valid_sqlid(column_name) {
+            return Err(anyhow::anyhow!("Invalid column name: {}", column_name));
+        }
+    }
+    let (tbl_name, backend) = readable_table(&conn, subject, target)?;
+    Ok((conn, tbl_name, backend))
+}
+
+/// Returns the table and the backend of a target `subject` may read: its own
+/// target, or a target of another subject granted to it or to one of its
+/// groups. `target` must be a generated target ID, so that a target such as
+/// `bob_<id>` never reaches the table of a subject named `<subject>_bob`.
+/// Anything else is `tonic::Status::not_found`, so that targets of other
+/// subjects cannot be probed.
+fn readable_table(
+    conn: &Connection,
+    subject: &Subject,
+    target: &str,
+) -> anyhow::Result<(String, Backend)> {
+    if !is_generated_target(target) {
+        return Err(tonic::Status::not_found(format!("target {} not found", target)).into());
+    }
+    let tbl_name = format!("{}_{}", subject.name, target);
+    if is_valid_sqlid(&tbl_name) {
+        if let Some(backend) = target_backend(conn, &tbl_name)? {
+            return Ok((tbl_name, backend));
+        }
+    }
+    for tbl_name in granted_tables(conn, subject, target)? {
+        if let Some(backend) = target_backend(conn, &tbl_name)? {
+            return Ok((tbl_name, backend));
+        }
+    }
+    Err(tonic::Status::not_found(format!("target {} not found", target)).into())
+}
+
+/// Returns the tables of `target` granted to `subject` or its groups.
+fn granted_tables(
+    conn: &Connection,
+    subject: &Subject,
+    target: &str,
+) -> anyhow::Result<Vec<String>> {
+    if !table_exists(conn, "storage_grant")? {
+        return Ok(Vec::new());
+    }
+    let suffix = format!("_{}", target);
+    let mut stmt = conn.prepare(
+        "SELECT table_name, grantee_type, grantee FROM storage_grant
+        WHERE substr(table_name, -?1) = ?2",
+    )?;
+    let rows = stmt.query_map(rusqlite::params![suffix.len(), &suffix], |row| {
+        Ok((
+            row.get::<_, String>(0)?,
+            row.get::<_, String>(1)?,
+            row.get::<_, String>(2)?,
+        ))
+    })?;
+    let mut tables = Vec::new();
+    for row in rows {
+        let (tbl_name, grantee_type, grantee) = row?;
+        let granted = match grantee_from_columns(&grantee_type, grantee) {
+            Some(Grantee::Subject(name)) => name == subject.name,
+            Some(Grantee::Group(group)) => subject.groups.contains(&group),
+            None => false,
+        };
+        if granted && !tables.contains(&tbl_name) {
+            tables.push(tbl_name);
+        }
     }
-    if !is_valid_sqlid(column_name) {
-        return Err(anyhow::anyhow!("Invalid column name: {}", column_name));
+    Ok(tables)
+}
+
+/// Returns the row count of a target.
+fn count_rows(
+    cmd_opts: &CmdOptions,
+    conn: &Connection,
+    tbl_name: &str,
+    backend: Backend,
+) -> anyhow::Result<i64> {
+    match backend {
+        Backend::Sqlite => {
+            let sql = format!("SELECT COUNT(*) FROM {}", tbl_name);
+            Ok(conn.query_row(&sql, [], |row| row.get::<_, i64>(0))?)
+        }
+        Backend::Parquet => {
+            let mut num_rows = 0;
+            for part in parquet_parts(&parquet_dir(cmd_opts, tbl_name))? {
+                let builder =
+                    ParquetRecordBatchReaderBuilder::try_new(StoredFile::open(cmd_opts, &part)?)?;
+                num_rows += builder.metadata().file_metadata().num_rows();
+            }
+            Ok(num_rows)
+        }
     }
+}
 
-    let sql = format!("SELECT {} FROM {}", column_name, tbl_name);
-    let column_type = get_column_type(&conn, &tbl_name, column_name)?;
+/// Returns the schema and the row count of stored columns.
+pub fn get_schema(
+    cmd_opts: &CmdOptions,
+    subject: &Subject,
+    target: &str,
+    column_names: &[&str],
+) -> anyhow::Result<(SchemaRef, i64)> {
+    let (conn, tbl_name, backend) = open_table(cmd_opts, subject, target, column_names)?;
 
-    let mut stmt = conn.prepare(&sql)?;
-    let batch = match column_type {
-        DataType::Boolean => {
-            let values = stmt
-                .query_map([], |row| row.get::<_, Option<i64>>(0))?
-                .collect::<Result<Vec<_>, _>>()?
-                .into_iter()
-                .map(|value| value.map(|value| value != 0))
-                .collect::<Vec<_>>();
-            let new_field = arrow_schema::Field::new(column_name, DataType::Boolean, true);
-            let new_array = Arc::new(arrow::array::BooleanArray::from(values)) as _;
-            RecordBatch::try_new(
-                Arc::new(arrow_schema::Schema::new(vec![new_field])),
-                vec![new_array],
-            )?
-        }
-        DataType::Int32 => {
-            let values = stmt
-                .query_map([], |row| row.get::<_, Option<i32>>(0))?
-                .collect::<Result<Vec<_>, _>>()?;
-            let new_field = arrow_schema::Field::new(column_name, DataType::Int32, true);
-            let new_array = Arc::new(arrow::array::Int32Array::from(values)) as _;
-            RecordBatch::try_new(
-                Arc::new(arrow_schema::Schema::new(vec![new_field])),
-                vec![new_array],
-            )?
-        }
-        DataType::Float32 => {
-            let values = stmt
-                .query_map([], |row| row.get::<_, Option<f32>>(0))?
-                .collect::<Result<Vec<_>, _>>()?;
-            let new_field = arrow_schema::Field::new(column_name, DataType::Float32, true);
-            let new_array = Arc::new(arrow::array::Float32Array::from(values)) as _;
-            RecordBatch::try_new(
-                Arc::new(arrow_schema::Schema::new(vec![new_field])),
-                vec![new_array],
-            )?
-        }
-        DataType::Utf8 => {
-            let values = stmt
-                .query_map([], |row| row.get::<_, Option<String>>(0))?
-                .collect::<Result<Vec<_>, _>>()?;
-            let new_field = arrow_schema::Field::new(column_name, DataType::Utf8, true);
-            let new_array = Arc::new(arrow::array::StringArray::from(values)) as _;
-            RecordBatch::try_new(
-                Arc::new(arrow_schema::Schema::new(vec![new_field])),
-                vec![new_array],
-            )?
-        }
-        DataType::Binary => {
-            let values = stmt
-                .query_map([], |row| row.get::<_, Option<Vec<u8>>>(0))?
-                .collect::<Result<Vec<_>, _>>()?;
-            let borrowed_values = values
+    let mut fields = Vec::new();
+    for column_name in column_names {
+        let column_type = get_column_type(&conn, &tbl_name, column_name)?;
+        fields.push(Field::new(*column_name, column_type, true));
+    }
+    let num_rows = count_rows(cmd_opts, &conn, &tbl_name, backend)?;
+    Ok((Arc::new(arrow_schema::Schema::new(fields)), num_rows))
+}
+
+/// Builds an Arrow array of `field` from the SQLite values of a column.
+fn column_to_array(field: &Field, values: Vec<Value>) -> anyhow::Result<ArrayRef> {
+    let unexpected = |value: &Value| {
+        anyhow::anyhow!(
+            "Unexpected value {:?} for column {} of type {:?}",
+            value,
+            field.name(),
+            field.data_type()
+        )
+    };
+    let array: ArrayRef = match storage_type(field.data_type())? {
+        DataType::Boolean => Arc::new(
+            values
+                .iter()
+                .map(|value| match value {
+                    Value::Null => Ok(None),
+                    Value::Integer(v) => Ok(Some(*v != 0)),
+                    _ => Err(unexpected(value)),
+                })
+                .collect::<anyhow::Result<array::BooleanArray>>()?,
+        ),
+        DataType::Int64 => Arc::new(
+            values
+                .iter()
+                .map(|value| match value {
+                    Value::Null => Ok(None),
+                    Value::Integer(v) => Ok(Some(*v)),
+                    _ => Err(unexpected(value)),
+                })
+                .collect::<anyhow::Result<array::Int64Array>>()?,
+        ),
+        DataType::Float64 => Arc::new(
+            values
+                .iter()
+                .map(|value| match value {
+                    Value::Null => Ok(None),
+                    Value::Integer(v) => Ok(Some(*v as f64)),
+                    Value::Real(v) => Ok(Some(*v)),
+                    _ => Err(unexpected(value)),
+                })
+                .collect::<anyhow::Result<array::Float64Array>>()?,
+        ),
+        DataType::Utf8 => Arc::new(
+            values
+                .iter()
+                .map(|value| match value {
+                    Value::Null => Ok(None),
+                    Value::Text(v) => Ok(Some(v.as_str())),
+                    _ => Err(unexpected(value)),
+                })
+                .collect::<anyhow::Result<array::StringArray>>()?,
+        ),
+        DataType::Binary => Arc::new(
+            values
                 .iter()
-                .map(|value| value.as_deref())
-                .collect::<Vec<_>>();
-            let new_field = arrow_schema::Field::new(column_name, DataType::Binary, true);
-            let new_array = Arc::new(arrow::array::BinaryArray::from(borrowed_values)) as _;
-            RecordBatch::try_new(
-                Arc::new(arrow_schema::Schema::new(vec![new_field])),
-                vec![new_array],
-            )?
-        }
-        _ => {
+                .map(|value| match value {
+                    Value::Null => Ok(None),
+                    Value::Blob(v) => Ok(Some(v.as_slice())),
+                    _ => Err(unexpected(value)),
+                })
+                .collect::<anyhow::Result<array::BinaryArray>>()?,
+        ),
+        DataType::Decimal128(precision, scale) => Arc::new(
+            values
+                .iter()
+                .map(|value| match value {
+                    Value::Null => Ok(None),
+                    Value::Text(v) => v.parse::<i128>().map(Some).map_err(|_| unexpected(value)),
+                    Value::Integer(v) => Ok(Some(*v as i128)),
+                    _ => Err(unexpected(value)),
+                })
+                .collect::<anyhow::Result<array::Decimal128Array>>()?
+                .with_precision_and_scale(precision, scale)?,
+        ),
+        data_type => {
             return Err(anyhow::anyhow!(
-                "Unsupported Arrow type {:?} for column {}",
-                column_type,
-                column_name
+                "Unexpected storage type {:?} for column {}",
+                data_type,
+                field.name()
             ));
         }
     };
-    Ok(vec![batch])
+    from_storage_array(array, field.data_type())
+        .with_context(|| format!("failed to convert column {}", field.name()))
 }
 
-pub fn get_policy(
+/// Streams columns of a Parquet target, reading only the requested columns of
+/// each file.
+fn get_parquet_data(
     cmd_opts: &CmdOptions,
-    subject: &str,
+    tbl_name: &str,
+    schema: SchemaRef,
+    batch_size: usize,
+    sink: &mut BatchSink,
+) -> anyhow::Result<()> {
+    let mut sent = false;
+    for part in parquet_parts(&parquet_dir(cmd_opts, tbl_name))? {
+        let builder = ParquetRecordBatchReaderBuilder::try_new(StoredFile::open(cmd_opts, &part)?)?;
+        let indices = schema
+            .fields()
+            .iter()
+            .map(|field| builder.schema().index_of(field.name()))
+            .collect::<Result<Vec<_>, _>>()?;
+        let mask = ProjectionMask::roots(builder.parquet_schema(), indices);
+        let reader = builder
+            .with_projection(mask)
+            .with_batch_size(batch_size)
+            .build()?;
+        for batch in reader {
+            // The projection keeps the file order of the columns.
+            let batch = batch?;
+            let columns = schema
+                .fields()
+                .iter()
+                .map(|field| {
+                    batch
+                        .column_by_name(field.name())
+                        .cloned()
+                        .ok_or_else(|| anyhow::anyhow!("Column {} not found", field.name()))
+                })
+                .collect::<anyhow::Result<Vec<_>>>()?;
+            sent = true;
+            if !sink(RecordBatch::try_new(schema.clone(), columns)?) {
+                return Ok(());
+            }
+        }
+    }
+    if !sent {
+        sink(RecordBatch::new_empty(schema));
+    }
+    Ok(())
+}
+
+/// Streams stored columns as row-aligned batches of up to `batch_size` rows,
+/// stepping a cursor over the table instead of reading it into memory.
+pub fn get_data(
+    cmd_opts: &CmdOptions,
+    subject: &Subject,
     target: &str,
-    column_name: &str,
-) -> anyhow::Result<String> {
-    let tbl_name = format!("{}_{}", subject, target);
+    column_names: &[&str],
+    batch_size: usize,
+    sink: &mut BatchSink,
+) -> anyhow::Result<()> {
+    let (conn, tbl_name, backend) = open_table(cmd_opts, subject, target, column_names)?;
+    let batch_size = batch_size.max(1);
 
-    let conn = rusqlite::Connection::open_with_flags(
-        &cmd_opts.storage_db,
-        rusqlite::OpenFlags::SQLITE_OPEN_READ_ONLY,
-    )?;
+    let mut fields = Vec::new();
+    for column_name in column_names {
+        let column_type = get_column_type(&conn, &tbl_name, column_name)?;
+        fields.push(Field::new(*column_name, column_type, true));
+    }
+    let schema = Arc::new(arrow_schema::Schema::new(fields));
+    if backend == Backend::Parquet {
+        return get_parquet_data(cmd_opts, &tbl_name, schema, batch_size, sink);
+    }
+    let data_key = table_key(cmd_opts, &conn, &tbl_name)?;
+    let aads = column_names
+        .iter()
+        .map(|column_name| value_aad(&tbl_name, column_name))
+        .collect::<Vec<_>>();
 
-    let sql = "SELECT json FROM policy WHERE table_name = ?".to_string();
-    let mut stmt = conn.prepare(&sql)?;
-    let entry_iter = stmt.query_map(rusqlite::params![tbl_name], |row| {
-        let val: String = row.get(0)?;
-        Ok(val)
+    // Every batch is read by a query of its own, so that storage.db is not
+    // locked while the sink waits for the client to take the batch.
+    let sql = format!(
+        "SELECT rowid, {} FROM {} WHERE rowid > ? ORDER BY rowid LIMIT ?",
+        column_names.join(", "),
+        tbl_name
+    );
+    let mut last_rowid = i64::MIN;
+    let mut sent = false;
+    loop {
+        let mut columns = vec![Vec::with_capacity(batch_size); column_names.len()];
+        let mut num_rows = 0;
+        let mut stmt = conn.prepare_cached(&sql)?;
+        let mut rows = stmt.query(rusqlite::params![last_rowid, batch_size as i64])?;
+        while let Some(row) = rows.next()? {
+            last_rowid = row.get(0)?;
+            for (idx, column) in columns.iter_mut().enumerate() {
+                let value = row.get::<_, Value>(idx + 1)?;
+                column.push(match &data_key {
+                    Some(data_key) => open_value(data_key, &aads[idx], value)?,
+                    None => value,
+                });
+            }
+            num_rows += 1;
+        }
+        drop(rows);
+        drop(stmt);
+        if num_rows == 0 && sent {
+            break;
+        }
+
+        let arrays = schema
+            .fields()
+            .iter()
+            .zip(columns)
+            .map(|(field, values)| column_to_array(field, values))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        let batch = RecordBatch::try_new(schema.clone(), arrays)?;
+        sent = true;
+        if !sink(batch) || num_rows < batch_size {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// Returns true if `target` has the shape produced by `generate_target`, so
+/// that `<subject>_<target>` table names can be split unambiguously.
+fn is_generated_target(target: &str) -> bool {
+    let re = regex::Regex::new(r"^[0-9]+_[0-9]+_[0-9a-f]{16}$").unwrap();
+    re.is_match(target)
+}
+
+/// Returns the tables whose TTL has run out but which are not purged yet.
+fn expired_tables(conn: &Connection) -> anyhow::Result<HashSet<String>> {
+    if !table_exists(conn, "storage_target")?
+        || !column_exists(conn, "storage_target", "expires_at")?
+    {
+        return Ok(HashSet::new());
+    }
+    let mut stmt = conn.prepare("SELECT table_name FROM storage_target WHERE expires_at <= ?")?;
+    let tables = stmt
+        .query_map(rusqlite::params![now_secs()], |row| row.get::<_, String>(0))?
+        .collect::<rusqlite::Result<HashSet<_>>>()?;
+    Ok(tables)
+}
+
+/// Lists the columns of every stored target owned by `subject`.
+pub fn list_columns(cmd_opts: &CmdOptions, subject: &str) -> anyhow::Result<Vec<(String, Field)>> {
+    if !Path::new(&cmd_opts.storage_db).exists() {
+        return Ok(Vec::new());
+    }
+    let conn = open_storage_db(cmd_opts, OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+    if !table_exists(&conn, "storage_schema")? {
+        return Ok(Vec::new());
+    }
+
+    let expired = expired_tables(&conn)?;
+    let prefix = format!("{}_", subject);
+    let mut stmt = conn.prepare(
+        "SELECT table_name, column_name, arrow_type FROM storage_schema ORDER BY table_name, rowid",
+    )?;
+    let rows = stmt.query_map([], |row| {
+        Ok((
+            row.get::<_, String>(0)?,
+            row.get::<_, String>(1)?,
+            row.get::<_, String>(2)?,
+        ))
     })?;
-    if let Some(policy) = entry_iter.last() {
-        let policy = policy?;
-        let mut policy_file = PolicyFile::from_json(&policy);
-        for (_, rule) in policy_file.rules.iter_mut() {
-            rule.column_name = column_name.to_owned();
+
+    let mut columns = Vec::new();
+    for row in rows {
+        let (tbl_name, column_name, arrow_type) = row?;
+        let Some(target) = tbl_name.strip_prefix(&prefix) else {
+            continue;
+        };
+        if !is_generated_target(target) || expired.contains(&tbl_name) {
+            continue;
         }
-        Ok(serde_json::to_string(&policy_file).unwrap())
+        let data_type = arrow_type_from_name(&arrow_type)?;
+        columns.push((target.to_string(), Field::new(column_name, data_type, true)));
+    }
+    Ok(columns)
+}
+
+/// Returns the table and the backend of `target` after checking that `target`
+/// belongs to `subject` and exists. A missing target is
+/// `tonic::Status::not_found`.
+fn owned_table(
+    conn: &Connection,
+    subject: &str,
+    target: &str,
+) -> anyhow::Result<(String, Backend)> {
+    let tbl_name = format!("{}_{}", subject, target);
+    let backend = if is_generated_target(target) && is_valid_sqlid(&tbl_name) {
+        target_backend(conn, &tbl_name)?
     } else {
-        Err(anyhow::anyhow!("No policy found for table: {}", tbl_name))
+        None
+    };
+    let backend =
+        backend.ok_or_else(|| tonic::Status::not_found(format!("target {} not found", target)))?;
+    Ok((tbl_name, backend))
+}
+
+/// Lists the targets stored by `subject` with their label, creation time and
+/// row count.
+pub fn list_targets(cmd_opts: &CmdOptions, subject: &str) -> anyhow::Result<Vec<StoredTarget>> {
+    let mut targets: Vec<StoredTarget> = Vec::new();
+    for (target, field) in list_columns(cmd_opts, subject)? {
+        match targets.last_mut() {
+            Some(last) if last.target == target => last.columns.push(field.name().clone()),
+            _ => targets.push(StoredTarget {
+                created_at: created_at(&target),
+                target,
+                label: None,
+                expires_at: None,
+                grants: Vec::new(),
+                num_rows: 0,
+                columns: vec![field.name().clone()],
+            }),
+        }
+    }
+    if targets.is_empty() {
+        return Ok(targets);
+    }
+
+    let conn = open_storage_db(cmd_opts, OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+    let has_metadata = table_exists(&conn, "storage_target")?
+        && column_exists(&conn, "storage_target", "expires_at")?;
+    for stored in targets.iter_mut() {
+        let tbl_name = format!("{}_{}", subject, stored.target);
+        let backend = target_backend(&conn, &tbl_name)?.unwrap_or(Backend::Sqlite);
+        stored.num_rows = count_rows(cmd_opts, &conn, &tbl_name, backend)?;
+        if has_metadata {
+            // Targets stored before the metadata table existed have no row.
+            let metadata = conn
+                .query_row(
+                    "SELECT created_at, label, expires_at FROM storage_target WHERE table_name = ?",
+                    rusqlite::params![&tbl_name],
+                    |row| {
+                        Ok((
+                            row.get::<_, u64>(0)?,
+                            row.get::<_, Option<String>>(1)?,
+                            row.get::<_, Option<u64>>(2)?,
+                        ))
+                    },
+                )
+                .optional()?;
+            if let Some((created_at, label, expires_at)) = metadata {
+                stored.created_at = created_at;
+                stored.label = label;
+                stored.expires_at = expires_at;
+            }
+        }
+        stored.grants = target_grants(&conn, &tbl_name)?;
     }
+    Ok(targets)
 }
 
-#[cfg(test)]
-mod tests {
-    use super::{create_storage, get_data, insert_data};
-    use crate::CmdOptions;
-    use arrow::array::{BinaryArray, BooleanArray, Float32Array, Int32Array, StringArray};
-    use arrow::record_batch::RecordBatch;
-    use arrow_schema::{DataType, Field, Schema};
-    use std::sync::Arc;
+/// Deletes a target of `subject` together with its schema, policy and
+/// metadata rows. `secure_delete` overwrites the freed pages so that the
+/// deleted values do not linger in the database file.
+pub fn delete_target(cmd_opts: &CmdOptions, subject: &str, target: &str) -> anyhow::Result<()> {
+    let mut conn = open_storage_db(cmd_opts, OpenFlags::SQLITE_OPEN_READ_WRITE)?;
+    conn.pragma_update(None, "secure_delete", true)?;
+    let tx = conn.transaction()?;
+    ensure_storage_metadata_tables(&tx)?;
+    let (tbl_name, backend) = owned_table(&tx, subject, target)?;
+    drop_target(cmd_opts, tx, &tbl_name, backend)
+}
 
-    fn test_cmd_opts(storage_db: &str) -> CmdOptions {
-        CmdOptions {
-            no_tls: true,
-            authorized_subject: None,
-            csv_file: None,
-            edinet_db: None,
-            parquet_path: "./parquet".to_string(),
-            storage_db: storage_db.to_string(),
-            policy_db: "./policy.db".to_string(),
-            cert: "./certs/server.crt".to_string(),
-            key: "./certs/server.key".to_string(),
-            port: 50053,
-            use_test_challenge: false,
-            allow_test_subject: true,
-            server_ld: None,
+/// Drops the table or the Parquet files of a target and its metadata rows,
+/// committing `tx`.
+fn drop_target(
+    cmd_opts: &CmdOptions,
+    tx: Transaction<'_>,
+    tbl_name: &str,
+    backend: Backend,
+) -> anyhow::Result<()> {
+    if backend == Backend::Sqlite {
+        tx.execute(&format!("DROP TABLE IF EXISTS {}", tbl_name), [])?;
+    }
+    for metadata in [
+        "storage_schema",
+        "policy",
+        "storage_target",
+        "storage_grant",
+        "storage_key",
+    ] {
+        tx.execute(
+            &format!("DELETE FROM {} WHERE table_name = ?", metadata),
+            rusqlite::params![tbl_name],
+        )?;
+    }
+    tx.commit()?;
+    if backend == Backend::Parquet {
+        let dir = parquet_dir(cmd_opts, tbl_name);
+        match std::fs::remove_dir_all(&dir) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                return Err(
+                    anyhow::Error::from(e).context(format!("failed to remove {}", dir.display()))
+                );
+            }
+            _ => {}
         }
     }
+    Ok(())
+}
 
-    #[test]
-    fn create_storage_generates_unique_targets() {
-        let temp_dir = tempfile::tempdir().unwrap();
-        let db_path = temp_dir.path().join("storage.db");
-        let cmd_opts = test_cmd_opts(db_path.to_str().unwrap());
-        let schema = Arc::new(Schema::new(vec![Field::new(
-            "value",
-            DataType::Int32,
-            true,
-        )]));
+/// Deletes every target whose TTL has run out by `now`, in seconds since the
+/// Unix epoch, and returns their tables. A target that cannot be deleted is
+/// logged and skipped.
+pub fn purge_expired(cmd_opts: &CmdOptions, now: u64) -> anyhow::Result<Vec<String>> {
+    if !Path::new(&cmd_opts.storage_db).exists() {
+        return Ok(Vec::new());
+    }
+    let mut conn = open_storage_db(cmd_opts, OpenFlags::SQLITE_OPEN_READ_WRITE)?;
+    conn.pragma_update(None, "secure_delete", true)?;
+    ensure_storage_metadata_tables(&conn)?;
+    let mut stmt = conn.prepare(
+        "SELECT table_name, backend FROM storage_target WHERE expires_at <= ? ORDER BY expires_at",
+    )?;
+    let expired = stmt
+        .query_map(rusqlite::params![now], |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+        })?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    drop(stmt);
 
-        let first = create_storage(&cmd_opts, "subject", schema.clone(), None).unwrap();
-        let second = create_storage(&cmd_opts, "subject", schema, None).unwrap();
+    let mut purged = Vec::new();
+    for (tbl_name, backend) in expired {
+        if !is_valid_sqlid(&tbl_name) {
+            continue;
+        }
+        let dropped = Backend::from_name(&backend).and_then(|backend| {
+            let tx = conn.transaction()?;
+            drop_target(cmd_opts, tx, &tbl_name, backend)
+        });
+        match dropped {
+            Ok(()) => purged.push(tbl_name),
+            Err(e) => error!("Failed to purge expired target {}: {:?}", tbl_name, e),
+        }
+    }
+    Ok(purged)
+}
 
-        assert_ne!(first, second);
+/// Rows re-written per statement when a plaintext table is encrypted.
+const ENCRYPT_CHUNK_ROWS: i64 = 1024;
+
+/// Encrypts the values of the SQLite targets stored before a master key was
+/// configured, one transaction per target, and returns how many were
+/// encrypted.
+pub fn encrypt_plaintext(cmd_opts: &CmdOptions, master_key: &MasterKey) -> anyhow::Result<usize> {
+    if !Path::new(&cmd_opts.storage_db).exists() {
+        return Ok(0);
+    }
+    let mut conn = open_storage_db(cmd_opts, OpenFlags::SQLITE_OPEN_READ_WRITE)?;
+    conn.pragma_update(None, "secure_delete", true)?;
+    ensure_storage_metadata_tables(&conn)?;
+    let mut stmt = conn.prepare(
+        "SELECT DISTINCT table_name FROM storage_schema
+        WHERE table_name NOT IN (SELECT table_name FROM storage_key)",
+    )?;
+    let tables = stmt
+        .query_map([], |row| row.get::<_, String>(0))?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    drop(stmt);
+
+    let mut encrypted = 0;
+    for tbl_name in tables {
+        if !is_valid_sqlid(&tbl_name) || target_backend(&conn, &tbl_name)? != Some(Backend::Sqlite)
+        {
+            continue;
+        }
+        let tx = conn.transaction()?;
+        let data_key = DataKey::generate();
+        insert_table_key(&tx, &tbl_name, master_key, &data_key)?;
+        let mut stmt = tx.prepare(&format!("PRAGMA table_info({})", tbl_name))?;
+        let column_names = stmt
+            .query_map([], |row| row.get::<_, String>(1))?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        drop(stmt);
+        let aads = column_names
+            .iter()
+            .map(|column_name| value_aad(&tbl_name, column_name))
+            .collect::<Vec<_>>();
+
+        let mut select = tx.prepare(&format!(
+            "SELECT rowid, {} FROM {} WHERE rowid > ? ORDER BY rowid LIMIT ?",
+            column_names.join(", "),
+            tbl_name
+        ))?;
+        let assignments = column_names
+            .iter()
+            .map(|column_name| format!("{} = ?", column_name))
+            .collect::<Vec<_>>();
+        let mut update = tx.prepare(&format!(
+            "UPDATE {} SET {} WHERE rowid = ?",
+            tbl_name,
+            assignments.join(", ")
+        ))?;
+        let mut last_rowid = i64::MIN;
+        loop {
+            let rows = select
+                .query_map(rusqlite::params![last_rowid, ENCRYPT_CHUNK_ROWS], |row| {
+                    let values = (1..=column_names.len())
+                        .map(|idx| row.get::<_, Value>(idx))
+                        .collect::<rusqlite::Result<Vec<_>>>()?;
+                    Ok((row.get::<_, i64>(0)?, values))
+                })?
+                .collect::<rusqlite::Result<Vec<_>>>()?;
+            let Some((rowid, _)) = rows.last() else {
+                break;
+            };
+            last_rowid = *rowid;
+            for (rowid, values) in rows {
+                let mut params = values
+                    .into_iter()
+                    .zip(&aads)
+                    .map(|(value, aad)| seal_value(&data_key, aad, value))
+                    .collect::<anyhow::Result<Vec<_>>>()?;
+                params.push(Value::Integer(rowid));
+                update.execute(rusqlite::params_from_iter(params.iter()))?;
+            }
+        }
+        drop(select);
+        drop(update);
+        tx.commit()?;
+        encrypted += 1;
+    }
+    Ok(encrypted)
+}
+
+/// Re-wraps the data keys wrapped by `previous` under `current` after a
+/// master key rotation, leaving the encrypted values as they are. Returns how
+/// many targets were re-wrapped.
+pub fn rewrap_keys(
+    cmd_opts: &CmdOptions,
+    previous: &MasterKey,
+    current: &MasterKey,
+) -> anyhow::Result<usize> {
+    if !Path::new(&cmd_opts.storage_db).exists() {
+        return Ok(0);
+    }
+    let mut conn = open_storage_db(cmd_opts, OpenFlags::SQLITE_OPEN_READ_WRITE)?;
+    ensure_storage_metadata_tables(&conn)?;
+    let tx = conn.transaction()?;
+    let mut stmt =
+        tx.prepare("SELECT table_name, wrapped_key FROM storage_key WHERE key_id = ?")?;
+    let keys = stmt
+        .query_map(rusqlite::params![previous.id()], |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, Vec<u8>>(1)?))
+        })?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    drop(stmt);
+    for (tbl_name, wrapped_key) in &keys {
+        let data_key = previous
+            .unwrap(wrapped_key)
+            .with_context(|| format!("failed to unwrap data key for {}", tbl_name))?;
+        tx.execute(
+            "UPDATE storage_key SET key_id = ?, wrapped_key = ? WHERE table_name = ?",
+            rusqlite::params![current.id(), current.wrap(&data_key)?, tbl_name],
+        )?;
+    }
+    tx.commit()?;
+    Ok(keys.len())
+}
+
+/// Sets or removes the label of a target of `subject`.
+pub fn label_target(
+    cmd_opts: &CmdOptions,
+    subject: &str,
+    target: &str,
+    label: Option<&str>,
+) -> anyhow::Result<()> {
+    let mut conn = open_storage_db(cmd_opts, OpenFlags::SQLITE_OPEN_READ_WRITE)?;
+    let tx = conn.transaction()?;
+    ensure_storage_metadata_tables(&tx)?;
+    let (tbl_name, _) = owned_table(&tx, subject, target)?;
+    tx.execute(
+        "INSERT INTO storage_target (table_name, created_at, label) VALUES (?, ?, ?)
+            ON CONFLICT (table_name) DO UPDATE SET label = excluded.label",
+        rusqlite::params![&tbl_name, created_at(target), label],
+    )?;
+    tx.commit()?;
+    Ok(())
+}
+
+/// The columns of a `storage_grant` row.
+fn grantee_columns(grantee: &Grantee) -> (&'static str, &str) {
+    match grantee {
+        Grantee::Subject(name) => ("subject", name),
+        Grantee::Group(group) => ("group", group),
+    }
+}
+
+/// Parses the columns of a `storage_grant` row.
+fn grantee_from_columns(grantee_type: &str, grantee: String) -> Option<Grantee> {
+    match grantee_type {
+        "subject" => Some(Grantee::Subject(grantee)),
+        "group" => Some(Grantee::Group(grantee)),
+        _ => None,
+    }
+}
+
+/// Lets `grantee` read a target of `subject` with `do_get`.
+pub fn grant_target(
+    cmd_opts: &CmdOptions,
+    subject: &str,
+    target: &str,
+    grantee: &Grantee,
+) -> anyhow::Result<()> {
+    let mut conn = open_storage_db(cmd_opts, OpenFlags::SQLITE_OPEN_READ_WRITE)?;
+    let tx = conn.transaction()?;
+    ensure_storage_metadata_tables(&tx)?;
+    let (tbl_name, _) = owned_table(&tx, subject, target)?;
+    let (grantee_type, grantee) = grantee_columns(grantee);
+    tx.execute(
+        "INSERT OR IGNORE INTO storage_grant (table_name, grantee_type, grantee) VALUES (?, ?, ?)",
+        rusqlite::params![&tbl_name, grantee_type, grantee],
+    )?;
+    tx.commit()?;
+    Ok(())
+}
+
+/// Removes a grant made with `grant_target`. A grant that does not exist is
+/// `tonic::Status::not_found`.
+pub fn revoke_target(
+    cmd_opts: &CmdOptions,
+    subject: &str,
+    target: &str,
+    grantee: &Grantee,
+) -> anyhow::Result<()> {
+    let mut conn = open_storage_db(cmd_opts, OpenFlags::SQLITE_OPEN_READ_WRITE)?;
+    let tx = conn.transaction()?;
+    ensure_storage_metadata_tables(&tx)?;
+    let (tbl_name, _) = owned_table(&tx, subject, target)?;
+    let (grantee_type, grantee) = grantee_columns(grantee);
+    let deleted = tx.execute(
+        "DELETE FROM storage_grant WHERE table_name = ? AND grantee_type = ? AND grantee = ?",
+        rusqlite::params![&tbl_name, grantee_type, grantee],
+    )?;
+    if deleted == 0 {
+        return Err(tonic::Status::not_found(format!(
+            "{} {} has no grant on target {}",
+            grantee_type, grantee, target
+        ))
+        .into());
+    }
+    tx.commit()?;
+    Ok(())
+}
+
+/// Returns the grants on a table.
+fn target_grants(conn: &Connection, tbl_name: &str) -> anyhow::Result<Vec<Grantee>> {
+    if !table_exists(conn, "storage_grant")? {
+        return Ok(Vec::new());
+    }
+    let mut stmt = conn.prepare(
+        "SELECT grantee_type, grantee FROM storage_grant WHERE table_name = ? ORDER BY rowid",
+    )?;
+    let grants = stmt
+        .query_map(rusqlite::params![tbl_name], |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+        })?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    Ok(grants
+        .into_iter()
+        .filter_map(|(grantee_type, grantee)| grantee_from_columns(&grantee_type, grantee))
+        .collect())
+}
+
+pub fn get_policy(
+    cmd_opts: &CmdOptions,
+    subject: &Subject,
+    target: &str,
+    column_name: &str,
+) -> anyhow::Result<String> {
+    let conn = open_storage_db(cmd_opts, OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+    let (tbl_name, _) = readable_table(&conn, subject, target)?;
+
+    let sql = "SELECT json FROM policy WHERE table_name = ?".to_string();
+    let mut stmt = conn.prepare(&sql)?;
+    let entry_iter = stmt.query_map(rusqlite::params![tbl_name], |row| {
+        let val: String = row.get(0)?;
+        Ok(val)
+    })?;
+    if let Some(policy) = entry_iter.last() {
+        let policy = policy?;
+        let mut policy_file = PolicyFile::from_json(&policy);
+        for (_, rule) in policy_file.rules.iter_mut() {
+            rule.column_name = column_name.to_owned();
+        }
+        Ok(serde_json::to_string(&policy_file).unwrap())
+    } else {
+        Err(anyhow::anyhow!("No policy found for table: {}", tbl_name))
+    }
+}
+
+/// Serves the targets stored by each subject with `do_put`.
+pub struct StorageSource {
+    cmd_opts: CmdOptions,
+}
+
+impl StorageSource {
+    pub fn new(cmd_opts: CmdOptions) -> Self {
+        Self { cmd_opts }
+    }
+}
+
+impl DataSource for StorageSource {
+    fn schema(
+        &self,
+        subject: &Subject,
+        target: &str,
+        columns: &[&str],
+    ) -> tonic::Result<(SchemaRef, i64)> {
+        get_schema(&self.cmd_opts, subject, target, columns)
+            .map_err(|e| storage_status("failed to get schema", e))
+    }
+
+    fn get_data(
+        &self,
+        subject: &Subject,
+        target: &str,
+        columns: &[&str],
+        batch_size: usize,
+        sink: &mut BatchSink,
+    ) -> tonic::Result<()> {
+        get_data(&self.cmd_opts, subject, target, columns, batch_size, sink)
+            .map_err(|e| storage_status("failed to get data", e))
+    }
+
+    fn get_policy(
+        &self,
+        subject: &Subject,
+        target: &str,
+        column_name: &str,
+    ) -> tonic::Result<String> {
+        get_policy(&self.cmd_opts, subject, target, column_name)
+            .map_err(|e| storage_status("failed to get policy", e))
+    }
+
+    fn list(&self, subject: &Subject, _target: &str) -> tonic::Result<Vec<ColumnInfo>> {
+        Ok(list_columns(&self.cmd_opts, &subject.name)
+            .map_err(|e| tonic::Status::internal(format!("failed to list storage: {:?}", e)))?
+            .into_iter()
+            .map(|(target, field)| ColumnInfo {
+                target,
+                column_name: field.name().clone(),
+                field: Some(field),
+            })
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        append_data, create_storage, delete_target, encrypt_plaintext, get_data, get_policy,
+        get_schema, grant_target, insert_data, label_target, list_columns, list_targets,
+        purge_expired, revoke_target, rewrap_keys, store_data,
+    };
+    use crate::auth::Subject;
+    use crate::crypto::MasterKey;
+    use crate::CmdOptions;
+    use arrow::array::ArrayRef;
+    use arrow::array::{
+        BinaryArray, BooleanArray, Date32Array, Decimal128Array, DictionaryArray, Float32Array,
+        Float64Array, Int32Array, Int64Array, StringArray, TimestampMicrosecondArray, UInt64Array,
+    };
+    use arrow::datatypes::Int32Type;
+    use arrow::record_batch::RecordBatch;
+    use arrow_schema::{DataType, Field, Schema, TimeUnit};
+    use isekai_utils::module::Grantee;
+    use std::sync::Arc;
+
+    fn test_cmd_opts(storage_db: &str) -> CmdOptions {
+        CmdOptions {
+            storage_db: storage_db.to_string(),
+            ..crate::test_cmd_opts()
+        }
+    }
+
+    fn read_batches(
+        cmd_opts: &CmdOptions,
+        subject: &str,
+        target: &str,
+        column_names: &[&str],
+        batch_size: usize,
+    ) -> anyhow::Result<Vec<RecordBatch>> {
+        let mut batches = Vec::new();
+        get_data(
+            cmd_opts,
+            &Subject::new(subject),
+            target,
+            column_names,
+            batch_size,
+            &mut |batch| {
+                batches.push(batch);
+                true
+            },
+        )?;
+        Ok(batches)
+    }
+
+    #[test]
+    fn create_storage_generates_unique_targets() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let db_path = temp_dir.path().join("storage.db");
+        let cmd_opts = test_cmd_opts(db_path.to_str().unwrap());
+        let schema = Arc::new(Schema::new(vec![Field::new(
+            "value",
+            DataType::Int32,
+            true,
+        )]));
+
+        let first = create_storage(&cmd_opts, "subject", schema.clone(), None).unwrap();
+        let second = create_storage(&cmd_opts, "subject", schema, None).unwrap();
+
+        assert_ne!(first, second);
     }
 
     #[test]
@@ -549,6 +2239,21 @@ mod tests {
             Field::new("ratio", DataType::Float32, true),
             Field::new("label", DataType::Utf8, true),
             Field::new("blob", DataType::Binary, true),
+            Field::new("total", DataType::Int64, true),
+            Field::new("id", DataType::UInt64, true),
+            Field::new("mean", DataType::Float64, true),
+            Field::new("day", DataType::Date32, true),
+            Field::new(
+                "at",
+                DataType::Timestamp(TimeUnit::Microsecond, Some("Asia/Tokyo".into())),
+                true,
+            ),
+            Field::new("price", DataType::Decimal128(10, 2), true),
+            Field::new(
+                "category",
+                DataType::Dictionary(Box::new(DataType::Int32), Box::new(DataType::Utf8)),
+                true,
+            ),
         ]));
         let batch = RecordBatch::try_new(
             schema.clone(),
@@ -558,47 +2263,831 @@ mod tests {
                 Arc::new(Float32Array::from(vec![Some(1.5), Some(2.5)])),
                 Arc::new(StringArray::from(vec![Some("a"), Some("b")])),
                 Arc::new(BinaryArray::from(vec![Some(&b"x"[..]), Some(&b"y"[..])])),
+                Arc::new(Int64Array::from(vec![Some(9007199254740993), None])),
+                Arc::new(UInt64Array::from(vec![Some(u64::MAX), Some(0)])),
+                Arc::new(Float64Array::from(vec![Some(0.1), Some(-2.5e300)])),
+                Arc::new(Date32Array::from(vec![Some(19814), None])),
+                Arc::new(
+                    TimestampMicrosecondArray::from(vec![Some(1_711_963_800_000_001), None])
+                        .with_timezone("Asia/Tokyo"),
+                ),
+                Arc::new(
+                    Decimal128Array::from(vec![Some(12345), Some(-1)])
+                        .with_precision_and_scale(10, 2)
+                        .unwrap(),
+                ),
+                Arc::new(DictionaryArray::<Int32Type>::from_iter([Some("x"), None])),
             ],
         )
         .unwrap();
 
-        let target = create_storage(&cmd_opts, "subject", schema, None).unwrap();
-        insert_data(&cmd_opts, "subject", &target, batch).unwrap();
+        let target = create_storage(&cmd_opts, "subject", schema.clone(), None).unwrap();
+        insert_data(&cmd_opts, "subject", &target, batch.clone()).unwrap();
+
+        for (idx, field) in schema.fields().iter().enumerate().skip(5) {
+            let batches = read_batches(
+                &cmd_opts,
+                "subject",
+                &target,
+                &[field.name().as_str()],
+                1024,
+            )
+            .unwrap();
+            assert_eq!(
+                batches[0].schema_ref().field(0).data_type(),
+                field.data_type()
+            );
+            assert_eq!(batches[0].column(0), batch.column(idx));
+        }
 
         assert_eq!(
-            get_data(&cmd_opts, "subject", &target, "flag").unwrap()[0]
+            read_batches(&cmd_opts, "subject", &target, &["flag"], 1024).unwrap()[0]
                 .schema_ref()
                 .field(0)
                 .data_type(),
             &DataType::Boolean
         );
         assert_eq!(
-            get_data(&cmd_opts, "subject", &target, "count").unwrap()[0]
+            read_batches(&cmd_opts, "subject", &target, &["count"], 1024).unwrap()[0]
                 .schema_ref()
                 .field(0)
                 .data_type(),
             &DataType::Int32
         );
         assert_eq!(
-            get_data(&cmd_opts, "subject", &target, "ratio").unwrap()[0]
+            read_batches(&cmd_opts, "subject", &target, &["ratio"], 1024).unwrap()[0]
                 .schema_ref()
                 .field(0)
                 .data_type(),
             &DataType::Float32
         );
         assert_eq!(
-            get_data(&cmd_opts, "subject", &target, "label").unwrap()[0]
+            read_batches(&cmd_opts, "subject", &target, &["label"], 1024).unwrap()[0]
                 .schema_ref()
                 .field(0)
                 .data_type(),
             &DataType::Utf8
         );
         assert_eq!(
-            get_data(&cmd_opts, "subject", &target, "blob").unwrap()[0]
+            read_batches(&cmd_opts, "subject", &target, &["blob"], 1024).unwrap()[0]
                 .schema_ref()
                 .field(0)
                 .data_type(),
             &DataType::Binary
         );
     }
+
+    #[test]
+    fn store_data_rolls_back_when_a_limit_is_exceeded() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let db_path = temp_dir.path().join("storage.db");
+        let mut cmd_opts = test_cmd_opts(db_path.to_str().unwrap());
+        cmd_opts.max_put_rows = Some(4);
+        let schema = Arc::new(Schema::new(vec![Field::new(
+            "count",
+            DataType::Int32,
+            true,
+        )]));
+        let batch = RecordBatch::try_new(
+            schema.clone(),
+            vec![Arc::new(Int32Array::from(vec![Some(1), Some(2), Some(3)]))],
+        )
+        .unwrap();
+
+        let target = store_data(
+            &cmd_opts,
+            "subject",
+            schema.clone(),
+            None,
+            None,
+            vec![Ok(batch.clone())],
+        )
+        .unwrap();
+        let err = store_data(
+            &cmd_opts,
+            "subject",
+            schema,
+            None,
+            None,
+            vec![Ok(batch.clone()), Ok(batch)],
+        )
+        .unwrap_err();
+
+        let status = err.downcast_ref::<tonic::Status>().unwrap();
+        assert_eq!(status.code(), tonic::Code::ResourceExhausted);
+        let columns = list_columns(&cmd_opts, "subject").unwrap();
+        assert_eq!(columns.len(), 1);
+        assert_eq!(columns[0].0, target);
+    }
+
+    #[test]
+    fn get_schema_returns_column_type_and_row_count() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let db_path = temp_dir.path().join("storage.db");
+        let cmd_opts = test_cmd_opts(db_path.to_str().unwrap());
+        let schema = Arc::new(Schema::new(vec![Field::new(
+            "count",
+            DataType::Int32,
+            true,
+        )]));
+        let batch = RecordBatch::try_new(
+            schema.clone(),
+            vec![Arc::new(Int32Array::from(vec![Some(1), None, Some(3)]))],
+        )
+        .unwrap();
+
+        let target = create_storage(&cmd_opts, "subject", schema, None).unwrap();
+        insert_data(&cmd_opts, "subject", &target, batch).unwrap();
+
+        let (schema, num_rows) =
+            get_schema(&cmd_opts, &Subject::new("subject"), &target, &["count"]).unwrap();
+
+        assert_eq!(schema.field(0).name(), "count");
+        assert_eq!(schema.field(0).data_type(), &DataType::Int32);
+        assert_eq!(num_rows, 3);
+    }
+
+    #[test]
+    fn get_data_returns_row_aligned_columns() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let db_path = temp_dir.path().join("storage.db");
+        let cmd_opts = test_cmd_opts(db_path.to_str().unwrap());
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("count", DataType::Int32, true),
+            Field::new("label", DataType::Utf8, true),
+        ]));
+        let batch = RecordBatch::try_new(
+            schema.clone(),
+            vec![
+                Arc::new(Int32Array::from(vec![Some(1), None, Some(3)])),
+                Arc::new(StringArray::from(vec![Some("a"), Some("b"), None])),
+            ],
+        )
+        .unwrap();
+
+        let target = create_storage(&cmd_opts, "subject", schema, None).unwrap();
+        insert_data(&cmd_opts, "subject", &target, batch.clone()).unwrap();
+
+        let batches =
+            read_batches(&cmd_opts, "subject", &target, &["label", "count"], 1024).unwrap();
+
+        assert_eq!(batches.len(), 1);
+        assert_eq!(batches[0].schema_ref().field(0).name(), "label");
+        assert_eq!(batches[0].column(0), batch.column(1));
+        assert_eq!(batches[0].column(1), batch.column(0));
+
+        let batches = read_batches(&cmd_opts, "subject", &target, &["count"], 2).unwrap();
+        assert_eq!(
+            batches.iter().map(|b| b.num_rows()).collect::<Vec<_>>(),
+            vec![2, 1]
+        );
+    }
+
+    #[test]
+    fn list_columns_returns_only_subject_targets() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let db_path = temp_dir.path().join("storage.db");
+        let cmd_opts = test_cmd_opts(db_path.to_str().unwrap());
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("count", DataType::Int32, true),
+            Field::new("label", DataType::Utf8, true),
+        ]));
+
+        let target = create_storage(&cmd_opts, "subject", schema.clone(), None).unwrap();
+        create_storage(&cmd_opts, "subject_other", schema, None).unwrap();
+
+        let columns = list_columns(&cmd_opts, "subject").unwrap();
+
+        assert_eq!(columns.len(), 2);
+        assert!(columns.iter().all(|(t, _)| t == &target));
+        assert_eq!(columns[0].1.name(), "count");
+        assert_eq!(columns[0].1.data_type(), &DataType::Int32);
+        assert_eq!(columns[1].1.name(), "label");
+        assert_eq!(columns[1].1.data_type(), &DataType::Utf8);
+    }
+
+    #[test]
+    fn append_data_checks_owner_and_schema() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let db_path = temp_dir.path().join("storage.db");
+        let cmd_opts = test_cmd_opts(db_path.to_str().unwrap());
+        let schema = Arc::new(Schema::new(vec![Field::new(
+            "count",
+            DataType::Int32,
+            true,
+        )]));
+        let batch = RecordBatch::try_new(
+            schema.clone(),
+            vec![Arc::new(Int32Array::from(vec![Some(1), Some(2)]))],
+        )
+        .unwrap();
+        let target = store_data(
+            &cmd_opts,
+            "subject",
+            schema.clone(),
+            None,
+            None,
+            vec![Ok(batch.clone())],
+        )
+        .unwrap();
+
+        append_data(
+            &cmd_opts,
+            "subject",
+            &target,
+            schema.clone(),
+            vec![Ok(batch.clone())],
+        )
+        .unwrap();
+        let (_, num_rows) =
+            get_schema(&cmd_opts, &Subject::new("subject"), &target, &["count"]).unwrap();
+        assert_eq!(num_rows, 4);
+
+        let err = append_data(&cmd_opts, "other", &target, schema, vec![Ok(batch)]).unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<tonic::Status>().unwrap().code(),
+            tonic::Code::NotFound
+        );
+
+        let other_schema = Arc::new(Schema::new(vec![Field::new("count", DataType::Utf8, true)]));
+        let err = append_data(&cmd_opts, "subject", &target, other_schema, vec![]).unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<tonic::Status>().unwrap().code(),
+            tonic::Code::InvalidArgument
+        );
+    }
+
+    #[test]
+    fn uploads_do_not_lock_the_storage_database_while_received() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let db_path = temp_dir.path().join("storage.db");
+        let mut cmd_opts = test_cmd_opts(db_path.to_str().unwrap());
+        cmd_opts.parquet_path = temp_dir
+            .path()
+            .join("parquet")
+            .to_str()
+            .unwrap()
+            .to_string();
+        let schema = Arc::new(Schema::new(vec![Field::new("n", DataType::Int32, true)]));
+        let batch =
+            RecordBatch::try_new(schema.clone(), vec![Arc::new(Int32Array::from(vec![1, 2]))])
+                .unwrap();
+
+        for parquet_storage in [false, true] {
+            cmd_opts.parquet_storage = parquet_storage;
+            let target = store_data(
+                &cmd_opts,
+                "subject",
+                schema.clone(),
+                None,
+                None,
+                vec![Ok(batch.clone())],
+            )
+            .unwrap();
+
+            // Another upload is stored while this one is still being received.
+            let mut received = 0;
+            let batches = std::iter::from_fn(|| {
+                received += 1;
+                match received {
+                    1 => Some(Ok(batch.clone())),
+                    2 => {
+                        store_data(
+                            &cmd_opts,
+                            "other",
+                            schema.clone(),
+                            None,
+                            None,
+                            vec![Ok(batch.clone())],
+                        )
+                        .unwrap();
+                        Some(Ok(batch.clone()))
+                    }
+                    _ => None,
+                }
+            });
+            append_data(&cmd_opts, "subject", &target, schema.clone(), batches).unwrap();
+
+            let (_, num_rows) =
+                get_schema(&cmd_opts, &Subject::new("subject"), &target, &["n"]).unwrap();
+            assert_eq!(num_rows, 6);
+        }
+        assert_eq!(list_targets(&cmd_opts, "other").unwrap().len(), 2);
+    }
+
+    #[test]
+    fn targets_can_be_listed_labelled_and_deleted() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let db_path = temp_dir.path().join("storage.db");
+        let cmd_opts = test_cmd_opts(db_path.to_str().unwrap());
+        let schema = Arc::new(Schema::new(vec![Field::new(
+            "count",
+            DataType::Int32,
+            true,
+        )]));
+        let batch = RecordBatch::try_new(
+            schema.clone(),
+            vec![Arc::new(Int32Array::from(vec![Some(1), Some(2)]))],
+        )
+        .unwrap();
+        let target = create_storage(&cmd_opts, "subject", schema, Some("{}".to_string())).unwrap();
+        insert_data(&cmd_opts, "subject", &target, batch).unwrap();
+
+        label_target(&cmd_opts, "subject", &target, Some("daily")).unwrap();
+        let targets = list_targets(&cmd_opts, "subject").unwrap();
+        assert_eq!(targets.len(), 1);
+        assert_eq!(targets[0].target, target);
+        assert_eq!(targets[0].label.as_deref(), Some("daily"));
+        assert_eq!(targets[0].num_rows, 2);
+        assert_eq!(targets[0].columns, vec!["count".to_string()]);
+        assert!(targets[0].created_at > 0);
+
+        let err = delete_target(&cmd_opts, "other", &target).unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<tonic::Status>().unwrap().code(),
+            tonic::Code::NotFound
+        );
+        delete_target(&cmd_opts, "subject", &target).unwrap();
+        assert!(list_targets(&cmd_opts, "subject").unwrap().is_empty());
+
+        let conn = rusqlite::Connection::open(&db_path).unwrap();
+        for metadata in ["storage_schema", "policy", "storage_target", "storage_key"] {
+            let sql = format!("SELECT COUNT(*) FROM {}", metadata);
+            let count: i64 = conn.query_row(&sql, [], |row| row.get(0)).unwrap();
+            assert_eq!(count, 0);
+        }
+    }
+
+    #[test]
+    fn parquet_targets_are_read_with_projection() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let db_path = temp_dir.path().join("storage.db");
+        let mut cmd_opts = test_cmd_opts(db_path.to_str().unwrap());
+        cmd_opts.parquet_path = temp_dir
+            .path()
+            .join("parquet")
+            .to_str()
+            .unwrap()
+            .to_string();
+        cmd_opts.parquet_storage = true;
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("id", DataType::Int64, true),
+            Field::new("name", DataType::Utf8, true),
+        ]));
+        let batch = |ids: Vec<i64>, names: Vec<&str>| {
+            Ok(RecordBatch::try_new(
+                schema.clone(),
+                vec![
+                    Arc::new(Int64Array::from(ids)),
+                    Arc::new(StringArray::from(names)),
+                ],
+            )
+            .unwrap())
+        };
+        let target = store_data(
+            &cmd_opts,
+            "subject",
+            schema.clone(),
+            Some("{}".to_string()),
+            None,
+            [batch(vec![1, 2], vec!["a", "b"])],
+        )
+        .unwrap();
+        append_data(
+            &cmd_opts,
+            "subject",
+            &target,
+            schema.clone(),
+            [batch(vec![3], vec!["c"])],
+        )
+        .unwrap();
+
+        let dir = temp_dir
+            .path()
+            .join("parquet")
+            .join("storage")
+            .join(format!("subject_{}", target));
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 2);
+        let conn = rusqlite::Connection::open(&db_path).unwrap();
+        let sql = format!(
+            "SELECT COUNT(*) FROM sqlite_master WHERE name = 'subject_{}'",
+            target
+        );
+        let count: i64 = conn.query_row(&sql, [], |row| row.get(0)).unwrap();
+        assert_eq!(count, 0);
+
+        let (_, num_rows) =
+            get_schema(&cmd_opts, &Subject::new("subject"), &target, &["name"]).unwrap();
+        assert_eq!(num_rows, 3);
+        let batches = read_batches(&cmd_opts, "subject", &target, &["name", "id"], 1024).unwrap();
+        let batch = arrow::compute::concat_batches(&batches[0].schema(), &batches).unwrap();
+        assert_eq!(batch.schema().field(0).name(), "name");
+        let names: ArrayRef = Arc::new(StringArray::from(vec!["a", "b", "c"]));
+        let ids: ArrayRef = Arc::new(Int64Array::from(vec![1, 2, 3]));
+        assert_eq!(batch.column(0), &names);
+        assert_eq!(batch.column(1), &ids);
+
+        delete_target(&cmd_opts, "subject", &target).unwrap();
+        assert!(!dir.exists());
+        assert!(list_targets(&cmd_opts, "subject").unwrap().is_empty());
+    }
+
+    #[test]
+    fn expired_targets_are_hidden_and_purged() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let db_path = temp_dir.path().join("storage.db");
+        let mut cmd_opts = test_cmd_opts(db_path.to_str().unwrap());
+        cmd_opts.max_ttl_secs = Some(60);
+        let schema = Arc::new(Schema::new(vec![Field::new("n", DataType::Int32, true)]));
+        let batch = RecordBatch::try_new(schema.clone(), vec![Arc::new(Int32Array::from(vec![1]))])
+            .unwrap();
+        let capped = store_data(
+            &cmd_opts,
+            "subject",
+            schema.clone(),
+            None,
+            Some(3600),
+            vec![Ok(batch.clone())],
+        )
+        .unwrap();
+        let short = store_data(
+            &cmd_opts,
+            "subject",
+            schema.clone(),
+            None,
+            Some(10),
+            vec![Ok(batch)],
+        )
+        .unwrap();
+
+        let targets = list_targets(&cmd_opts, "subject").unwrap();
+        let expires_at = |target: &str| {
+            let stored = targets.iter().find(|t| t.target == target).unwrap();
+            stored.expires_at.unwrap() - stored.created_at
+        };
+        assert_eq!(expires_at(&capped), 60);
+        assert_eq!(expires_at(&short), 10);
+
+        let created_at = targets[0].created_at.min(targets[1].created_at);
+        assert!(purge_expired(&cmd_opts, created_at).unwrap().is_empty());
+        // A target that cannot be dropped does not stop the others from
+        // being purged.
+        let conn = rusqlite::Connection::open(&db_path).unwrap();
+        conn.execute(
+            "INSERT INTO storage_target (table_name, created_at, backend, expires_at)
+             VALUES ('other_broken', ?1, 'unknown', ?1)",
+            rusqlite::params![created_at],
+        )
+        .unwrap();
+        let purged = purge_expired(&cmd_opts, created_at + 3600).unwrap();
+        assert_eq!(purged.len(), 2);
+        assert!(!purged.contains(&"other_broken".to_string()));
+        assert!(list_targets(&cmd_opts, "subject").unwrap().is_empty());
+        let err = get_schema(&cmd_opts, &Subject::new("subject"), &short, &["n"]).unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<tonic::Status>().unwrap().code(),
+            tonic::Code::NotFound
+        );
+    }
+
+    #[test]
+    fn quotas_limit_targets_and_bytes_per_subject() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let db_path = temp_dir.path().join("storage.db");
+        let mut cmd_opts = test_cmd_opts(db_path.to_str().unwrap());
+        let schema = Arc::new(Schema::new(vec![Field::new("n", DataType::Int32, true)]));
+        let batch =
+            RecordBatch::try_new(schema.clone(), vec![Arc::new(Int32Array::from(vec![1, 2]))])
+                .unwrap();
+        let store = |cmd_opts: &CmdOptions, subject: &str| {
+            store_data(
+                cmd_opts,
+                subject,
+                schema.clone(),
+                None,
+                None,
+                vec![Ok(batch.clone())],
+            )
+        };
+        let assert_exhausted = |err: anyhow::Error| {
+            assert_eq!(
+                err.downcast_ref::<tonic::Status>().unwrap().code(),
+                tonic::Code::ResourceExhausted
+            );
+        };
+
+        cmd_opts.max_subject_targets = Some(1);
+        store(&cmd_opts, "subject").unwrap();
+        assert_exhausted(store(&cmd_opts, "subject").unwrap_err());
+        store(&cmd_opts, "other").unwrap();
+
+        cmd_opts.max_subject_targets = None;
+        cmd_opts.max_subject_bytes = Some(16);
+        let target = store(&cmd_opts, "subject").unwrap();
+        assert_exhausted(store(&cmd_opts, "subject").unwrap_err());
+        let err = append_data(
+            &cmd_opts,
+            "subject",
+            &target,
+            schema.clone(),
+            vec![Ok(batch.clone())],
+        )
+        .unwrap_err();
+        assert_exhausted(err);
+        assert_eq!(list_targets(&cmd_opts, "subject").unwrap().len(), 2);
+    }
+
+    #[test]
+    fn granted_targets_are_readable_by_target_id() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let db_path = temp_dir.path().join("storage.db");
+        let cmd_opts = test_cmd_opts(db_path.to_str().unwrap());
+        let schema = Arc::new(Schema::new(vec![Field::new("n", DataType::Int32, true)]));
+        let batch = RecordBatch::try_new(schema.clone(), vec![Arc::new(Int32Array::from(vec![7]))])
+            .unwrap();
+        let target = store_data(
+            &cmd_opts,
+            "owner",
+            schema,
+            Some("{}".to_string()),
+            None,
+            vec![Ok(batch)],
+        )
+        .unwrap();
+        let colleague = Subject::new("colleague");
+        let analyst = Subject {
+            name: "analyst".to_string(),
+            groups: vec!["research".to_string()],
+            ..Default::default()
+        };
+        let assert_not_found = |subject: &Subject| {
+            let err = get_schema(&cmd_opts, subject, &target, &["n"]).unwrap_err();
+            assert_eq!(
+                err.downcast_ref::<tonic::Status>().unwrap().code(),
+                tonic::Code::NotFound
+            );
+        };
+        assert_not_found(&colleague);
+        assert_not_found(&analyst);
+
+        let err = grant_target(
+            &cmd_opts,
+            "colleague",
+            &target,
+            &Grantee::Subject("colleague".to_string()),
+        )
+        .unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<tonic::Status>().unwrap().code(),
+            tonic::Code::NotFound
+        );
+        grant_target(
+            &cmd_opts,
+            "owner",
+            &target,
+            &Grantee::Subject("colleague".to_string()),
+        )
+        .unwrap();
+        grant_target(
+            &cmd_opts,
+            "owner",
+            &target,
+            &Grantee::Group("research".to_string()),
+        )
+        .unwrap();
+        assert_eq!(
+            list_targets(&cmd_opts, "owner").unwrap()[0].grants,
+            vec![
+                Grantee::Subject("colleague".to_string()),
+                Grantee::Group("research".to_string())
+            ]
+        );
+
+        let batches = read_batches(&cmd_opts, "colleague", &target, &["n"], 1024).unwrap();
+        assert_eq!(batches[0].num_rows(), 1);
+        let (_, num_rows) = get_schema(&cmd_opts, &analyst, &target, &["n"]).unwrap();
+        assert_eq!(num_rows, 1);
+        assert!(get_policy(&cmd_opts, &analyst, &target, "n").is_ok());
+        assert!(list_targets(&cmd_opts, "colleague").unwrap().is_empty());
+
+        revoke_target(
+            &cmd_opts,
+            "owner",
+            &target,
+            &Grantee::Group("research".to_string()),
+        )
+        .unwrap();
+        assert_not_found(&analyst);
+        let err = revoke_target(
+            &cmd_opts,
+            "owner",
+            &target,
+            &Grantee::Group("research".to_string()),
+        )
+        .unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<tonic::Status>().unwrap().code(),
+            tonic::Code::NotFound
+        );
+    }
+
+    #[test]
+    fn reads_do_not_lock_the_storage_database_between_batches() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let db_path = temp_dir.path().join("storage.db");
+        let cmd_opts = test_cmd_opts(db_path.to_str().unwrap());
+        let schema = Arc::new(Schema::new(vec![Field::new("n", DataType::Int32, true)]));
+        let batch = RecordBatch::try_new(
+            schema.clone(),
+            vec![Arc::new(Int32Array::from(vec![1, 2, 3]))],
+        )
+        .unwrap();
+        let target = store_data(
+            &cmd_opts,
+            "subject",
+            schema.clone(),
+            None,
+            None,
+            vec![Ok(batch.clone())],
+        )
+        .unwrap();
+
+        // Another upload is stored while the client has yet to take a batch.
+        let mut num_rows = Vec::new();
+        get_data(
+            &cmd_opts,
+            &Subject::new("subject"),
+            &target,
+            &["n"],
+            2,
+            &mut |batch| {
+                if num_rows.is_empty() {
+                    store_data(
+                        &cmd_opts,
+                        "other",
+                        schema.clone(),
+                        None,
+                        None,
+                        vec![Ok(batch.clone())],
+                    )
+                    .unwrap();
+                }
+                num_rows.push(batch.num_rows());
+                true
+            },
+        )
+        .unwrap();
+        assert_eq!(num_rows, vec![2, 1]);
+        assert_eq!(list_targets(&cmd_opts, "other").unwrap().len(), 1);
+    }
+
+    #[test]
+    fn targets_are_not_resolved_by_subject_prefix() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let db_path = temp_dir.path().join("storage.db");
+        let cmd_opts = test_cmd_opts(db_path.to_str().unwrap());
+        let schema = Arc::new(Schema::new(vec![Field::new("n", DataType::Int32, true)]));
+        let batch = RecordBatch::try_new(schema.clone(), vec![Arc::new(Int32Array::from(vec![7]))])
+            .unwrap();
+        let target =
+            store_data(&cmd_opts, "alice_bob", schema, None, None, vec![Ok(batch)]).unwrap();
+        assert_eq!(
+            read_batches(&cmd_opts, "alice_bob", &target, &["n"], 1024).unwrap()[0].num_rows(),
+            1
+        );
+
+        let err =
+            read_batches(&cmd_opts, "alice", &format!("bob_{}", target), &["n"], 1024).unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<tonic::Status>().unwrap().code(),
+            tonic::Code::NotFound
+        );
+    }
+
+    #[test]
+    fn stored_values_are_encrypted_at_rest() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let db_path = temp_dir.path().join("storage.db");
+        let mut cmd_opts = test_cmd_opts(db_path.to_str().unwrap());
+        cmd_opts.parquet_path = temp_dir
+            .path()
+            .join("parquet")
+            .to_str()
+            .unwrap()
+            .to_string();
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("id", DataType::Int64, true),
+            Field::new("name", DataType::Utf8, true),
+        ]));
+        let batch = |ids: Vec<Option<i64>>, names: Vec<&str>| {
+            Ok(RecordBatch::try_new(
+                schema.clone(),
+                vec![
+                    Arc::new(Int64Array::from(ids)),
+                    Arc::new(StringArray::from(names)),
+                ],
+            )
+            .unwrap())
+        };
+        let target = store_data(
+            &cmd_opts,
+            "subject",
+            schema.clone(),
+            None,
+            None,
+            [batch(vec![Some(1), None], vec!["a", "b"])],
+        )
+        .unwrap();
+
+        let key_path = temp_dir.path().join("master.key");
+        std::fs::write(&key_path, [7u8; 32]).unwrap();
+        let master_key = MasterKey::load(key_path.to_str().unwrap()).unwrap();
+        cmd_opts.master_key_file = Some(key_path.to_str().unwrap().to_string());
+        assert_eq!(encrypt_plaintext(&cmd_opts, &master_key).unwrap(), 1);
+        assert_eq!(encrypt_plaintext(&cmd_opts, &master_key).unwrap(), 0);
+        append_data(
+            &cmd_opts,
+            "subject",
+            &target,
+            schema.clone(),
+            [batch(vec![Some(3)], vec!["c"])],
+        )
+        .unwrap();
+
+        let conn = rusqlite::Connection::open(&db_path).unwrap();
+        let sql = format!(
+            "SELECT typeof(id), typeof(name) FROM subject_{} ORDER BY rowid",
+            target
+        );
+        let mut stmt = conn.prepare(&sql).unwrap();
+        let types = stmt
+            .query_map([], |row| {
+                Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+            })
+            .unwrap()
+            .collect::<rusqlite::Result<Vec<_>>>()
+            .unwrap();
+        let blob = || "blob".to_string();
+        assert_eq!(
+            types,
+            vec![
+                (blob(), blob()),
+                ("null".to_string(), blob()),
+                (blob(), blob())
+            ]
+        );
+
+        let ids: ArrayRef = Arc::new(Int64Array::from(vec![Some(1), None, Some(3)]));
+        let names: ArrayRef = Arc::new(StringArray::from(vec!["a", "b", "c"]));
+        let batches = read_batches(&cmd_opts, "subject", &target, &["id", "name"], 1024).unwrap();
+        assert_eq!(batches[0].column(0), &ids);
+        assert_eq!(batches[0].column(1), &names);
+
+        cmd_opts.parquet_storage = true;
+        let parquet_target = store_data(
+            &cmd_opts,
+            "subject",
+            schema.clone(),
+            None,
+            None,
+            [batch(vec![Some(4)], vec!["d"])],
+        )
+        .unwrap();
+        let dir = temp_dir
+            .path()
+            .join("parquet")
+            .join("storage")
+            .join(format!("subject_{}", parquet_target));
+        for entry in std::fs::read_dir(&dir).unwrap() {
+            let data = std::fs::read(entry.unwrap().path()).unwrap();
+            assert!(data.starts_with(b"ISEKAIE1"));
+        }
+
+        let rotated_path = temp_dir.path().join("rotated.key");
+        std::fs::write(&rotated_path, [8u8; 32]).unwrap();
+        let rotated_key = MasterKey::load(rotated_path.to_str().unwrap()).unwrap();
+        let mut rotated_opts = cmd_opts.clone();
+        rotated_opts.master_key_file = Some(rotated_path.to_str().unwrap().to_string());
+        assert_eq!(
+            rewrap_keys(&rotated_opts, &master_key, &rotated_key).unwrap(),
+            1
+        );
+        let batches =
+            read_batches(&rotated_opts, "subject", &target, &["id", "name"], 1024).unwrap();
+        assert_eq!(batches[0].column(0), &ids);
+        assert!(read_batches(&cmd_opts, "subject", &target, &["id"], 1024).is_err());
+
+        cmd_opts.master_key_file = None;
+        assert!(read_batches(&cmd_opts, "subject", &target, &["id"], 1024).is_err());
+        assert!(read_batches(&cmd_opts, "subject", &parquet_target, &["id"], 1024).is_err());
+    }
+
+    #[test]
+    fn list_columns_handles_missing_database() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let db_path = temp_dir.path().join("missing.db");
+        let cmd_opts = test_cmd_opts(db_path.to_str().unwrap());
+
+        assert!(list_columns(&cmd_opts, "subject").unwrap().is_empty());
+    }
 }
@@ -126,6 +126,7 @@ async fn handle_load_data(request: Request<IncomingBody>, responder: Responder)
     let ticket = GetTicket {
         target: target.clone(),
         column_name: col_name.clone(),
+        columns: Vec::new(),
     }
     .to_json();
 